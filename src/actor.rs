@@ -53,15 +53,40 @@ pub struct Address {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Version(usize);
 
+impl Address {
+    /// Refers to the actor with the given index, as written in textual references to actors.
+    pub fn from_index(index: usize) -> Address {
+        Address { index }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
 impl Version {
     pub const ZERO: Version = Version(0);
 
+    pub fn new(number: usize) -> Version {
+        Version(number)
+    }
+
+    pub fn number(self) -> usize {
+        self.0
+    }
+
     #[must_use]
     pub fn increment(self) -> Version {
         Version(self.0 + 1)
     }
 }
 
+impl Default for System {
+    fn default() -> System {
+        System::new()
+    }
+}
+
 impl System {
     pub fn new() -> System {
         System {
@@ -73,42 +98,73 @@ impl System {
 
     pub fn run(&mut self) {
         while let Some(queued) = self.queue.pop_front() {
-            let Some(actor) = self.actors.get_mut(&queued.target) else {
-                // Prevent a back-and-forth unreachable message loop from occuring in the scenario
-                // where there are two nodes that both get retired while there is a message queued
-                // to go from one to the other.
-                if !matches!(&queued.message, Message::Unreachable { .. }) {
-                    // NOTE push_front to make this be the very next message sent
-                    self.queue.push_front(QueuedMessage {
-                        sender: queued.target,
-                        target: queued.sender,
-                        message: Message::Unreachable {
-                            message: Box::new(queued.message),
-                        },
-                    });
-                }
-
-                continue;
-            };
-
-            let mut actor = actor
-                .take()
-                .expect("invariant broken: actor was checked out during run step");
-
-            actor.handle(
-                queued.message,
-                Context {
-                    system: RefCell::new(self),
-                    me: queued.target.clone(),
-                },
-            );
-
-            if let Some(entry) = self.actors.get_mut(&queued.target) {
-                if entry.is_none() {
-                    *entry = Some(actor);
-                } else {
-                    // This means the actor already transformed into another via `shift`
-                }
+            self.deliver(queued);
+        }
+    }
+
+    /// Like [`System::run`], but returns every message delivered in the order they were handled,
+    /// each with its sender and target.
+    #[cfg(test)]
+    pub fn run_traced(&mut self) -> Vec<(Address, Address, Message)> {
+        let mut trace = Vec::new();
+        while let Some(queued) = self.queue.pop_front() {
+            trace.push((
+                queued.sender.clone(),
+                queued.target.clone(),
+                queued.message.clone(),
+            ));
+            self.deliver(queued);
+        }
+
+        trace
+    }
+
+    /// Queues `message` as if it had been sent by `sender`, for driving actors from outside.
+    #[cfg(test)]
+    pub fn send(&mut self, sender: &Address, target: &Address, message: Message) {
+        self.queue.push_back(QueuedMessage {
+            sender: sender.clone(),
+            target: target.clone(),
+            message,
+        });
+    }
+
+    fn deliver(&mut self, queued: QueuedMessage) {
+        let Some(actor) = self.actors.get_mut(&queued.target) else {
+            // Prevent a back-and-forth unreachable message loop from occuring in the scenario
+            // where there are two nodes that both get retired while there is a message queued
+            // to go from one to the other.
+            if !matches!(&queued.message, Message::Unreachable { .. }) {
+                // NOTE push_front to make this be the very next message sent
+                self.queue.push_front(QueuedMessage {
+                    sender: queued.target,
+                    target: queued.sender,
+                    message: Message::Unreachable {
+                        message: Box::new(queued.message),
+                    },
+                });
+            }
+
+            return;
+        };
+
+        let mut actor = actor
+            .take()
+            .expect("invariant broken: actor was checked out during run step");
+
+        actor.handle(
+            queued.message,
+            Context {
+                system: RefCell::new(self),
+                me: queued.target.clone(),
+            },
+        );
+
+        if let Some(entry) = self.actors.get_mut(&queued.target) {
+            if entry.is_none() {
+                *entry = Some(actor);
+            } else {
+                // This means the actor already transformed into another via `shift`
             }
         }
     }
@@ -140,7 +196,6 @@ impl<'a> Context<'a> {
 
     /// Queues `message` to be sent to and handled by `target`.
     pub fn send(&self, target: &Address, message: Message) {
-        let message = message.into();
         self.system.borrow_mut().queue.push_back(QueuedMessage {
             sender: self.me.clone(),
             target: target.clone(),
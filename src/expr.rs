@@ -1,18 +1,26 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
 use crate::node::VersionedReactiveAddress;
 
 pub mod eval;
+pub mod parse;
+mod print;
+pub mod typecheck;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Name {
     pub text: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Upgrade {
     Seq(Box<Upgrade>, Box<Upgrade>),
     Var(Ident, Expr<Ident>),
     Def(Ident, Expr<Ident>),
     Del(VersionedReactiveAddress),
+    /// Turns an existing reactive into a variable whose value is computed from its current value,
+    /// which the expression reads through the reactive's own address.
+    Migrate(VersionedReactiveAddress, Expr<Ident>),
     Nil,
     // NOTE: control flow for upgrades is not planned
 }
@@ -29,38 +37,313 @@ impl From<VersionedReactiveAddress> for Ident {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr<Ident = VersionedReactiveAddress> {
     // TODO: more exprs
     Tuple(Box<[Expr<Ident>]>),
     Read(Ident),
     Value(Value),
+    Unary(UnaryOp, Box<Expr<Ident>>),
+    Binary(BinaryOp, Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// Evaluates to the second expression if the condition holds and the third otherwise. Only the
+    /// taken branch is evaluated, so the reads of the other branch need not be available.
+    If(Box<Expr<Ident>>, Box<Expr<Ident>>, Box<Expr<Ident>>),
+    Record(Box<[(Name, Expr<Ident>)]>),
+    Field(Box<Expr<Ident>>, Name),
+    Variant(Name, Box<Expr<Ident>>),
+    /// Evaluates the arm whose tag matches the variant the scrutinee evaluates to, with the
+    /// variant's payload bound to the arm's binding. Like with [`Expr::If`], only the taken arm is
+    /// evaluated.
+    Match(Box<Expr<Ident>>, Box<[MatchArm<Ident>]>),
+    /// Evaluates the second expression with the value of the first bound to the name.
+    Let(Name, Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// A function of one parameter. Lambdas are not values, so they cannot be held by reactives,
+    /// but they can be bound with [`Expr::Let`] and passed to other lambdas.
+    Lambda(Name, Box<Expr<Ident>>),
+    Apply(Box<Expr<Ident>>, Box<Expr<Ident>>),
+    List(Box<[Expr<Ident>]>),
+    /// Applies the function to every item of the list.
+    Map(Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// Keeps the items of the list for which the predicate holds.
+    Filter(Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// Combines the items of the list into the accumulator, starting from the second expression,
+    /// by applying the function to the accumulator and then to each item in turn.
+    Fold(Box<Expr<Ident>>, Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// The number of items in a list or map, or characters in a string.
+    Len(Box<Expr<Ident>>),
+    /// The item of a list at an integer position, or the value of a map at a key.
+    Index(Box<Expr<Ident>>, Box<Expr<Ident>>),
+    /// Joins lists, or strings, end to end.
+    Concat(Box<[Expr<Ident>]>),
+    /// The item of a tuple at the given position.
+    Proj(Box<Expr<Ident>>, usize),
+    /// A local variable bound by an enclosing expression, as opposed to a reactive. Locals are
+    /// scoped lexically, with inner bindings shadowing outer ones.
+    Local(Name),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm<Ident = VersionedReactiveAddress> {
+    pub tag: Name,
+    pub binding: Name,
+    pub body: Expr<Ident>,
+}
+
+impl<I> Expr<I> {
+    /// Converts the expression into one reading the same reactives through the identifiers
+    /// `resolve` gives for them, such as their addresses once an upgrade has placed them.
+    pub fn map_reads<J>(self, resolve: &mut dyn FnMut(I) -> J) -> Expr<J> {
+        fn one<I, J>(expr: Expr<I>, resolve: &mut dyn FnMut(I) -> J) -> Box<Expr<J>> {
+            Box::new(expr.map_reads(resolve))
+        }
+
+        fn all<I, J>(exprs: Box<[Expr<I>]>, resolve: &mut dyn FnMut(I) -> J) -> Box<[Expr<J>]> {
+            exprs
+                .into_vec()
+                .into_iter()
+                .map(|expr| expr.map_reads(resolve))
+                .collect()
+        }
+
+        let r = resolve;
+        match self {
+            Expr::Tuple(items) => Expr::Tuple(all(items, r)),
+            Expr::Read(ident) => Expr::Read(r(ident)),
+            Expr::Value(value) => Expr::Value(value),
+            Expr::Unary(op, operand) => Expr::Unary(op, one(*operand, r)),
+            Expr::Binary(op, lhs, rhs) => Expr::Binary(op, one(*lhs, r), one(*rhs, r)),
+            Expr::If(cond, then, otherwise) => {
+                Expr::If(one(*cond, r), one(*then, r), one(*otherwise, r))
+            }
+            Expr::Record(fields) => Expr::Record(
+                fields
+                    .into_vec()
+                    .into_iter()
+                    .map(|(name, field)| (name, field.map_reads(r)))
+                    .collect(),
+            ),
+            Expr::Field(record, name) => Expr::Field(one(*record, r), name),
+            Expr::Variant(tag, payload) => Expr::Variant(tag, one(*payload, r)),
+            Expr::Match(scrutinee, arms) => Expr::Match(
+                one(*scrutinee, r),
+                arms.into_vec()
+                    .into_iter()
+                    .map(|arm| MatchArm {
+                        tag: arm.tag,
+                        binding: arm.binding,
+                        body: arm.body.map_reads(r),
+                    })
+                    .collect(),
+            ),
+            Expr::Let(name, bound, body) => Expr::Let(name, one(*bound, r), one(*body, r)),
+            Expr::Lambda(param, body) => Expr::Lambda(param, one(*body, r)),
+            Expr::Apply(function, argument) => Expr::Apply(one(*function, r), one(*argument, r)),
+            Expr::List(items) => Expr::List(all(items, r)),
+            Expr::Map(list, function) => Expr::Map(one(*list, r), one(*function, r)),
+            Expr::Filter(list, predicate) => Expr::Filter(one(*list, r), one(*predicate, r)),
+            Expr::Fold(list, init, function) => {
+                Expr::Fold(one(*list, r), one(*init, r), one(*function, r))
+            }
+            Expr::Len(collection) => Expr::Len(one(*collection, r)),
+            Expr::Index(collection, key) => Expr::Index(one(*collection, r), one(*key, r)),
+            Expr::Concat(items) => Expr::Concat(all(items, r)),
+            Expr::Proj(tuple, index) => Expr::Proj(one(*tuple, r), index),
+            Expr::Local(name) => Expr::Local(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    // arithmetic on integers, checked for overflow, and on floats
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+
+    // equality on values of any type
+    Eq,
+    Ne,
+
+    // ordering on values of the same type
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    // logic on booleans
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Action {
     Seq(Box<Action>, Box<Action>),
     Write(VersionedReactiveAddress, Expr<VersionedReactiveAddress>),
+    /// Performs the first action if the condition holds and the second otherwise. Like with
+    /// [`Expr::If`], only the taken branch is evaluated, so only its reactives are locked.
+    If(Expr<VersionedReactiveAddress>, Box<Action>, Box<Action>),
+    /// Performs the action for as long as the condition holds. The loop fails once it has
+    /// performed the action as many times as the bound allows and the condition still holds, so
+    /// that every transaction ends.
+    While(Expr<VersionedReactiveAddress>, Box<Action>, usize),
+    /// Performs the action with the value of the expression bound to the name.
+    Let(Name, Expr<VersionedReactiveAddress>, Box<Action>),
     Nil,
-    // TODO: control flow
 }
 
+/// A value held by a reactive.
+///
+/// Values are totally ordered, so that they can serve as map keys. Values of the same variant
+/// are compared by their contents, with floats compared by [`f64::total_cmp`]; values of different
+/// variants are ordered by variant. The comparison operators of expressions compare floats as
+/// IEEE 754 numbers instead, wherever they are nested, as [`Value::ieee_cmp`] does.
 #[derive(Debug, Clone)]
 pub enum Value {
     Tuple(Box<[Value]>),
     Integer(isize),
+    Boolean(bool),
+    String(String),
+    Float(f64),
+    Unit,
+    /// A list whose items are all of the same type.
+    List(Box<[Value]>),
+    /// A map whose keys are all of one type and whose values are all of one type.
+    Map(BTreeMap<Value, Value>),
+    Record(BTreeMap<Name, Value>),
+    Variant(Name, Box<Value>),
 }
 
 impl Value {
-    pub fn compute_type(&self) -> Type {
-        match self {
+    /// Computes the type of this value, or `None` if a list or map in it holds items of
+    /// different types.
+    pub fn compute_type(&self) -> Option<Type> {
+        Some(match self {
             Value::Tuple(items) => Type::Tuple(
                 items
                     .iter()
                     .map(|item| item.compute_type())
-                    .collect::<Box<[_]>>(),
+                    .collect::<Option<Box<[_]>>>()?,
             ),
             Value::Integer(_) => Type::Integer,
+            Value::Boolean(_) => Type::Boolean,
+            Value::String(_) => Type::String,
+            Value::Float(_) => Type::Float,
+            Value::Unit => Type::Unit,
+            Value::List(items) => Type::List(Box::new(Type::unify_all(
+                items.iter().map(Value::compute_type),
+            )?)),
+            Value::Map(entries) => Type::Map(
+                Box::new(Type::unify_all(entries.keys().map(Value::compute_type))?),
+                Box::new(Type::unify_all(entries.values().map(Value::compute_type))?),
+            ),
+            Value::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, value)| Some((name.clone(), value.compute_type()?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Variant(tag, payload) => {
+                Type::Variant(BTreeMap::from([(tag.clone(), payload.compute_type()?)]))
+            }
+        })
+    }
+
+    /// Compares values the way the comparison operators of expressions do: like the [`Ord`]
+    /// impl, except that floats, wherever they are nested, are compared as IEEE 754 numbers, so
+    /// that `0.0` equals `-0.0` and NaN is unordered with everything. Values are compared
+    /// lexicographically, so they are unordered if the first floats that tell them apart are.
+    /// Map keys are still compared by the total order, as that is how they are looked up.
+    pub fn ieee_cmp(&self, other: &Value) -> Option<Ordering> {
+        fn lexicographic(
+            pairs: impl IntoIterator<Item = Option<Ordering>>,
+            a: usize,
+            b: usize,
+        ) -> Option<Ordering> {
+            for ordering in pairs {
+                if ordering != Some(Ordering::Equal) {
+                    return ordering;
+                }
+            }
+
+            Some(a.cmp(&b))
+        }
+
+        fn entry<K: Ord>((k, v): (&K, &Value), (l, w): (&K, &Value)) -> Option<Ordering> {
+            match k.cmp(l) {
+                Ordering::Equal => v.ieee_cmp(w),
+                ordering => Some(ordering),
+            }
+        }
+
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Tuple(a), Value::Tuple(b)) | (Value::List(a), Value::List(b)) => lexicographic(
+                a.iter().zip(b.iter()).map(|(a, b)| a.ieee_cmp(b)),
+                a.len(),
+                b.len(),
+            ),
+            (Value::Map(a), Value::Map(b)) => {
+                lexicographic(a.iter().zip(b).map(|(a, b)| entry(a, b)), a.len(), b.len())
+            }
+            (Value::Record(a), Value::Record(b)) => {
+                lexicographic(a.iter().zip(b).map(|(a, b)| entry(a, b)), a.len(), b.len())
+            }
+            (Value::Variant(a, x), Value::Variant(b, y)) => entry((a, x), (b, y)),
+            (a, b) => Some(a.cmp(b)),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Unit => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) => 2,
+            Value::Float(_) => 3,
+            Value::String(_) => 4,
+            Value::Tuple(_) => 5,
+            Value::List(_) => 6,
+            Value::Map(_) => 7,
+            Value::Record(_) => 8,
+            Value::Variant(..) => 9,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Tuple(a), Value::Tuple(b)) | (Value::List(a), Value::List(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Unit, Value::Unit) => Ordering::Equal,
+            (Value::Map(a), Value::Map(b)) => a.cmp(b),
+            (Value::Record(a), Value::Record(b)) => a.cmp(b),
+            (Value::Variant(a, x), Value::Variant(b, y)) => (a, x).cmp(&(b, y)),
+            (a, b) => a.rank().cmp(&b.rank()),
         }
     }
 }
@@ -69,4 +352,80 @@ impl Value {
 pub enum Type {
     Tuple(Box<[Type]>),
     Integer,
+    Boolean,
+    String,
+    Float,
+    Unit,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Record(BTreeMap<Name, Type>),
+    /// The type of values of any of the given tags, each with a payload of the given type.
+    Variant(BTreeMap<Name, Type>),
+    /// The type of no values, such as the items of an empty list. It unifies with every type.
+    Never,
+}
+
+impl Type {
+    /// Finds the most specific type that both `self` and `other` conform to, if any.
+    pub fn unify(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Never, other) | (other, Type::Never) => Some(other.clone()),
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => a
+                .iter()
+                .zip(b.iter())
+                .map(|(a, b)| a.unify(b))
+                .collect::<Option<Box<[_]>>>()
+                .map(Type::Tuple),
+            (Type::List(a), Type::List(b)) => Some(Type::List(Box::new(a.unify(b)?))),
+            (Type::Map(ak, av), Type::Map(bk, bv)) => {
+                Some(Type::Map(Box::new(ak.unify(bk)?), Box::new(av.unify(bv)?)))
+            }
+            (Type::Record(a), Type::Record(b)) if a.keys().eq(b.keys()) => a
+                .iter()
+                .zip(b.values())
+                .map(|((name, a), b)| Some((name.clone(), a.unify(b)?)))
+                .collect::<Option<_>>()
+                .map(Type::Record),
+            // A variant type is the union of the tags of both sides.
+            (Type::Variant(a), Type::Variant(b)) => {
+                let mut tags = a.clone();
+                for (tag, b) in b {
+                    let payload = match tags.get(tag) {
+                        Some(a) => a.unify(b)?,
+                        None => b.clone(),
+                    };
+                    tags.insert(tag.clone(), payload);
+                }
+                Some(Type::Variant(tags))
+            }
+            (a, b) if a == b => Some(a.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether every value of this type is also of type `other`, i.e. whether unifying the two
+    /// leaves `other` as it is. A variant type conforms to those with more tags, but not the
+    /// other way around.
+    pub fn conforms_to(&self, other: &Type) -> bool {
+        other.unify(self).as_ref() == Some(other)
+    }
+
+    /// Finds the tags of this variant type that are not among `tags`, i.e. the arms a match on a
+    /// value of this type is missing. Any other type has no tags to cover.
+    pub fn uncovered_tags<'a>(&self, tags: impl IntoIterator<Item = &'a Name>) -> Vec<Name> {
+        let Type::Variant(variants) = self else {
+            return Vec::new();
+        };
+
+        let covered = tags.into_iter().collect::<Vec<_>>();
+        variants
+            .keys()
+            .filter(|tag| !covered.contains(tag))
+            .cloned()
+            .collect()
+    }
+
+    fn unify_all(mut types: impl Iterator<Item = Option<Type>>) -> Option<Type> {
+        types.try_fold(Type::Never, |a, b| a.unify(&b?))
+    }
 }
@@ -1,42 +1,161 @@
-use std::mem;
+use std::{cmp::Ordering, collections::BTreeMap, fmt, mem};
 
 use crate::{actor::Address, expr::Value, node::VersionedReactiveAddress};
 
-use super::{Action, Expr, Ident, Upgrade};
+use super::{Action, BinaryOp, Expr, Ident, Name, UnaryOp, Upgrade};
+
+/// Why an evaluation failed. Unlike a read whose value is not available yet, which only holds
+/// evaluation back until it is, a failure is final.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError<Ident = VersionedReactiveAddress> {
+    /// An operation was applied to values it is not defined on.
+    TypeMismatch {
+        operation: String,
+        operands: Vec<Value>,
+    },
+    /// Integer arithmetic produced a result that does not fit in an integer.
+    Overflow {
+        operation: String,
+        operands: Vec<Value>,
+    },
+    DivisionByZero,
+    MissingField(Name),
+    DuplicateField(Name),
+    /// A tuple position, list position or map key that is not present.
+    MissingItem(Value),
+    NoMatchingArm(Name),
+    UnboundLocal(Name),
+    /// The expression reads a reactive that does not exist.
+    MissingReactive(Ident),
+    /// A loop's condition still held after as many iterations as its bound allows.
+    IterationLimit,
+    /// Evaluation could not be completed even though it had to be, such as that of a variable
+    /// declared by an upgrade from a definition the upgrade declares, or it applied lambdas more
+    /// deeply or did more work than allowed.
+    Stalled,
+}
+
+impl<Ident> EvalError<Ident> {
+    /// Converts the error into one referring to reactives through other identifiers.
+    pub fn map_reactive<J>(self, resolve: impl FnOnce(Ident) -> J) -> EvalError<J> {
+        match self {
+            EvalError::TypeMismatch {
+                operation,
+                operands,
+            } => EvalError::TypeMismatch {
+                operation,
+                operands,
+            },
+            EvalError::Overflow {
+                operation,
+                operands,
+            } => EvalError::Overflow {
+                operation,
+                operands,
+            },
+            EvalError::DivisionByZero => EvalError::DivisionByZero,
+            EvalError::MissingField(name) => EvalError::MissingField(name),
+            EvalError::DuplicateField(name) => EvalError::DuplicateField(name),
+            EvalError::MissingItem(key) => EvalError::MissingItem(key),
+            EvalError::NoMatchingArm(tag) => EvalError::NoMatchingArm(tag),
+            EvalError::UnboundLocal(name) => EvalError::UnboundLocal(name),
+            EvalError::MissingReactive(ident) => EvalError::MissingReactive(resolve(ident)),
+            EvalError::IterationLimit => EvalError::IterationLimit,
+            EvalError::Stalled => EvalError::Stalled,
+        }
+    }
+}
+
+impl<Ident: fmt::Debug> fmt::Display for EvalError<Ident> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::TypeMismatch {
+                operation,
+                operands,
+            } => write!(f, "cannot apply {operation} to {operands:?}"),
+            EvalError::Overflow {
+                operation,
+                operands,
+            } => write!(f, "overflow applying {operation} to {operands:?}"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::MissingField(name) => write!(f, "record has no field {}", name.text),
+            EvalError::DuplicateField(name) => {
+                write!(f, "record has duplicate field {}", name.text)
+            }
+            EvalError::MissingItem(key) => write!(f, "no item at {key:?}"),
+            EvalError::NoMatchingArm(tag) => write!(f, "match has no arm for #{}", tag.text),
+            EvalError::UnboundLocal(name) => write!(f, "unbound local {}", name.text),
+            EvalError::MissingReactive(ident) => write!(f, "reactive {ident:?} does not exist"),
+            EvalError::IterationLimit => {
+                write!(f, "loop did not finish within its iteration limit")
+            }
+            EvalError::Stalled => write!(f, "evaluation stalled"),
+        }
+    }
+}
+
+fn mismatch<Ident>(operation: impl fmt::Display, operands: &[&Value]) -> EvalError<Ident> {
+    EvalError::TypeMismatch {
+        operation: operation.to_string(),
+        operands: operands.iter().map(|&value| value.clone()).collect(),
+    }
+}
+
+/// The number of nodes in a value, with each character of a string counted as one.
+fn value_size(value: &Value) -> usize {
+    let sum = |values: &[Value]| values.iter().map(value_size).sum::<usize>();
+
+    1 + match value {
+        Value::Tuple(items) | Value::List(items) => sum(items),
+        Value::String(string) => string.len(),
+        Value::Integer(_) | Value::Boolean(_) | Value::Float(_) | Value::Unit => 0,
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| value_size(key) + value_size(value))
+            .sum(),
+        Value::Record(fields) => fields.values().map(value_size).sum(),
+        Value::Variant(_, payload) => value_size(payload),
+    }
+}
 
 pub trait UpgradeEvalContext: ExprEvalContext<Ident> {
     fn var(&mut self, ident: Ident, value: Value);
     fn def(&mut self, ident: Ident, expr: Expr<Ident>);
     fn del(&mut self, address: VersionedReactiveAddress);
+    fn migrate(&mut self, address: VersionedReactiveAddress, migration: Expr<Ident>);
 }
 
 pub trait ActionEvalContext: ExprEvalContext<VersionedReactiveAddress> {
     /// Attempts to write to the node referenced by `address` with the given `value`.
     ///
     /// Returns true if the write was performed.
-    fn write(&mut self, address: &VersionedReactiveAddress, value: &Value) -> bool;
+    fn write(
+        &mut self,
+        address: &VersionedReactiveAddress,
+        value: &Value,
+    ) -> Result<bool, EvalError>;
 }
 
 pub trait ExprEvalContext<Ident = Address> {
     /// Reads the value held by the node referenced by `ident`.
     ///
     /// If the value is not yet ready, this function will return `None` instead of a value.
-    fn read(&mut self, ident: &Ident) -> Option<&Value>;
+    fn read(&mut self, ident: &Ident) -> Result<Option<&Value>, EvalError<Ident>>;
 }
 
 impl Upgrade {
-    pub fn eval(&mut self, ctx: &mut impl UpgradeEvalContext) {
+    pub fn eval(&mut self, ctx: &mut impl UpgradeEvalContext) -> Result<(), EvalError<Ident>> {
         match self {
             Upgrade::Seq(a, b) => {
-                a.eval(ctx);
+                a.eval(ctx)?;
                 if let Upgrade::Nil = &**a {
-                    b.eval(ctx);
+                    b.eval(ctx)?;
 
                     *self = mem::replace(b, Upgrade::Nil);
                 }
             }
             Upgrade::Var(_, expr) => {
-                expr.eval(ctx);
+                expr.eval(ctx)?;
 
                 if let Expr::Value(_) = expr {
                     let Upgrade::Var(ident, Expr::Value(value)) = mem::replace(self, Upgrade::Nil)
@@ -45,8 +164,6 @@ impl Upgrade {
                     };
 
                     ctx.var(ident, value);
-                } else {
-                    panic!("var expr could not be fully evaluated")
                 }
             }
             Upgrade::Def(..) => {
@@ -63,8 +180,17 @@ impl Upgrade {
 
                 ctx.del(address);
             }
+            Upgrade::Migrate(..) => {
+                let Upgrade::Migrate(address, migration) = mem::replace(self, Upgrade::Nil) else {
+                    unreachable!()
+                };
+
+                ctx.migrate(address, migration);
+            }
             Upgrade::Nil => {}
         }
+
+        Ok(())
     }
 
     pub fn visit_upgrades(&self, mut visitor: impl FnMut(&VersionedReactiveAddress)) {
@@ -76,6 +202,7 @@ impl Upgrade {
             Upgrade::Var(Ident::Existing(address), _) => visitor(address),
             Upgrade::Def(Ident::Existing(address), _) => visitor(address),
             Upgrade::Del(address) => visitor(address),
+            Upgrade::Migrate(address, _) => visitor(address),
             _ => {}
         }
     }
@@ -93,6 +220,10 @@ impl Upgrade {
                 expr.visit_reads(&mut |ident, _definite| visitor(ident, false));
             }
             Upgrade::Del(..) => {}
+            Upgrade::Migrate(.., migration) => {
+                // The migration is evaluated by the node holding the reactive when it commits.
+                migration.visit_reads(&mut |ident, _definite| visitor(ident, false));
+            }
             Upgrade::Nil => {}
         }
     }
@@ -102,66 +233,270 @@ impl Action {
     /// Evaluates this action.
     ///
     /// When `self` is [`Action::Nil`], no further evaulation will be done.
-    pub fn eval<C>(&mut self, ctx: &mut C)
+    pub fn eval<C>(&mut self, ctx: &mut C) -> Result<(), EvalError>
     where
         C: ActionEvalContext,
     {
-        match self {
-            Action::Seq(a, b) => {
-                a.eval(ctx);
-                if let Action::Nil = &**a {
-                    b.eval(ctx);
+        // Actions are evaluated a step at a time in a loop rather than recursively, so that
+        // neither long sequences nor loops with many iterations run out of stack.
+        loop {
+            let progressed = match self {
+                Action::Seq(a, b) => match &mut **a {
+                    Action::Seq(..) => {
+                        // Reassociate so that the first statement to evaluate is always at the
+                        // front of the sequence.
+                        let Action::Seq(a, c) = mem::replace(self, Action::Nil) else {
+                            unreachable!()
+                        };
+                        let Action::Seq(a, b) = *a else {
+                            unreachable!()
+                        };
+                        *self = Action::Seq(a, Box::new(Action::Seq(b, c)));
+                        true
+                    }
+                    Action::Nil => {
+                        *self = mem::replace(&mut **b, Action::Nil);
+                        true
+                    }
+                    first => first.step(ctx)?,
+                },
+                _ => self.step(ctx)?,
+            };
 
-                    *self = mem::replace(b, Action::Nil);
-                }
+            if !progressed {
+                return Ok(());
             }
+        }
+    }
+
+    /// Evaluates an action other than a sequence as far as it can be without evaluating any
+    /// action it contains, replacing it with what is left to evaluate.
+    ///
+    /// Returns true if the action changed, so that evaluation may carry on.
+    fn step<C>(&mut self, ctx: &mut C) -> Result<bool, EvalError>
+    where
+        C: ActionEvalContext,
+    {
+        match self {
+            Action::Seq(..) => unreachable!("sequences are evaluated by `Action::eval`"),
             Action::Write(ident, expr) => {
-                expr.eval(ctx);
+                expr.eval(ctx)?;
 
                 if let Expr::Value(value) = expr {
-                    if ctx.write(ident, value) {
+                    if ctx.write(ident, value)? {
+                        *self = Action::Nil;
+                        return Ok(true);
+                    }
+                }
+            }
+            Action::If(cond, then, otherwise) => {
+                cond.eval(ctx)?;
+
+                let taken = match cond {
+                    Expr::Value(Value::Boolean(true)) => then,
+                    Expr::Value(Value::Boolean(false)) => otherwise,
+                    Expr::Value(value) => return Err(mismatch("if", &[value])),
+                    _ => return Ok(false),
+                };
+
+                *self = mem::replace(&mut **taken, Action::Nil);
+                return Ok(true);
+            }
+            Action::While(cond, _, 0) => {
+                // The loop has run out of iterations, so it may only end here.
+                cond.eval(ctx)?;
+
+                match cond {
+                    Expr::Value(Value::Boolean(true)) => return Err(EvalError::IterationLimit),
+                    Expr::Value(Value::Boolean(false)) => {
                         *self = Action::Nil;
+                        return Ok(true);
                     }
+                    Expr::Value(value) => return Err(mismatch("while", &[value])),
+                    _ => {}
+                }
+            }
+            Action::While(..) => {
+                let Action::While(cond, body, bound) = mem::replace(self, Action::Nil) else {
+                    unreachable!()
+                };
+
+                // Unrolling one iteration keeps the condition and body as written for the next,
+                // as evaluating them in place consumes them.
+                let rest = Action::While(cond.clone(), body.clone(), bound - 1);
+                *self = Action::If(
+                    cond,
+                    Box::new(Action::Seq(body, Box::new(rest))),
+                    Box::new(Action::Nil),
+                );
+                return Ok(true);
+            }
+            Action::Let(binding, bound, body) => {
+                bound.eval(ctx)?;
+
+                if bound.is_evaluated() {
+                    let mut body = mem::replace(&mut **body, Action::Nil);
+                    body.substitute(binding, bound, &mut Fuel::new())?;
+                    *self = body;
+                    return Ok(true);
+                }
+            }
+            Action::Nil => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Replaces the free occurrences of the local `name` in the expressions of the action with
+    /// `replacement`, like [`Expr::substitute`].
+    fn substitute(
+        &mut self,
+        name: &Name,
+        replacement: &Expr,
+        fuel: &mut Fuel,
+    ) -> Result<(), EvalError> {
+        match self {
+            Action::Seq(a, b) => {
+                a.substitute(name, replacement, fuel)?;
+                b.substitute(name, replacement, fuel)?;
+            }
+            Action::Write(_, expr) => expr.substitute(name, replacement, fuel)?,
+            Action::If(cond, then, otherwise) => {
+                cond.substitute(name, replacement, fuel)?;
+                then.substitute(name, replacement, fuel)?;
+                otherwise.substitute(name, replacement, fuel)?;
+            }
+            Action::While(cond, body, _) => {
+                cond.substitute(name, replacement, fuel)?;
+                body.substitute(name, replacement, fuel)?;
+            }
+            Action::Let(binding, bound, body) => {
+                bound.substitute(name, replacement, fuel)?;
+                if binding != name {
+                    body.substitute(name, replacement, fuel)?;
                 }
             }
             Action::Nil => {}
         }
+
+        Ok(())
     }
 
     /// Traverses the expression, calling the callback with each VersionedAddress the Action might write to.
-    pub fn visit_writes(&self, mut visitor: impl FnMut(&VersionedReactiveAddress, bool)) {
+    ///
+    /// Writes are reported as definite when they are performed whichever way the action's
+    /// conditions turn out.
+    pub fn visit_writes(&self, visitor: &mut impl FnMut(&VersionedReactiveAddress, bool)) {
+        self.visit_writes_with(true, visitor);
+    }
+
+    /// Like [`Action::visit_writes`], but reports every write as indefinite unless `definite`
+    /// holds.
+    fn visit_writes_with(
+        &self,
+        definite: bool,
+        visitor: &mut impl FnMut(&VersionedReactiveAddress, bool),
+    ) {
         match self {
             Action::Seq(a, b) => {
-                a.visit_writes(&mut visitor);
-                b.visit_writes(&mut visitor);
+                a.visit_writes_with(definite, visitor);
+                b.visit_writes_with(definite, visitor);
             }
             Action::Write(ident, _) => {
-                visitor(ident, true);
+                visitor(ident, definite);
+            }
+            Action::If(_, then, otherwise) => {
+                then.visit_writes_with(false, visitor);
+                otherwise.visit_writes_with(false, visitor);
             }
+            // The body of a loop might not be performed at all.
+            Action::While(_, body, _) => body.visit_writes_with(false, visitor),
+            Action::Let(_, _, body) => body.visit_writes_with(definite, visitor),
             Action::Nil => {}
         }
     }
 
     /// Traverses the action, calling the callback with each VersionedAddress the Action might read from.
     pub fn visit_reads(&self, visitor: &mut impl FnMut(&VersionedReactiveAddress, bool)) {
+        self.visit_reads_with(true, visitor);
+    }
+
+    /// Like [`Action::visit_reads`], but reports every read as indefinite unless `definite` holds.
+    fn visit_reads_with(
+        &self,
+        definite: bool,
+        visitor: &mut impl FnMut(&VersionedReactiveAddress, bool),
+    ) {
         match self {
             Action::Seq(a, b) => {
-                a.visit_reads(visitor);
-                b.visit_reads(visitor);
+                a.visit_reads_with(definite, visitor);
+                b.visit_reads_with(definite, visitor);
             }
-            Action::Write(_, expr) => {
-                expr.visit_reads(visitor);
+            Action::Write(_, expr) => expr.visit_reads_with(definite, visitor),
+            Action::If(cond, then, otherwise) => {
+                cond.visit_reads_with(definite, visitor);
+                then.visit_reads_with(false, visitor);
+                otherwise.visit_reads_with(false, visitor);
+            }
+            Action::While(cond, body, _) => {
+                cond.visit_reads_with(definite, visitor);
+                body.visit_reads_with(false, visitor);
+            }
+            Action::Let(_, bound, body) => {
+                bound.visit_reads_with(definite, visitor);
+                body.visit_reads_with(definite, visitor);
             }
             Action::Nil => {}
         }
     }
 }
 
-impl<Ident> Expr<Ident> {
+/// How much work evaluating an expression may do in total, counted in the applications made and
+/// in the nodes visited and copied while substituting locals.
+const WORK_LIMIT: usize = 1 << 22;
+
+/// How deeply lambda applications may nest while evaluating an expression.
+const DEPTH_LIMIT: usize = 64;
+
+/// Bounds the work done while evaluating an expression, so that evaluating a lambda that keeps
+/// applying itself, as in `(\x -> x(x))(\x -> x(x))`, or one whose applications build ever
+/// larger terms, as in `t(t)(t)(t)` where `t = \f -> \x -> f(f(x))`, fails rather than going on
+/// forever or running out of memory.
+struct Fuel {
+    /// The work that can still be done.
+    work: usize,
+    /// The number of applications being evaluated.
+    depth: usize,
+}
+
+impl Fuel {
+    fn new() -> Fuel {
+        Fuel {
+            work: WORK_LIMIT,
+            depth: 0,
+        }
+    }
+
+    /// Uses up `amount` of the work that can still be done, failing if there is not as much left.
+    fn burn<Ident>(&mut self, amount: usize) -> Result<(), EvalError<Ident>> {
+        self.work = self.work.checked_sub(amount).ok_or(EvalError::Stalled)?;
+        Ok(())
+    }
+}
+
+impl<Ident: Clone> Expr<Ident> {
     /// Evaluates this expression.
     ///
-    /// When `self` is an [`Expr::Value`], no further evaulation will be done.
-    pub fn eval<C>(&mut self, ctx: &mut C)
+    /// When `self` is an [`Expr::Value`], no further evaulation will be done. Otherwise, evaluation
+    /// is waiting on reads and can be resumed by evaluating `self` again, unless it failed.
+    pub fn eval<C>(&mut self, ctx: &mut C) -> Result<(), EvalError<Ident>>
+    where
+        C: ExprEvalContext<Ident>,
+    {
+        self.eval_with(ctx, &mut Fuel::new())
+    }
+
+    fn eval_with<C>(&mut self, ctx: &mut C, fuel: &mut Fuel) -> Result<(), EvalError<Ident>>
     where
         C: ExprEvalContext<Ident>,
     {
@@ -169,7 +504,7 @@ impl<Ident> Expr<Ident> {
             Expr::Tuple(items) => {
                 let mut all_evaled = true;
                 for item in items.iter_mut() {
-                    item.eval(ctx);
+                    item.eval_with(ctx, fuel)?;
                     if !matches!(item, Expr::Value(_)) {
                         all_evaled = false;
                     }
@@ -189,24 +524,965 @@ impl<Ident> Expr<Ident> {
                     *self = Expr::Value(Value::Tuple(values.into_boxed_slice()))
                 }
             }
-            Expr::Read(ident) => match ctx.read(ident) {
-                Some(value) => *self = Expr::Value(value.clone()),
-                None => (),
-            },
+            Expr::Read(ident) => {
+                if let Some(value) = ctx.read(ident)? {
+                    *self = Expr::Value(value.clone());
+                }
+            }
             Expr::Value(_) => (),
+            Expr::Unary(op, operand) => {
+                operand.eval_with(ctx, fuel)?;
+
+                if let Expr::Value(value) = &**operand {
+                    *self = Expr::Value(op.apply(value)?);
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                lhs.eval_with(ctx, fuel)?;
+                rhs.eval_with(ctx, fuel)?;
+
+                if let (Expr::Value(lhs), Expr::Value(rhs)) = (&**lhs, &**rhs) {
+                    *self = Expr::Value(op.apply(lhs, rhs)?);
+                }
+            }
+            Expr::If(cond, then, otherwise) => {
+                cond.eval_with(ctx, fuel)?;
+
+                let taken = match &**cond {
+                    Expr::Value(Value::Boolean(true)) => then,
+                    Expr::Value(Value::Boolean(false)) => otherwise,
+                    Expr::Value(value) => return Err(mismatch("if", &[value])),
+                    _ => return Ok(()),
+                };
+
+                let mut taken = mem::replace(&mut **taken, Expr::Value(Value::Unit));
+                taken.eval_with(ctx, fuel)?;
+                *self = taken;
+            }
+            Expr::Record(fields) => {
+                let mut all_evaled = true;
+                for (_, field) in fields.iter_mut() {
+                    field.eval_with(ctx, fuel)?;
+                    if !matches!(field, Expr::Value(_)) {
+                        all_evaled = false;
+                    }
+                }
+
+                if all_evaled {
+                    let fields = mem::replace(fields, Box::from([]));
+                    let mut values = BTreeMap::new();
+                    for (name, field) in fields {
+                        let Expr::Value(value) = field else {
+                            unreachable!()
+                        };
+
+                        if values.contains_key(&name) {
+                            return Err(EvalError::DuplicateField(name));
+                        }
+
+                        values.insert(name, value);
+                    }
+
+                    *self = Expr::Value(Value::Record(values))
+                }
+            }
+            Expr::Field(record, name) => {
+                record.eval_with(ctx, fuel)?;
+
+                if let Expr::Value(value) = &mut **record {
+                    let Value::Record(fields) = value else {
+                        return Err(mismatch(format_args!("field {}", name.text), &[value]));
+                    };
+
+                    let Some(field) = fields.remove(name) else {
+                        return Err(EvalError::MissingField(name.clone()));
+                    };
+
+                    *self = Expr::Value(field);
+                }
+            }
+            Expr::Variant(tag, payload) => {
+                payload.eval_with(ctx, fuel)?;
+
+                if let Expr::Value(value) = &mut **payload {
+                    let payload = mem::replace(value, Value::Unit);
+                    *self = Expr::Value(Value::Variant(tag.clone(), Box::new(payload)));
+                }
+            }
+            Expr::Match(scrutinee, arms) => {
+                scrutinee.eval_with(ctx, fuel)?;
+
+                let Expr::Value(value) = &**scrutinee else {
+                    return Ok(());
+                };
+
+                let Value::Variant(tag, payload) = value else {
+                    return Err(mismatch("match", &[value]));
+                };
+
+                let Some(arm) = arms.iter_mut().find(|arm| &arm.tag == tag) else {
+                    return Err(EvalError::NoMatchingArm(tag.clone()));
+                };
+
+                let mut taken = mem::replace(&mut arm.body, Expr::Value(Value::Unit));
+                taken.substitute(&arm.binding, &Expr::Value((**payload).clone()), fuel)?;
+                taken.eval_with(ctx, fuel)?;
+                *self = taken;
+            }
+            Expr::Let(binding, bound, body) => {
+                bound.eval_with(ctx, fuel)?;
+
+                if bound.is_evaluated() {
+                    let mut body = mem::replace(&mut **body, Expr::Value(Value::Unit));
+                    body.substitute(binding, bound, fuel)?;
+                    body.eval_with(ctx, fuel)?;
+                    *self = body;
+                }
+            }
+            // A lambda is only evaluated once applied.
+            Expr::Lambda(..) => (),
+            Expr::Apply(function, argument) => {
+                function.eval_with(ctx, fuel)?;
+                argument.eval_with(ctx, fuel)?;
+
+                if function.is_evaluated() && argument.is_evaluated() {
+                    let (param, body) = match &mut **function {
+                        Expr::Lambda(param, body) => (param, body),
+                        Expr::Value(value) => return Err(mismatch("application", &[value])),
+                        _ => unreachable!(),
+                    };
+
+                    if fuel.depth == DEPTH_LIMIT {
+                        return Err(EvalError::Stalled);
+                    }
+
+                    fuel.burn(1)?;
+                    let mut body = mem::replace(&mut **body, Expr::Value(Value::Unit));
+                    body.substitute(param, argument, fuel)?;
+                    fuel.depth += 1;
+                    let result = body.eval_with(ctx, fuel);
+                    fuel.depth -= 1;
+                    result?;
+                    *self = body;
+                }
+            }
+            Expr::List(items) => {
+                let mut all_evaled = true;
+                for item in items.iter_mut() {
+                    item.eval_with(ctx, fuel)?;
+                    if !matches!(item, Expr::Value(_)) {
+                        all_evaled = false;
+                    }
+                }
+
+                if all_evaled {
+                    let items = mem::take(items);
+                    let values = items
+                        .into_vec()
+                        .into_iter()
+                        .map(|item| {
+                            let Expr::Value(value) = item else {
+                                unreachable!()
+                            };
+
+                            value
+                        })
+                        .collect();
+
+                    *self = Expr::Value(Value::List(values))
+                }
+            }
+            Expr::Map(list, function) => {
+                list.eval_with(ctx, fuel)?;
+                function.eval_with(ctx, fuel)?;
+
+                if let (Expr::Value(value), true) = (&mut **list, function.is_evaluated()) {
+                    let Value::List(items) = value else {
+                        return Err(mismatch("map", &[value]));
+                    };
+
+                    // Applying the function to each item separately keeps the items that were
+                    // already mapped when some application cannot be completed yet.
+                    let mut mapped = Expr::List(
+                        mem::take(items)
+                            .into_vec()
+                            .into_iter()
+                            .map(|item| Expr::Apply(function.clone(), Box::new(Expr::Value(item))))
+                            .collect(),
+                    );
+                    mapped.eval_with(ctx, fuel)?;
+                    *self = mapped;
+                }
+            }
+            Expr::Filter(list, predicate) => {
+                list.eval_with(ctx, fuel)?;
+                predicate.eval_with(ctx, fuel)?;
+
+                if let (Expr::Value(value), true) = (&mut **list, predicate.is_evaluated()) {
+                    let Value::List(items) = value else {
+                        return Err(mismatch("filter", &[value]));
+                    };
+
+                    let mut filtered = Expr::Concat(
+                        mem::take(items)
+                            .into_vec()
+                            .into_iter()
+                            .map(|item| {
+                                Expr::If(
+                                    Box::new(Expr::Apply(
+                                        predicate.clone(),
+                                        Box::new(Expr::Value(item.clone())),
+                                    )),
+                                    Box::new(Expr::Value(Value::List(Box::new([item])))),
+                                    Box::new(Expr::Value(Value::List(Box::new([])))),
+                                )
+                            })
+                            .collect(),
+                    );
+                    filtered.eval_with(ctx, fuel)?;
+                    *self = filtered;
+                }
+            }
+            Expr::Fold(list, accumulator, function) => {
+                list.eval_with(ctx, fuel)?;
+                function.eval_with(ctx, fuel)?;
+
+                // Each step is folded into the accumulator, so the steps taken so far are kept
+                // when some step cannot be completed yet.
+                loop {
+                    accumulator.eval_with(ctx, fuel)?;
+
+                    let (Expr::Value(value), Expr::Value(_), true) =
+                        (&mut **list, &**accumulator, function.is_evaluated())
+                    else {
+                        return Ok(());
+                    };
+
+                    let Value::List(items) = value else {
+                        return Err(mismatch("fold", &[value]));
+                    };
+
+                    let mut items = mem::take(items).into_vec();
+                    if items.is_empty() {
+                        break;
+                    }
+
+                    let item = items.remove(0);
+                    *value = Value::List(items.into_boxed_slice());
+
+                    let prior = mem::replace(&mut **accumulator, Expr::Value(Value::Unit));
+                    **accumulator = Expr::Apply(
+                        Box::new(Expr::Apply(function.clone(), Box::new(prior))),
+                        Box::new(Expr::Value(item)),
+                    );
+                }
+
+                *self = mem::replace(&mut **accumulator, Expr::Value(Value::Unit));
+            }
+            Expr::Len(collection) => {
+                collection.eval_with(ctx, fuel)?;
+
+                if let Expr::Value(value) = &**collection {
+                    let len = match value {
+                        Value::List(items) => items.len(),
+                        Value::Map(entries) => entries.len(),
+                        Value::String(string) => string.chars().count(),
+                        _ => return Err(mismatch("len", &[value])),
+                    };
+
+                    *self = Expr::Value(Value::Integer(
+                        len.try_into().expect("length should fit in an integer"),
+                    ));
+                }
+            }
+            Expr::Index(collection, key) => {
+                collection.eval_with(ctx, fuel)?;
+                key.eval_with(ctx, fuel)?;
+
+                if let (Expr::Value(collection), Expr::Value(key)) = (&mut **collection, &**key) {
+                    let item = match (collection, key) {
+                        (Value::List(items), Value::Integer(index)) => usize::try_from(*index)
+                            .ok()
+                            .and_then(|index| items.get_mut(index))
+                            .map(|item| mem::replace(item, Value::Unit)),
+                        (Value::Map(entries), key) => entries.remove(key),
+                        (collection, key) => return Err(mismatch("index", &[collection, key])),
+                    };
+
+                    let Some(item) = item else {
+                        return Err(EvalError::MissingItem(key.clone()));
+                    };
+
+                    *self = Expr::Value(item);
+                }
+            }
+            Expr::Concat(parts) => {
+                let mut all_evaled = true;
+                for part in parts.iter_mut() {
+                    part.eval_with(ctx, fuel)?;
+                    if !matches!(part, Expr::Value(_)) {
+                        all_evaled = false;
+                    }
+                }
+
+                if all_evaled {
+                    let mut items = Vec::new();
+                    let mut string = None::<String>;
+
+                    for part in mem::take(parts).into_vec() {
+                        match (part, &mut string) {
+                            (Expr::Value(Value::List(part)), None) => items.extend(part),
+                            (Expr::Value(Value::String(part)), Some(string)) => {
+                                string.push_str(&part)
+                            }
+                            (Expr::Value(Value::String(part)), None) if items.is_empty() => {
+                                string = Some(part)
+                            }
+                            (Expr::Value(value), _) => return Err(mismatch("concat", &[&value])),
+                            _ => unreachable!(),
+                        }
+                    }
+
+                    *self = Expr::Value(match string {
+                        Some(string) => Value::String(string),
+                        None => Value::List(items.into_boxed_slice()),
+                    });
+                }
+            }
+            Expr::Proj(tuple, index) => {
+                tuple.eval_with(ctx, fuel)?;
+
+                if let Expr::Value(value) = &mut **tuple {
+                    let Value::Tuple(items) = value else {
+                        return Err(mismatch(format_args!("projection {index}"), &[value]));
+                    };
+
+                    let Some(item) = items.get_mut(*index) else {
+                        return Err(EvalError::MissingItem(Value::Integer(*index as isize)));
+                    };
+
+                    *self = Expr::Value(mem::replace(item, Value::Unit));
+                }
+            }
+            Expr::Local(name) => return Err(EvalError::UnboundLocal(name.clone())),
+        }
+
+        Ok(())
+    }
+
+    /// Whether this expression cannot be evaluated any further, being either a value or a
+    /// lambda awaiting application.
+    fn is_evaluated(&self) -> bool {
+        matches!(self, Expr::Value(_) | Expr::Lambda(..))
+    }
+
+    /// Replaces the free occurrences of the local `name` with `replacement`.
+    ///
+    /// Since evaluation substitutes locals from the outside in, `replacement` is always closed,
+    /// so no care needs to be taken to avoid capturing its locals.
+    fn substitute(
+        &mut self,
+        name: &Name,
+        replacement: &Expr<Ident>,
+        fuel: &mut Fuel,
+    ) -> Result<(), EvalError<Ident>> {
+        fuel.burn(1)?;
+
+        match self {
+            Expr::Tuple(items) => {
+                for item in items.iter_mut() {
+                    item.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Read(_) | Expr::Value(_) => (),
+            Expr::Unary(_, operand) => operand.substitute(name, replacement, fuel)?,
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.substitute(name, replacement, fuel)?;
+                rhs.substitute(name, replacement, fuel)?;
+            }
+            Expr::If(cond, then, otherwise) => {
+                cond.substitute(name, replacement, fuel)?;
+                then.substitute(name, replacement, fuel)?;
+                otherwise.substitute(name, replacement, fuel)?;
+            }
+            Expr::Record(fields) => {
+                for (_, field) in fields.iter_mut() {
+                    field.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Field(record, _) => record.substitute(name, replacement, fuel)?,
+            Expr::Variant(_, payload) => payload.substitute(name, replacement, fuel)?,
+            Expr::Match(scrutinee, arms) => {
+                scrutinee.substitute(name, replacement, fuel)?;
+
+                // An arm binding the same name shadows it.
+                for arm in arms.iter_mut().filter(|arm| &arm.binding != name) {
+                    arm.body.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Let(binding, bound, body) => {
+                bound.substitute(name, replacement, fuel)?;
+                if binding != name {
+                    body.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Lambda(param, body) => {
+                if param != name {
+                    body.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Apply(function, argument) => {
+                function.substitute(name, replacement, fuel)?;
+                argument.substitute(name, replacement, fuel)?;
+            }
+            Expr::List(items) | Expr::Concat(items) => {
+                for item in items.iter_mut() {
+                    item.substitute(name, replacement, fuel)?;
+                }
+            }
+            Expr::Map(list, function) | Expr::Filter(list, function) => {
+                list.substitute(name, replacement, fuel)?;
+                function.substitute(name, replacement, fuel)?;
+            }
+            Expr::Fold(list, accumulator, function) => {
+                list.substitute(name, replacement, fuel)?;
+                accumulator.substitute(name, replacement, fuel)?;
+                function.substitute(name, replacement, fuel)?;
+            }
+            Expr::Len(collection) => collection.substitute(name, replacement, fuel)?,
+            Expr::Index(collection, key) => {
+                collection.substitute(name, replacement, fuel)?;
+                key.substitute(name, replacement, fuel)?;
+            }
+            Expr::Proj(tuple, _) => tuple.substitute(name, replacement, fuel)?,
+            Expr::Local(local) => {
+                if local == name {
+                    fuel.burn(replacement.size())?;
+                    *self = replacement.clone();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of nodes in the expression, counting those of the values in it.
+    fn size(&self) -> usize {
+        let sum = |exprs: &[Expr<Ident>]| exprs.iter().map(Expr::size).sum::<usize>();
+
+        1 + match self {
+            Expr::Tuple(items) | Expr::List(items) | Expr::Concat(items) => sum(items),
+            Expr::Read(_) | Expr::Local(_) => 0,
+            Expr::Value(value) => value_size(value),
+            Expr::Unary(_, operand) => operand.size(),
+            Expr::Binary(_, a, b)
+            | Expr::Let(_, a, b)
+            | Expr::Apply(a, b)
+            | Expr::Map(a, b)
+            | Expr::Filter(a, b)
+            | Expr::Index(a, b) => a.size() + b.size(),
+            Expr::If(a, b, c) | Expr::Fold(a, b, c) => a.size() + b.size() + c.size(),
+            Expr::Record(fields) => fields.iter().map(|(_, field)| field.size()).sum(),
+            Expr::Field(a, _)
+            | Expr::Variant(_, a)
+            | Expr::Lambda(_, a)
+            | Expr::Len(a)
+            | Expr::Proj(a, _) => a.size(),
+            Expr::Match(scrutinee, arms) => {
+                scrutinee.size() + arms.iter().map(|arm| arm.body.size()).sum::<usize>()
+            }
         }
     }
 
     /// Traverses the expression, calling the callback with each Ident the Expr might read from.
     pub fn visit_reads(&self, visitor: &mut impl FnMut(&Ident, bool)) {
+        self.visit_reads_with(true, visitor);
+    }
+
+    /// Like [`Expr::visit_reads`], but reports every read as indefinite unless `definite` holds.
+    fn visit_reads_with(&self, definite: bool, visitor: &mut impl FnMut(&Ident, bool)) {
         match self {
             Expr::Tuple(items) => {
                 for item in items {
-                    item.visit_reads(visitor);
+                    item.visit_reads_with(definite, visitor);
                 }
             }
-            Expr::Read(ident) => visitor(ident, true),
+            Expr::Read(ident) => visitor(ident, definite),
             Expr::Value(_) => (),
+            Expr::Unary(_, operand) => operand.visit_reads_with(definite, visitor),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.visit_reads_with(definite, visitor);
+                rhs.visit_reads_with(definite, visitor);
+            }
+            Expr::If(cond, then, otherwise) => {
+                cond.visit_reads_with(definite, visitor);
+
+                // Which branch is taken is only known during evaluation.
+                then.visit_reads_with(false, visitor);
+                otherwise.visit_reads_with(false, visitor);
+            }
+            Expr::Record(fields) => {
+                for (_, field) in fields {
+                    field.visit_reads_with(definite, visitor);
+                }
+            }
+            Expr::Field(record, _) => record.visit_reads_with(definite, visitor),
+            Expr::Variant(_, payload) => payload.visit_reads_with(definite, visitor),
+            Expr::Match(scrutinee, arms) => {
+                scrutinee.visit_reads_with(definite, visitor);
+
+                for arm in arms {
+                    arm.body.visit_reads_with(false, visitor);
+                }
+            }
+            Expr::Let(_, bound, body) => {
+                bound.visit_reads_with(definite, visitor);
+                body.visit_reads_with(definite, visitor);
+            }
+            // Whether the body of a lambda is evaluated depends on whether it is applied.
+            Expr::Lambda(_, body) => body.visit_reads_with(false, visitor),
+            Expr::Apply(function, argument) => {
+                function.visit_reads_with(definite, visitor);
+                argument.visit_reads_with(definite, visitor);
+            }
+            Expr::List(items) | Expr::Concat(items) => {
+                for item in items {
+                    item.visit_reads_with(definite, visitor);
+                }
+            }
+            Expr::Map(list, function) | Expr::Filter(list, function) => {
+                list.visit_reads_with(definite, visitor);
+                function.visit_reads_with(definite, visitor);
+            }
+            Expr::Fold(list, accumulator, function) => {
+                list.visit_reads_with(definite, visitor);
+                accumulator.visit_reads_with(definite, visitor);
+                function.visit_reads_with(definite, visitor);
+            }
+            Expr::Len(collection) => collection.visit_reads_with(definite, visitor),
+            Expr::Index(collection, key) => {
+                collection.visit_reads_with(definite, visitor);
+                key.visit_reads_with(definite, visitor);
+            }
+            Expr::Proj(tuple, _) => tuple.visit_reads_with(definite, visitor),
+            Expr::Local(_) => (),
+        }
+    }
+}
+
+impl UnaryOp {
+    pub fn apply<Ident>(self, operand: &Value) -> Result<Value, EvalError<Ident>> {
+        match (self, operand) {
+            (UnaryOp::Neg, Value::Integer(n)) => {
+                n.checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| EvalError::Overflow {
+                        operation: format!("{self:?}"),
+                        operands: vec![operand.clone()],
+                    })
+            }
+            (UnaryOp::Neg, Value::Float(x)) => Ok(Value::Float(-x)),
+            (UnaryOp::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+            _ => Err(mismatch(format_args!("{self:?}"), &[operand])),
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn apply<Ident>(self, lhs: &Value, rhs: &Value) -> Result<Value, EvalError<Ident>> {
+        let mismatch = || mismatch(format_args!("{self:?}"), &[lhs, rhs]);
+
+        Ok(match (self, lhs, rhs) {
+            (BinaryOp::Eq, lhs, rhs) => Value::Boolean(lhs.ieee_cmp(rhs) == Some(Ordering::Equal)),
+            (BinaryOp::Ne, lhs, rhs) => Value::Boolean(lhs.ieee_cmp(rhs) != Some(Ordering::Equal)),
+            // Floats are ordered as IEEE 754 numbers, wherever they are nested, so that every
+            // comparison deciding on NaN fails.
+            (BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge, lhs, rhs)
+                if mem::discriminant(lhs) == mem::discriminant(rhs) =>
+            {
+                let ordering = lhs.ieee_cmp(rhs);
+                Value::Boolean(match self {
+                    BinaryOp::Lt => ordering.is_some_and(Ordering::is_lt),
+                    BinaryOp::Le => ordering.is_some_and(Ordering::is_le),
+                    BinaryOp::Gt => ordering.is_some_and(Ordering::is_gt),
+                    _ => ordering.is_some_and(Ordering::is_ge),
+                })
+            }
+            (_, Value::Integer(a), Value::Integer(b)) => {
+                if matches!(self, BinaryOp::Div | BinaryOp::Rem) && *b == 0 {
+                    return Err(EvalError::DivisionByZero);
+                }
+
+                let result = match self {
+                    BinaryOp::Add => a.checked_add(*b),
+                    BinaryOp::Sub => a.checked_sub(*b),
+                    BinaryOp::Mul => a.checked_mul(*b),
+                    BinaryOp::Div => a.checked_div(*b),
+                    BinaryOp::Rem => a.checked_rem(*b),
+                    _ => return Err(mismatch()),
+                };
+
+                Value::Integer(result.ok_or_else(|| EvalError::Overflow {
+                    operation: format!("{self:?}"),
+                    operands: vec![lhs.clone(), rhs.clone()],
+                })?)
+            }
+            (_, Value::Float(a), Value::Float(b)) => Value::Float(match self {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => a % b,
+                _ => return Err(mismatch()),
+            }),
+            (BinaryOp::And, Value::Boolean(a), Value::Boolean(b)) => Value::Boolean(*a && *b),
+            (BinaryOp::Or, Value::Boolean(a), Value::Boolean(b)) => Value::Boolean(*a || *b),
+            _ => return Err(mismatch()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        expr::{parse::parse_action, Action, BinaryOp, Expr, MatchArm, Name, Type, UnaryOp, Value},
+        node::VersionedReactiveAddress,
+    };
+
+    use super::{ActionEvalContext, EvalError, ExprEvalContext};
+
+    struct NoReads;
+
+    /// A single reactive, which every name refers to.
+    struct Memory(Value);
+
+    impl ExprEvalContext<VersionedReactiveAddress> for Memory {
+        fn read(&mut self, _: &VersionedReactiveAddress) -> Result<Option<&Value>, EvalError> {
+            Ok(Some(&self.0))
         }
     }
+
+    impl ActionEvalContext for Memory {
+        fn write(
+            &mut self,
+            _: &VersionedReactiveAddress,
+            value: &Value,
+        ) -> Result<bool, EvalError> {
+            self.0 = value.clone();
+            Ok(true)
+        }
+    }
+
+    impl ExprEvalContext<VersionedReactiveAddress> for NoReads {
+        fn read(&mut self, _: &VersionedReactiveAddress) -> Result<Option<&Value>, EvalError> {
+            Ok(None)
+        }
+    }
+
+    fn address() -> VersionedReactiveAddress {
+        VersionedReactiveAddress {
+            address: crate::actor::Address::from_index(0),
+            id: crate::node::ReactiveId(0),
+            version: crate::actor::Version::ZERO,
+        }
+    }
+
+    /// Evaluates an expression that reads no reactives.
+    fn eval(source: &str) -> Result<Expr, EvalError> {
+        let action = parse_action(&format!("a := {source}"), |_| Some(address())).unwrap();
+        let Action::Write(_, mut expr) = action else {
+            unreachable!()
+        };
+
+        expr.eval(&mut NoReads)?;
+        Ok(expr)
+    }
+
+    fn apply(op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        op.apply::<()>(&lhs, &rhs).unwrap()
+    }
+
+    #[test]
+    fn operators_apply_to_integers_and_booleans() {
+        let int = Value::Integer;
+        assert_eq!(apply(BinaryOp::Sub, int(2), int(5)), int(-3));
+        assert_eq!(apply(BinaryOp::Div, int(-7), int(2)), int(-3));
+        assert_eq!(apply(BinaryOp::Rem, int(-7), int(2)), int(-1));
+        assert_eq!(apply(BinaryOp::Ge, int(2), int(2)), Value::Boolean(true));
+        assert_eq!(
+            apply(BinaryOp::Or, Value::Boolean(false), Value::Boolean(true)),
+            Value::Boolean(true)
+        );
+
+        // Values of any type are compared for equality.
+        let pair = || Value::Tuple(Box::new([int(1), Value::Boolean(true)]));
+        assert_eq!(apply(BinaryOp::Eq, pair(), pair()), Value::Boolean(true));
+        assert_eq!(apply(BinaryOp::Ne, pair(), int(1)), Value::Boolean(true));
+
+        // !(-(1 + 2) * 3 < 0)
+        let value = |value| Box::new(Expr::Value(value));
+        let sum = Expr::Binary(BinaryOp::Add, value(int(1)), value(int(2)));
+        let negated = Expr::Unary(UnaryOp::Neg, Box::new(sum));
+        let product = Expr::Binary(BinaryOp::Mul, Box::new(negated), value(int(3)));
+        let negative = Expr::Binary(BinaryOp::Lt, Box::new(product), value(int(0)));
+        let mut expr = Expr::Unary(UnaryOp::Not, Box::new(negative));
+        expr.eval(&mut NoReads).unwrap();
+        assert!(matches!(expr, Expr::Value(Value::Boolean(false))));
+    }
+
+    #[test]
+    fn strings_floats_lists_and_maps_compute_and_compare() {
+        let string = |s: &str| Value::String(s.to_owned());
+        let list = |items: Vec<Value>| Value::List(items.into_boxed_slice());
+        assert_eq!(
+            apply(BinaryOp::Div, Value::Float(1.0), Value::Float(4.0)),
+            Value::Float(0.25)
+        );
+        assert_eq!(
+            apply(BinaryOp::Lt, string("apple"), string("banana")),
+            Value::Boolean(true)
+        );
+        let shorter = list(vec![Value::Integer(1)]);
+        let longer = list(vec![Value::Integer(1), Value::Integer(0)]);
+        assert_eq!(apply(BinaryOp::Gt, longer, shorter), Value::Boolean(true));
+
+        // Maps may be keyed by any value, and have the types of their keys and values.
+        let map = Value::Map(
+            [
+                (list(vec![string("a")]), Value::Unit),
+                (list(vec![]), Value::Unit),
+            ]
+            .into(),
+        );
+        let Value::Map(entries) = &map else {
+            unreachable!()
+        };
+        assert_eq!(entries.keys().next(), Some(&list(vec![])));
+        assert_eq!(
+            map.compute_type(),
+            Some(Type::Map(
+                Box::new(Type::List(Box::new(Type::String))),
+                Box::new(Type::Unit)
+            ))
+        );
+        assert_eq!(
+            list(vec![]).compute_type(),
+            Some(Type::List(Box::new(Type::Never)))
+        );
+        assert_eq!(list(vec![string("a"), Value::Unit]).compute_type(), None);
+    }
+
+    #[test]
+    fn matches_bind_the_payload_of_the_taken_arm() {
+        let name = |text: &str| Name {
+            text: text.to_owned(),
+        };
+        let local = |text| Box::new(Expr::Local(name(text)));
+        let arm = |tag, binding, body| MatchArm {
+            tag: name(tag),
+            binding: name(binding),
+            body,
+        };
+
+        // match some({ x: 1, y: 2 }) { none(_) -> 0, some(p) -> match other(p.y) { other(p) -> p } }
+        let point = Expr::Record(Box::new([
+            (name("x"), Expr::Value(Value::Integer(1))),
+            (name("y"), Expr::Value(Value::Integer(2))),
+        ]));
+        let inner = Expr::Match(
+            Box::new(Expr::Variant(
+                name("other"),
+                Box::new(Expr::Field(local("p"), name("y"))),
+            )),
+            Box::new([arm("other", "p", Expr::Local(name("p")))]),
+        );
+        let mut expr = Expr::Match(
+            Box::new(Expr::Variant(name("some"), Box::new(point))),
+            Box::new([
+                arm("none", "_", Expr::Value(Value::Integer(0))),
+                arm("some", "p", inner),
+            ]),
+        );
+        expr.eval(&mut NoReads).unwrap();
+        assert!(matches!(expr, Expr::Value(Value::Integer(2))));
+    }
+
+    #[test]
+    fn lambdas_see_the_locals_in_scope_where_they_are_written() {
+        let name = |text: &str| Name {
+            text: text.to_owned(),
+        };
+        let local = |text| Box::new(Expr::Local(name(text)));
+        let int = |n| Box::new(Expr::Value(Value::Integer(n)));
+        let lambda = |param, body| Box::new(Expr::Lambda(name(param), body));
+        let apply = |function, argument| Box::new(Expr::Apply(function, argument));
+
+        // let add = \x -> \y -> x + y in let x = 10 in add(1)(x) + (\x -> x)(100)
+        let add = lambda(
+            "x",
+            lambda(
+                "y",
+                Box::new(Expr::Binary(BinaryOp::Add, local("x"), local("y"))),
+            ),
+        );
+        let sum = Expr::Binary(
+            BinaryOp::Add,
+            apply(apply(local("add"), int(1)), local("x")),
+            apply(lambda("x", local("x")), int(100)),
+        );
+        let mut expr = Expr::Let(
+            name("add"),
+            add,
+            Box::new(Expr::Let(name("x"), int(10), Box::new(sum))),
+        );
+        expr.eval(&mut NoReads).unwrap();
+        assert!(matches!(expr, Expr::Value(Value::Integer(111))));
+    }
+
+    #[test]
+    fn combinators_apply_lambdas_across_collections() {
+        let name = |text: &str| Name {
+            text: text.to_owned(),
+        };
+        let local = |text| Box::new(Expr::Local(name(text)));
+        let int = |n| Box::new(Expr::Value(Value::Integer(n)));
+        let lambda = |param, body| Box::new(Expr::Lambda(name(param), body));
+        let binary = |op, lhs, rhs| Box::new(Expr::Binary(op, lhs, rhs));
+        let eval = |mut expr: Expr| {
+            expr.eval(&mut NoReads).unwrap();
+            let Expr::Value(value) = expr else {
+                panic!("{expr:?} did not evaluate")
+            };
+            value
+        };
+
+        // fold(map(filter([1, 2, 3, 4], \x -> x % 2 == 0), \x -> x * 10), 1, \acc -> \x -> acc + x)
+        let list = Box::new(Expr::List((1..=4).map(|n| *int(n)).collect()));
+        let even = binary(
+            BinaryOp::Eq,
+            binary(BinaryOp::Rem, local("x"), int(2)),
+            int(0),
+        );
+        let evens = Box::new(Expr::Filter(list, lambda("x", even)));
+        let scaled = lambda("x", binary(BinaryOp::Mul, local("x"), int(10)));
+        let sum = lambda(
+            "acc",
+            lambda("x", binary(BinaryOp::Add, local("acc"), local("x"))),
+        );
+        let mapped = Box::new(Expr::Map(evens, scaled));
+        assert_eq!(eval(Expr::Fold(mapped, int(1), sum)), Value::Integer(61));
+
+        let string = |s: &str| Expr::Value(Value::String(s.to_owned()));
+        let joined = Expr::Concat(Box::new([string("hé"), string("llo")]));
+        assert_eq!(eval(Expr::Len(Box::new(joined))), Value::Integer(5));
+
+        let pair = Expr::Tuple(Box::new([*int(1), string("two")]));
+        let second = eval(Expr::Proj(Box::new(pair), 1));
+        assert_eq!(second, Value::String("two".to_owned()));
+        let items = Box::new(Expr::List(Box::new([*int(7), *int(8)])));
+        assert_eq!(eval(Expr::Index(items, int(1))), Value::Integer(8));
+    }
+
+    #[test]
+    fn failed_evaluations_return_errors() {
+        let int = |n| Box::new(Expr::Value(Value::Integer(n)));
+        let eval = |mut expr: Expr| expr.eval(&mut NoReads).map(|()| expr);
+
+        let overflow = Expr::Binary(BinaryOp::Add, int(isize::MAX), int(1));
+        assert!(matches!(eval(overflow), Err(EvalError::Overflow { .. })));
+        let quotient = Expr::Binary(BinaryOp::Div, int(1), int(0));
+        assert_eq!(eval(quotient).unwrap_err(), EvalError::DivisionByZero);
+        let negation = Expr::Unary(UnaryOp::Not, int(1));
+        assert!(matches!(
+            eval(negation),
+            Err(EvalError::TypeMismatch { .. })
+        ));
+
+        // Untaken branches are not evaluated, so they cannot fail.
+        let quotient = Box::new(Expr::Binary(BinaryOp::Div, int(1), int(0)));
+        let condition = Box::new(Expr::Value(Value::Boolean(false)));
+        let guarded = Expr::If(condition, quotient, int(0));
+        assert!(matches!(eval(guarded), Ok(Expr::Value(Value::Integer(0)))));
+    }
+
+    #[test]
+    fn floats_compare_as_ieee_numbers() {
+        let nan = || Value::Float(f64::NAN);
+        let zero = |x: f64| Value::Float(x);
+        assert_eq!(
+            apply(BinaryOp::Eq, zero(0.0), zero(-0.0)),
+            Value::Boolean(true)
+        );
+        assert_eq!(apply(BinaryOp::Eq, nan(), nan()), Value::Boolean(false));
+        assert_eq!(apply(BinaryOp::Ne, nan(), nan()), Value::Boolean(true));
+        assert_eq!(apply(BinaryOp::Le, nan(), nan()), Value::Boolean(false));
+        assert_eq!(
+            apply(BinaryOp::Lt, zero(-0.0), zero(0.0)),
+            Value::Boolean(false)
+        );
+
+        // Floats nested in other values compare the same way, by equality and by order alike.
+        let list = |x: Value| Value::List(Box::new([x]));
+        let eq = apply(BinaryOp::Eq, list(zero(0.0)), list(zero(-0.0)));
+        assert_eq!(eq, Value::Boolean(true));
+        let lt = apply(BinaryOp::Lt, list(zero(-0.0)), list(zero(0.0)));
+        assert_eq!(lt, Value::Boolean(false));
+        let le = apply(BinaryOp::Le, list(zero(0.0)), list(zero(-0.0)));
+        assert_eq!(le, Value::Boolean(true));
+        assert_eq!(
+            apply(BinaryOp::Eq, list(nan()), list(nan())),
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            apply(BinaryOp::Ge, list(nan()), list(zero(0.0))),
+            Value::Boolean(false)
+        );
+
+        // Longer lists still come after their prefixes.
+        let pair = Value::List(Box::new([zero(-0.0), zero(1.0)]));
+        assert_eq!(
+            apply(BinaryOp::Lt, list(zero(0.0)), pair),
+            Value::Boolean(true)
+        );
+
+        // As map keys, values are still told apart by their total order.
+        assert_ne!(zero(0.0), zero(-0.0));
+        assert_eq!(nan(), nan());
+    }
+
+    #[test]
+    fn applications_are_bounded() {
+        assert_eq!(eval(r"(\x -> x(x))(\x -> x(x))"), Err(EvalError::Stalled));
+
+        let twice = r"let twice = \f -> \x -> f(f(x)) in twice(twice)(\y -> y + 1)(0)";
+        assert_eq!(eval(twice), Ok(Expr::Value(Value::Integer(4))));
+    }
+
+    #[test]
+    fn substitutions_are_bounded() {
+        // Few applications nest, but there are exponentially many, building ever larger terms.
+        let t = |ts: &str| format!(r"let t = \f -> \x -> f(f(x)) in t{ts}(\y -> y + 1)(0)");
+        let sixteen = eval(&t("(t)(t)(t)"));
+        assert_eq!(sixteen, Ok(Expr::Value(Value::Integer(1 << 16))));
+        assert_eq!(eval(&t("(t)(t)(t)(t)")), Err(EvalError::Stalled));
+
+        // Each step doubles the accumulator, which is substituted into the step twice.
+        let items = (0..32)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let doubling = format!(r"fold([{items}], [0], \acc -> \x -> concat(acc, acc))");
+        assert_eq!(eval(&doubling), Err(EvalError::Stalled));
+    }
+
+    #[test]
+    fn loops_with_many_iterations_run_without_recursing() {
+        let source = "while x < 100000 limit 100000 { x := x + 1 }";
+        let mut action = parse_action(source, |_| Some(address())).unwrap();
+        let mut memory = Memory(Value::Integer(0));
+        action.eval(&mut memory).unwrap();
+
+        assert!(matches!(action, Action::Nil));
+        assert_eq!(memory.0, Value::Integer(100000));
+    }
 }
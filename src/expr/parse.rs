@@ -0,0 +1,1224 @@
+//! A concrete syntax for upgrades, actions and values.
+//!
+//! An upgrade is a sequence of statements separated by semicolons:
+//!
+//! ```text
+//! var x = 0; def y = x + 1; del z@3; migrate w = w * 2
+//! ```
+//!
+//! and an action is a sequence of writes, such as `x := y + 1; z := 0`, which can be made
+//! conditional, repeated and given locals:
+//!
+//! ```text
+//! if x > 0 { y := y + x } else { y := 0 };
+//! while x < 10 limit 100 { x := x + 1 };
+//! let d = x * 2 in z := d; w := d
+//! ```
+//!
+//! A loop fails if its condition still holds after as many iterations as its limit allows. A `let`
+//! extends over the statements after it up to the end of the enclosing block, which can also be
+//! written on its own as `{ ... }`.
+//!
+//! Reactives are referred to by name, which the caller resolves to the latest version of an
+//! existing reactive. `name@3` refers to version 3 instead, and `$node.id@version` refers to a
+//! reactive by its address without going through a name. In an upgrade, a name that was declared
+//! by an earlier statement refers to that reactive, and declaring a name that resolves to nothing
+//! creates a new reactive. Within expressions, locals shadow reactives of the same name, which
+//! can still be referred to as `$name`.
+//!
+//! The statements of an upgrade can be grouped in blocks `{ ... }` like those of an action, and
+//! `{}` on its own is an empty statement.
+//!
+//! Expressions are written with the usual operators, `let x = e in body`, lambdas `\x -> body`,
+//! `if c then a else b`, `match e { #tag x => body, ... }`, records `{a = 1}`, variants `#tag(e)`
+//! and the builtins `map`, `filter`, `fold`, `len` and `concat`. Constant compound values, which
+//! can also hold maps, are quoted: `'{1: "one"}`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Range,
+};
+
+use lexer::Token;
+
+use crate::{
+    actor::{Address, Version},
+    node::{ReactiveId, VersionedReactiveAddress},
+};
+
+use super::{
+    typecheck::Location, Action, BinaryOp, Expr, Ident, MatchArm, Name, UnaryOp, Upgrade, Value,
+};
+
+mod lexer;
+
+const KEYWORDS: &[&str] = &[
+    "var", "def", "del", "migrate", "let", "in", "if", "then", "else", "match", "true", "false",
+    "nan", "inf", "map", "filter", "fold", "len", "concat", "while", "limit",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte range of the source the error is about.
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Formats the error with the line of the source it is about, underlining its span.
+    pub fn render(&self, source: &str) -> String {
+        render(source, &self.span, &self.message)
+    }
+}
+
+/// Formats `message` with the line of `source` that `span` starts on, underlining the span.
+pub(super) fn render(source: &str, span: &Range<usize>, message: &str) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line = &source[line_start..line_end];
+    let number = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let width = source[start..span.end.clamp(start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+    format!(
+        "{number}:{column}: {message}\n{line}\n{}{}",
+        " ".repeat(column - 1),
+        "^".repeat(width),
+    )
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+/// Parses an upgrade, resolving names that are not declared by the upgrade itself with
+/// `reactives`.
+pub fn parse_upgrade(
+    source: &str,
+    reactives: impl Fn(&Name) -> Option<VersionedReactiveAddress>,
+) -> Result<Upgrade, ParseError> {
+    parse_upgrade_spanned(source, reactives).map(|(upgrade, _)| upgrade)
+}
+
+/// Like [`parse_upgrade`], but also returns where each node of the upgrade is in the source.
+pub fn parse_upgrade_spanned(
+    source: &str,
+    reactives: impl Fn(&Name) -> Option<VersionedReactiveAddress>,
+) -> Result<(Upgrade, Spans), ParseError> {
+    let mut parser = Parser::new(source, &reactives)?;
+    let upgrade = parser.parse_upgrade_statements()?;
+    parser.expect_end()?;
+    Ok((upgrade, parser.into_spans()))
+}
+
+/// Parses an action, resolving names with `reactives`.
+pub fn parse_action(
+    source: &str,
+    reactives: impl Fn(&Name) -> Option<VersionedReactiveAddress>,
+) -> Result<Action, ParseError> {
+    parse_action_spanned(source, reactives).map(|(action, _)| action)
+}
+
+/// Like [`parse_action`], but also returns where each node of the action is in the source.
+pub fn parse_action_spanned(
+    source: &str,
+    reactives: impl Fn(&Name) -> Option<VersionedReactiveAddress>,
+) -> Result<(Action, Spans), ParseError> {
+    let mut parser = Parser::new(source, &reactives)?;
+    let action = parser.parse_statements()?;
+    parser.expect_end()?;
+    Ok((action, parser.into_spans()))
+}
+
+/// Parses a value, such as `{"a": [1, 2], "b": []}`.
+pub fn parse_value(source: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser::new(source, &|_| None)?;
+    let value = parser.parse_value()?;
+    parser.expect_end()?;
+    Ok(value)
+}
+
+fn sequence<T>(statements: Vec<T>, nil: T, seq: impl Fn(T, T) -> T) -> T {
+    statements
+        .into_iter()
+        .rev()
+        .reduce(|rest, statement| seq(statement, rest))
+        .unwrap_or(nil)
+}
+
+/// Where the nodes of a parsed upgrade or action are in the source, by their [`Location`], so
+/// that type errors can be pointed out in it.
+#[derive(Debug, Clone, Default)]
+pub struct Spans(HashMap<Location, Range<usize>>);
+
+impl Spans {
+    /// The span of the node at `location`, or of its closest enclosing node that has one.
+    pub fn get(&self, location: &Location) -> Range<usize> {
+        (0..=location.len())
+            .rev()
+            .find_map(|len| self.0.get(&location[..len]))
+            .cloned()
+            .unwrap_or(0..0)
+    }
+}
+
+/// The span of a parsed node along with those of its children, which are numbered like in a
+/// [`Location`].
+struct SpanTree {
+    span: Range<usize>,
+    children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    fn flatten_into(self, location: &mut Location, spans: &mut Spans) {
+        spans.0.insert(location.clone(), self.span);
+        for (i, child) in self.children.into_iter().enumerate() {
+            location.push(i);
+            child.flatten_into(location, spans);
+            location.pop();
+        }
+    }
+}
+
+/// A reference to a reactive, as written in the source.
+enum Reference {
+    Name(Name, Option<Version>),
+    Address(VersionedReactiveAddress),
+}
+
+/// How references are resolved depends on what the expression is parsed for: upgrades may refer to
+/// the reactives they create, but actions only to existing ones.
+trait Resolve: Sized {
+    fn resolve(parser: &Parser, reference: (Reference, Range<usize>)) -> Result<Self, ParseError>;
+}
+
+impl Resolve for VersionedReactiveAddress {
+    fn resolve(parser: &Parser, reference: (Reference, Range<usize>)) -> Result<Self, ParseError> {
+        parser.existing(reference)
+    }
+}
+
+impl Resolve for Ident {
+    fn resolve(parser: &Parser, reference: (Reference, Range<usize>)) -> Result<Self, ParseError> {
+        if let (Reference::Name(name, None), _) = &reference {
+            if let Some(ident) = parser.declared.get(name) {
+                return Ok(ident.clone());
+            }
+        }
+        parser.existing(reference).map(Ident::Existing)
+    }
+}
+
+struct Parser<'r> {
+    tokens: Vec<(Token, Range<usize>)>,
+    position: usize,
+    reactives: &'r dyn Fn(&Name) -> Option<VersionedReactiveAddress>,
+    /// The reactives declared by the statements of the upgrade parsed so far.
+    declared: HashMap<Name, Ident>,
+    /// The locals in scope, innermost last.
+    locals: Vec<Name>,
+    /// The spans of the nodes parsed so far whose parents have not been parsed yet, last parsed
+    /// last.
+    spans: Vec<SpanTree>,
+}
+
+impl<'r> Parser<'r> {
+    fn new(
+        source: &str,
+        reactives: &'r dyn Fn(&Name) -> Option<VersionedReactiveAddress>,
+    ) -> Result<Parser<'r>, ParseError> {
+        Ok(Parser {
+            tokens: lexer::lex(source)?,
+            position: 0,
+            reactives,
+            declared: HashMap::new(),
+            locals: Vec::new(),
+            spans: Vec::new(),
+        })
+    }
+
+    /// Records a node starting at `start` and ending with the last token consumed, whose
+    /// children are the last `children` nodes recorded.
+    fn node(&mut self, start: usize, children: usize) {
+        let children = self.spans.split_off(self.spans.len() - children);
+        self.spans.push(SpanTree {
+            span: self.span_from(start),
+            children,
+        });
+    }
+
+    /// Records the nodes of a sequence of statements, which are the last `statements` nodes
+    /// recorded, the way [`sequence`] nests them.
+    fn sequence_spans(&mut self, statements: usize) {
+        if statements == 0 {
+            let start = self.span().start;
+            self.node(start, 0);
+            return;
+        }
+
+        let mut rest = self.spans.pop().unwrap();
+        for statement in self
+            .spans
+            .split_off(self.spans.len() - (statements - 1))
+            .into_iter()
+            .rev()
+        {
+            rest = SpanTree {
+                span: statement.span.start..rest.span.end,
+                children: vec![statement, rest],
+            };
+        }
+        self.spans.push(rest);
+    }
+
+    fn into_spans(mut self) -> Spans {
+        let mut spans = Spans::default();
+        if let Some(root) = self.spans.pop() {
+            root.flatten_into(&mut Vec::new(), &mut spans);
+        }
+        spans
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn peek_second(&self) -> &Token {
+        let position = (self.position + 1).min(self.tokens.len() - 1);
+        &self.tokens[position].0
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.tokens[self.position].1.clone()
+    }
+
+    /// The span from `start` up to the end of the last token consumed.
+    fn span_from(&self, start: usize) -> Range<usize> {
+        let end = self.tokens[..self.position]
+            .last()
+            .map_or(start, |(_, span)| span.end);
+        start..end.max(start)
+    }
+
+    fn at_end(&self) -> bool {
+        *self.peek() == Token::End
+    }
+
+    fn at(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Token::Symbol(s) if *s == symbol)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Name(name) if name == keyword)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let at = self.at(symbol);
+        if at {
+            self.position += 1;
+        }
+        at
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let at = self.at_keyword(keyword);
+        if at {
+            self.position += 1;
+        }
+        at
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError {
+            span: self.span(),
+            message: format!("expected {expected}, found {}", describe(self.peek())),
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{symbol}`")))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{keyword}`")))
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), ParseError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(self.unexpected("end of input"))
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<Name, ParseError> {
+        match self.peek() {
+            Token::Name(text) if !KEYWORDS.contains(&text.as_str()) => {
+                let text = text.clone();
+                self.position += 1;
+                Ok(Name { text })
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn parse_tag(&mut self) -> Result<Name, ParseError> {
+        match self.peek() {
+            Token::Tag(text) => {
+                let text = text.clone();
+                self.position += 1;
+                Ok(Name { text })
+            }
+            _ => Err(self.unexpected("a tag")),
+        }
+    }
+
+    /// Parses `name`, `$name`, `name@version` or `$node.id@version`.
+    fn parse_reference(&mut self) -> Result<(Reference, Range<usize>), ParseError> {
+        let start = self.span().start;
+        if let Token::Reactive(text) = self.peek() {
+            let name = Name { text: text.clone() };
+            self.position += 1;
+            return Ok((Reference::Name(name, None), self.span_from(start)));
+        }
+        if let Token::Address { node, id, version } = *self.peek() {
+            self.position += 1;
+            let address = VersionedReactiveAddress {
+                address: Address::from_index(node),
+                id: ReactiveId(id),
+                version: Version::new(version),
+            };
+            return Ok((Reference::Address(address), self.span_from(start)));
+        }
+
+        let name = self.parse_name()?;
+        let version = if self.eat("@") {
+            let Token::Integer(version) = *self.peek() else {
+                return Err(self.unexpected("a version"));
+            };
+            self.position += 1;
+            Some(Version::new(version as usize))
+        } else {
+            None
+        };
+        Ok((Reference::Name(name, version), self.span_from(start)))
+    }
+
+    /// Resolves a reference to an existing reactive.
+    fn existing(
+        &self,
+        (reference, span): (Reference, Range<usize>),
+    ) -> Result<VersionedReactiveAddress, ParseError> {
+        let (name, version) = match reference {
+            Reference::Address(address) => return Ok(address),
+            Reference::Name(name, version) => (name, version),
+        };
+
+        let address = match self.declared.get(&name) {
+            Some(Ident::Existing(address)) => Some(address.clone()),
+            Some(Ident::New(_)) => {
+                return Err(ParseError {
+                    span,
+                    message: format!("`{}` is created by this upgrade", name.text),
+                })
+            }
+            None => (self.reactives)(&name),
+        };
+        let Some(address) = address else {
+            return Err(ParseError {
+                span,
+                message: format!("unknown reactive `{}`", name.text),
+            });
+        };
+        Ok(VersionedReactiveAddress {
+            version: version.unwrap_or(address.version),
+            ..address
+        })
+    }
+
+    /// Parses the statements of an upgrade up to the end of the enclosing block.
+    fn parse_upgrade_statements(&mut self) -> Result<Upgrade, ParseError> {
+        let mut statements = Vec::new();
+        while !self.at_end() && !self.at("}") {
+            statements.push(self.parse_upgrade_statement()?);
+            if !self.eat(";") {
+                break;
+            }
+        }
+        self.sequence_spans(statements.len());
+        Ok(sequence(statements, Upgrade::Nil, |a, b| {
+            Upgrade::Seq(Box::new(a), Box::new(b))
+        }))
+    }
+
+    fn parse_upgrade_statement(&mut self) -> Result<Upgrade, ParseError> {
+        let start = self.span().start;
+        let var = self.at_keyword("var");
+        if self.eat_keyword("var") || self.eat_keyword("def") {
+            let (target, name) = self.parse_declaration()?;
+            self.expect("=")?;
+            let expr = self.parse_expr()?;
+            if let Some(name) = name {
+                self.declared.insert(name, target.clone());
+            }
+            self.node(start, 1);
+            Ok(if var {
+                Upgrade::Var(target, expr)
+            } else {
+                Upgrade::Def(target, expr)
+            })
+        } else if self.eat_keyword("del") {
+            let target = self.parse_reference()?;
+            self.node(start, 0);
+            Ok(Upgrade::Del(self.existing(target)?))
+        } else if self.eat_keyword("migrate") {
+            let target = self.parse_reference()?;
+            let target = self.existing(target)?;
+            self.expect("=")?;
+            let expr = self.parse_expr()?;
+            self.node(start, 1);
+            Ok(Upgrade::Migrate(target, expr))
+        } else if self.eat("{") {
+            let upgrade = self.parse_upgrade_statements()?;
+            self.expect("}")?;
+            Ok(upgrade)
+        } else {
+            Err(self.unexpected("`var`, `def`, `del`, `migrate` or a block"))
+        }
+    }
+
+    /// Parses the statements of an action up to the end of the enclosing block.
+    fn parse_statements(&mut self) -> Result<Action, ParseError> {
+        let mut statements = Vec::new();
+        while !self.at_end() && !self.at("}") {
+            let start = self.span().start;
+            if self.eat_keyword("let") {
+                let name = self.parse_name()?;
+                self.expect("=")?;
+                let value = self.parse_expr()?;
+                self.expect_keyword("in")?;
+                self.locals.push(name.clone());
+                let body = self.parse_statements();
+                self.locals.pop();
+                self.node(start, 2);
+                statements.push(Action::Let(name, value, Box::new(body?)));
+                break;
+            }
+
+            statements.push(self.parse_action_statement()?);
+            if !self.eat(";") {
+                break;
+            }
+        }
+        self.sequence_spans(statements.len());
+        Ok(sequence(statements, Action::Nil, |a, b| {
+            Action::Seq(Box::new(a), Box::new(b))
+        }))
+    }
+
+    fn parse_action_statement(&mut self) -> Result<Action, ParseError> {
+        let start = self.span().start;
+        if self.eat_keyword("if") {
+            let condition = self.parse_expr()?;
+            let then = self.parse_block()?;
+            let otherwise = if !self.eat_keyword("else") {
+                let start = self.span().start;
+                self.node(start, 0);
+                Action::Nil
+            } else if self.at_keyword("if") {
+                self.parse_action_statement()?
+            } else {
+                self.parse_block()?
+            };
+            self.node(start, 3);
+            Ok(Action::If(condition, Box::new(then), Box::new(otherwise)))
+        } else if self.eat_keyword("while") {
+            let condition = self.parse_expr()?;
+            self.expect_keyword("limit")?;
+            let Token::Integer(bound) = *self.peek() else {
+                return Err(self.unexpected("an iteration bound"));
+            };
+            self.position += 1;
+            let body = self.parse_block()?;
+            self.node(start, 2);
+            Ok(Action::While(condition, Box::new(body), bound as usize))
+        } else if self.at("{") {
+            self.parse_block()
+        } else {
+            let target = self.parse_reference()?;
+            let target = self.existing(target)?;
+            self.expect(":=")?;
+            let expr = self.parse_expr()?;
+            self.node(start, 1);
+            Ok(Action::Write(target, expr))
+        }
+    }
+
+    fn parse_block(&mut self) -> Result<Action, ParseError> {
+        self.expect("{")?;
+        let action = self.parse_statements()?;
+        self.expect("}")?;
+        Ok(action)
+    }
+
+    /// Parses the target of a `var` or `def`, which is a new reactive if its name does not resolve
+    /// to one, along with the name later statements can refer to it by.
+    fn parse_declaration(&mut self) -> Result<(Ident, Option<Name>), ParseError> {
+        let (reference, span) = self.parse_reference()?;
+        match reference {
+            Reference::Name(name, None) => {
+                let ident = match self.declared.get(&name) {
+                    Some(ident) => ident.clone(),
+                    None => match (self.reactives)(&name) {
+                        Some(address) => Ident::Existing(address),
+                        None => Ident::New(name.clone()),
+                    },
+                };
+                Ok((ident, Some(name)))
+            }
+            reference => Ok((Ident::Existing(self.existing((reference, span))?), None)),
+        }
+    }
+
+    fn parse_expr<I: Resolve>(&mut self) -> Result<Expr<I>, ParseError> {
+        let start = self.span().start;
+        if self.eat_keyword("let") {
+            let name = self.parse_name()?;
+            self.expect("=")?;
+            let value = self.parse_expr()?;
+            self.expect_keyword("in")?;
+            let body = self.parse_scoped(name.clone())?;
+            self.node(start, 2);
+            Ok(Expr::Let(name, Box::new(value), Box::new(body)))
+        } else if self.eat("\\") {
+            let name = self.parse_name()?;
+            self.expect("->")?;
+            let body = self.parse_scoped(name.clone())?;
+            self.node(start, 1);
+            Ok(Expr::Lambda(name, Box::new(body)))
+        } else if self.eat_keyword("if") {
+            let condition = self.parse_expr()?;
+            self.expect_keyword("then")?;
+            let then = self.parse_expr()?;
+            self.expect_keyword("else")?;
+            let otherwise = self.parse_expr()?;
+            self.node(start, 3);
+            Ok(Expr::If(
+                Box::new(condition),
+                Box::new(then),
+                Box::new(otherwise),
+            ))
+        } else {
+            self.parse_binary(0)
+        }
+    }
+
+    /// Parses an expression with `name` in scope.
+    fn parse_scoped<I: Resolve>(&mut self, name: Name) -> Result<Expr<I>, ParseError> {
+        self.locals.push(name);
+        let body = self.parse_expr();
+        self.locals.pop();
+        body
+    }
+
+    fn peek_binary_op(&self) -> Option<(BinaryOp, usize)> {
+        let Token::Symbol(symbol) = self.peek() else {
+            return None;
+        };
+        let op = match *symbol {
+            "||" => BinaryOp::Or,
+            "&&" => BinaryOp::And,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            _ => return None,
+        };
+        Some((op, op.precedence()))
+    }
+
+    /// Parses operators binding at least as tightly as `min`. Operators associate to the left,
+    /// except comparisons, which do not associate at all.
+    fn parse_binary<I: Resolve>(&mut self, min: usize) -> Result<Expr<I>, ParseError> {
+        let start = self.span().start;
+        let mut left = self.parse_unary()?;
+        let mut compared = false;
+        while let Some((op, precedence)) = self.peek_binary_op() {
+            if precedence < min {
+                break;
+            }
+            if op.is_comparison() {
+                if compared {
+                    return Err(ParseError {
+                        span: self.span(),
+                        message: "comparisons cannot be chained".to_owned(),
+                    });
+                }
+                compared = true;
+            }
+            self.position += 1;
+            let right = self.parse_binary(precedence + 1)?;
+            self.node(start, 2);
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary<I: Resolve>(&mut self) -> Result<Expr<I>, ParseError> {
+        let start = self.span().start;
+        if self.eat("!") {
+            let operand = self.parse_unary()?;
+            self.node(start, 1);
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(operand)));
+        }
+        if self.at("-") {
+            // A minus directly before a number is part of the literal, so that the smallest
+            // integer can be written.
+            let literal = match self.peek_second() {
+                Token::Integer(_) | Token::Float(_) => true,
+                Token::Name(name) => name == "inf" || name == "nan",
+                _ => false,
+            };
+            self.position += 1;
+            if literal {
+                let value = self.parse_number(true)?;
+                self.node(start, 0);
+                return self.parse_postfix(start, Expr::Value(value));
+            }
+            let operand = self.parse_unary()?;
+            self.node(start, 1);
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(operand)));
+        }
+        let primary = self.parse_primary()?;
+        self.parse_postfix(start, primary)
+    }
+
+    /// Parses the projections, fields, indices and applications following `expr`, which starts
+    /// at `start`.
+    fn parse_postfix<I: Resolve>(
+        &mut self,
+        start: usize,
+        mut expr: Expr<I>,
+    ) -> Result<Expr<I>, ParseError> {
+        loop {
+            if self.eat(".") {
+                expr = match self.peek() {
+                    Token::Integer(index) => {
+                        let index = *index as usize;
+                        self.position += 1;
+                        Expr::Proj(Box::new(expr), index)
+                    }
+                    _ => Expr::Field(Box::new(expr), self.parse_name()?),
+                };
+                self.node(start, 1);
+            } else if self.eat("[") {
+                let index = self.parse_expr()?;
+                self.expect("]")?;
+                self.node(start, 2);
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat("(") {
+                let argument = self.parse_expr()?;
+                self.expect(")")?;
+                self.node(start, 2);
+                expr = Expr::Apply(Box::new(expr), Box::new(argument));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parses a number, or `inf` or `nan`, which may have been preceded by a minus.
+    fn parse_number(&mut self, negative: bool) -> Result<Value, ParseError> {
+        let span = self.span();
+        let sign = if negative { -1.0 } else { 1.0 };
+        let value = match self.peek() {
+            Token::Integer(magnitude) => {
+                let magnitude = *magnitude;
+                let value = if negative {
+                    0i128 - i128::from(magnitude)
+                } else {
+                    i128::from(magnitude)
+                };
+                Value::Integer(isize::try_from(value).map_err(|_| ParseError {
+                    span,
+                    message: "integer literal is out of range".to_owned(),
+                })?)
+            }
+            Token::Float(value) => Value::Float(sign * value),
+            Token::Name(name) if name == "inf" => Value::Float(sign * f64::INFINITY),
+            // Negating flips the sign of a NaN, where multiplying it by -1 need not.
+            Token::Name(name) if name == "nan" && negative => Value::Float(-f64::NAN),
+            Token::Name(name) if name == "nan" => Value::Float(f64::NAN),
+            _ => return Err(self.unexpected("a number")),
+        };
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn parse_primary<I: Resolve>(&mut self) -> Result<Expr<I>, ParseError> {
+        let start = self.span().start;
+        let (expr, children) = match self.peek().clone() {
+            Token::Integer(_) | Token::Float(_) => (Expr::Value(self.parse_number(false)?), 0),
+            Token::String(text) => {
+                self.position += 1;
+                (Expr::Value(Value::String(text)), 0)
+            }
+            Token::Tag(_) => {
+                let tag = self.parse_tag()?;
+                let payload = if self.eat("(") {
+                    let payload = self.parse_expr()?;
+                    self.expect(")")?;
+                    payload
+                } else {
+                    self.node(start, 0);
+                    Expr::Value(Value::Unit)
+                };
+                (Expr::Variant(tag, Box::new(payload)), 1)
+            }
+            Token::Reactive(_) | Token::Address { .. } => {
+                let reference = self.parse_reference()?;
+                (Expr::Read(I::resolve(self, reference)?), 0)
+            }
+            Token::Symbol("(") => {
+                self.position += 1;
+                if self.eat(")") {
+                    (Expr::Value(Value::Unit), 0)
+                } else if self.eat(",") {
+                    self.expect(")")?;
+                    (Expr::Tuple(Box::new([])), 0)
+                } else {
+                    // A parenthesized expression is recorded as it is.
+                    let first = self.parse_expr()?;
+                    if self.eat(")") {
+                        return Ok(first);
+                    }
+                    self.expect(",")?;
+                    let mut items = vec![first];
+                    items.extend(self.parse_list(")", Self::parse_expr)?);
+                    let len = items.len();
+                    (Expr::Tuple(items.into()), len)
+                }
+            }
+            Token::Symbol("[") => {
+                self.position += 1;
+                let items = self.parse_list("]", Self::parse_expr)?;
+                let len = items.len();
+                (Expr::List(items.into()), len)
+            }
+            Token::Symbol("{") => {
+                self.position += 1;
+                let fields = self.parse_list("}", |parser| {
+                    let name = parser.parse_name()?;
+                    parser.expect("=")?;
+                    Ok((name, parser.parse_expr()?))
+                })?;
+                let len = fields.len();
+                (Expr::Record(fields.into()), len)
+            }
+            Token::Symbol("'") => {
+                self.position += 1;
+                (Expr::Value(self.parse_value()?), 0)
+            }
+            // These record their own nodes.
+            Token::Symbol("\\") => return self.parse_expr(),
+            Token::Name(name) => match name.as_str() {
+                "true" | "false" => {
+                    self.position += 1;
+                    (Expr::Value(Value::Boolean(name == "true")), 0)
+                }
+                "nan" | "inf" => (Expr::Value(self.parse_number(false)?), 0),
+                "let" | "if" => return self.parse_expr(),
+                "match" => self.parse_match()?,
+                "map" | "filter" | "fold" | "len" | "concat" => self.parse_builtin(&name)?,
+                _ => {
+                    let reference = self.parse_reference()?;
+                    match &reference {
+                        (Reference::Name(name, None), _) if self.locals.contains(name) => {
+                            (Expr::Local(name.clone()), 0)
+                        }
+                        _ => (Expr::Read(I::resolve(self, reference)?), 0),
+                    }
+                }
+            },
+            _ => return Err(self.unexpected("an expression")),
+        };
+
+        self.node(start, children);
+        Ok(expr)
+    }
+
+    /// Parses items separated by commas, with an optional trailing comma, up to `close`.
+    fn parse_list<T>(
+        &mut self,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(item(self)?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Parses a match, returning it along with its number of children: the scrutinee, and the
+    /// body of each arm.
+    fn parse_match<I: Resolve>(&mut self) -> Result<(Expr<I>, usize), ParseError> {
+        self.expect_keyword("match")?;
+        let scrutinee = self.parse_expr()?;
+        self.expect("{")?;
+        let arms = self.parse_list("}", |parser| {
+            let tag = parser.parse_tag()?;
+            let binding = parser.parse_name()?;
+            parser.expect("=>")?;
+            let body = parser.parse_scoped(binding.clone())?;
+            Ok(MatchArm { tag, binding, body })
+        })?;
+        let children = arms.len() + 1;
+        Ok((Expr::Match(Box::new(scrutinee), arms.into()), children))
+    }
+
+    /// Parses a builtin, returning it along with its number of children, its arguments.
+    fn parse_builtin<I: Resolve>(&mut self, name: &str) -> Result<(Expr<I>, usize), ParseError> {
+        let start = self.span().start;
+        self.position += 1;
+        self.expect("(")?;
+        let mut arguments = self.parse_list(")", Self::parse_expr)?;
+        let arity = match name {
+            "map" | "filter" => 2,
+            "fold" => 3,
+            "len" => 1,
+            _ => {
+                let len = arguments.len();
+                return Ok((Expr::Concat(arguments.into()), len));
+            }
+        };
+        if arguments.len() != arity {
+            return Err(ParseError {
+                span: self.span_from(start),
+                message: format!(
+                    "`{name}` takes {arity} arguments, but {} were given",
+                    arguments.len()
+                ),
+            });
+        }
+
+        let mut argument = || Box::new(arguments.remove(0));
+        let builtin = match name {
+            "map" => Expr::Map(argument(), argument()),
+            "filter" => Expr::Filter(argument(), argument()),
+            "fold" => Expr::Fold(argument(), argument(), argument()),
+            _ => Expr::Len(argument()),
+        };
+        Ok((builtin, arity))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let start = self.span().start;
+        match self.peek().clone() {
+            Token::Symbol("-") => {
+                self.position += 1;
+                self.parse_number(true)
+            }
+            Token::Integer(_) | Token::Float(_) => self.parse_number(false),
+            Token::String(text) => {
+                self.position += 1;
+                Ok(Value::String(text))
+            }
+            Token::Name(name) if name == "true" || name == "false" => {
+                self.position += 1;
+                Ok(Value::Boolean(name == "true"))
+            }
+            Token::Name(name) if name == "nan" || name == "inf" => self.parse_number(false),
+            Token::Tag(_) => {
+                let tag = self.parse_tag()?;
+                let payload = if self.eat("(") {
+                    let payload = self.parse_value()?;
+                    self.expect(")")?;
+                    payload
+                } else {
+                    Value::Unit
+                };
+                Ok(Value::Variant(tag, Box::new(payload)))
+            }
+            Token::Symbol("(") => {
+                self.position += 1;
+                if self.eat(")") {
+                    return Ok(Value::Unit);
+                }
+                if self.eat(",") {
+                    self.expect(")")?;
+                    return Ok(Value::Tuple(Box::new([])));
+                }
+                let first = self.parse_value()?;
+                if self.eat(")") {
+                    return Ok(first);
+                }
+                self.expect(",")?;
+                let mut items = vec![first];
+                items.extend(self.parse_list(")", Self::parse_value)?);
+                Ok(Value::Tuple(items.into()))
+            }
+            Token::Symbol("[") => {
+                self.position += 1;
+                let list = Value::List(self.parse_list("]", Self::parse_value)?.into());
+                self.homogeneous(list, start)
+            }
+            Token::Symbol("{") => {
+                self.position += 1;
+                if self.eat(":") {
+                    self.expect("}")?;
+                    return Ok(Value::Map(BTreeMap::new()));
+                }
+                if self.eat("}") {
+                    return Ok(Value::Record(BTreeMap::new()));
+                }
+                // A record starts with a field name, while a map starts with a key value.
+                if matches!(self.peek(), Token::Name(_))
+                    && *self.peek_second() == Token::Symbol("=")
+                {
+                    let fields = self.parse_list("}", |parser| {
+                        let name = parser.parse_name()?;
+                        parser.expect("=")?;
+                        Ok((name, parser.parse_value()?))
+                    })?;
+                    Ok(Value::Record(self.unique(fields, start)?))
+                } else {
+                    let entries = self.parse_list("}", |parser| {
+                        let key = parser.parse_value()?;
+                        parser.expect(":")?;
+                        Ok((key, parser.parse_value()?))
+                    })?;
+                    let map = Value::Map(self.unique(entries, start)?);
+                    self.homogeneous(map, start)
+                }
+            }
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    fn unique<K: Ord, V>(
+        &self,
+        entries: Vec<(K, V)>,
+        start: usize,
+    ) -> Result<BTreeMap<K, V>, ParseError> {
+        let count = entries.len();
+        let map = entries.into_iter().collect::<BTreeMap<_, _>>();
+        if map.len() != count {
+            return Err(ParseError {
+                span: self.span_from(start),
+                message: "duplicate key".to_owned(),
+            });
+        }
+        Ok(map)
+    }
+
+    /// Checks that the items of a list or map literal are all of one type.
+    fn homogeneous(&self, collection: Value, start: usize) -> Result<Value, ParseError> {
+        match collection.compute_type() {
+            Some(_) => Ok(collection),
+            None => Err(ParseError {
+                span: self.span_from(start),
+                message: "items of different types".to_owned(),
+            }),
+        }
+    }
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds; the higher, the tighter.
+    pub(super) fn precedence(self) -> usize {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 5,
+        }
+    }
+
+    pub(super) fn is_comparison(self) -> bool {
+        self.precedence() == 3
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(name) => format!("`{name}`"),
+        Token::Tag(tag) => format!("`#{tag}`"),
+        Token::Integer(value) => format!("`{value}`"),
+        Token::Float(value) => format!("`{value:?}`"),
+        Token::String(_) => "a string".to_owned(),
+        Token::Reactive(name) => format!("`${name}`"),
+        Token::Address { node, id, version } => format!("`${node}.{id}@{version}`"),
+        Token::Symbol(symbol) => format!("`{symbol}`"),
+        Token::End => "end of input".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        actor::{Address, Version},
+        expr::{BinaryOp, Expr, Ident, Name, Upgrade, Value},
+        node::{ReactiveId, VersionedReactiveAddress},
+    };
+
+    use super::{parse_action, parse_upgrade, parse_value};
+
+    fn name(text: &str) -> Name {
+        Name {
+            text: text.to_owned(),
+        }
+    }
+
+    /// Resolves `z` to the only existing reactive, at version 1.
+    fn resolve(name: &Name) -> Option<VersionedReactiveAddress> {
+        (name.text == "z").then(|| VersionedReactiveAddress {
+            address: Address::from_index(0),
+            id: ReactiveId(0),
+            version: Version::new(1),
+        })
+    }
+
+    fn int(value: isize) -> Box<Expr<Ident>> {
+        Box::new(Expr::Value(Value::Integer(value)))
+    }
+
+    fn binary(op: BinaryOp, left: Box<Expr<Ident>>, right: Box<Expr<Ident>>) -> Box<Expr<Ident>> {
+        Box::new(Expr::Binary(op, left, right))
+    }
+
+    /// The expression defining `d` in `source`, which ends with its definition.
+    fn definition(source: &str) -> Expr<Ident> {
+        let mut upgrade = parse_upgrade(source, resolve).unwrap();
+        while let Upgrade::Seq(_, rest) = upgrade {
+            upgrade = *rest;
+        }
+        let Upgrade::Def(Ident::New(defined), expr) = upgrade else {
+            panic!("{source} does not end with a definition");
+        };
+        assert_eq!(defined, name("d"));
+        expr
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(
+            definition("def d = 1 - 2 - 3 * 4"),
+            *binary(
+                BinaryOp::Sub,
+                binary(BinaryOp::Sub, int(1), int(2)),
+                binary(BinaryOp::Mul, int(3), int(4)),
+            )
+        );
+        assert_eq!(
+            definition("def d = 1 < 2 || 3 == 4 && 5 != 6"),
+            *binary(
+                BinaryOp::Or,
+                binary(BinaryOp::Lt, int(1), int(2)),
+                binary(
+                    BinaryOp::And,
+                    binary(BinaryOp::Eq, int(3), int(4)),
+                    binary(BinaryOp::Ne, int(5), int(6)),
+                ),
+            )
+        );
+        // The minus of a literal is part of it, so the smallest integer can be written.
+        assert_eq!(
+            definition(&format!("def d = {}", isize::MIN)),
+            *int(isize::MIN)
+        );
+        // Comparisons do not chain.
+        assert!(parse_upgrade("def d = 1 < 2 < 3", resolve).is_err());
+    }
+
+    #[test]
+    fn names_resolve_to_locals_then_declarations_then_existing_reactives() {
+        let z = resolve(&name("z")).unwrap();
+        let x = || Box::new(Expr::Read(Ident::New(name("x"))));
+        assert_eq!(
+            definition("var x = 1; def d = let x = z in x + $x + z@3"),
+            Expr::Let(
+                name("x"),
+                Box::new(Expr::Read(Ident::Existing(z.clone()))),
+                binary(
+                    BinaryOp::Add,
+                    binary(BinaryOp::Add, Box::new(Expr::Local(name("x"))), x()),
+                    Box::new(Expr::Read(Ident::Existing(VersionedReactiveAddress {
+                        version: Version::new(3),
+                        ..z
+                    }))),
+                ),
+            )
+        );
+
+        // Reactives created by the upgrade cannot be deleted or migrated by it.
+        assert!(parse_upgrade("var x = 1; del x", resolve).is_err());
+        assert!(parse_upgrade("def d = y", resolve).is_err());
+    }
+
+    #[test]
+    fn errors_point_at_where_they_are_in_the_source() {
+        let source = "z := 1;\nz := (1 +;";
+        let error = parse_action(source, resolve).unwrap_err();
+        assert_eq!(&source[error.span.clone()], ";");
+        assert_eq!(
+            error.render(source),
+            format!("2:10: {}\nz := (1 +;\n         ^", error.message)
+        );
+
+        let error = parse_action("y := 1", resolve).unwrap_err();
+        assert_eq!(error.message, "unknown reactive `y`");
+        assert_eq!(error.span, 0..1);
+    }
+
+    #[test]
+    fn collection_literals_hold_items_of_one_type() {
+        let error = parse_value(r#"[1, "a"]"#).unwrap_err();
+        assert_eq!(error.span, 0..8);
+        assert!(parse_value(r#"{1: 2, "a": 3}"#).is_err());
+        assert!(parse_value(r#"{1: 2, 3: "a"}"#).is_err());
+        assert!(parse_value(r#"[[1], ["a"]]"#).is_err());
+
+        // Variants with different tags are of one type, the union of their tags.
+        let value = parse_value(r#"[#a(1), #b("x")]"#).unwrap();
+        assert!(value.compute_type().is_some());
+        assert_eq!(parse_value("[]"), Ok(Value::List(Box::new([]))));
+    }
+}
@@ -0,0 +1,252 @@
+use std::ops::Range;
+
+use super::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    /// A variant tag, written `#tag`.
+    Tag(String),
+    Integer(u64),
+    Float(f64),
+    String(String),
+    /// A reactive written by name, `$name`, which locals of the same name do not shadow.
+    Reactive(String),
+    /// A reactive written by its address, `$node.id@version`.
+    Address {
+        node: usize,
+        id: usize,
+        version: usize,
+    },
+    Symbol(&'static str),
+    End,
+}
+
+// Longer symbols come first, so that they take precedence over their prefixes.
+const SYMBOLS: &[&str] = &[
+    ":=", "==", "!=", "<=", ">=", "&&", "||", "->", "=>", "(", ")", "[", "]", "{", "}", ",", ";",
+    ":", "=", "<", ">", "+", "-", "*", "/", "%", "!", ".", "@", "\\", "'",
+];
+
+pub fn lex(source: &str) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+    };
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_trivia();
+        let start = lexer.position;
+        // A number directly after a dot is a tuple position, so `t.0.1` is two projections rather
+        // than a projection by a float.
+        let after_dot = matches!(tokens.last(), Some((Token::Symbol("."), _)));
+        let token = lexer.next_token(after_dot)?;
+        let end = token == Token::End;
+        tokens.push((token, start..lexer.position));
+        if end {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'s> {
+    source: &'s str,
+    position: usize,
+}
+
+impl Lexer<'_> {
+    fn rest(&self) -> &str {
+        &self.source[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, start: usize, message: impl Into<String>) -> ParseError {
+        ParseError {
+            span: start..self.position,
+            message: message.into(),
+        }
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("//") {
+                let line = rest.find('\n').unwrap_or(rest.len());
+                self.position += line;
+            } else if self.peek().is_some_and(char::is_whitespace) {
+                self.bump();
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.source[start..self.position]
+    }
+
+    fn next_token(&mut self, after_dot: bool) -> Result<Token, ParseError> {
+        let start = self.position;
+        let Some(c) = self.peek() else {
+            return Ok(Token::End);
+        };
+
+        if is_name_start(c) {
+            return Ok(Token::Name(self.take_while(is_name_char).to_owned()));
+        }
+        if c.is_ascii_digit() {
+            return self.number(after_dot);
+        }
+        match c {
+            '#' => {
+                self.bump();
+                if !self.peek().is_some_and(is_name_start) {
+                    return Err(self.error(start, "expected a tag name after `#`"));
+                }
+                Ok(Token::Tag(self.take_while(is_name_char).to_owned()))
+            }
+            '"' => self.string(),
+            '$' => self.address(),
+            _ => {
+                let Some(symbol) = SYMBOLS
+                    .iter()
+                    .find(|symbol| self.rest().starts_with(**symbol))
+                else {
+                    self.bump();
+                    return Err(self.error(start, format!("unexpected character {c:?}")));
+                };
+                self.position += symbol.len();
+                Ok(Token::Symbol(symbol))
+            }
+        }
+    }
+
+    fn digits(&mut self) -> Result<usize, ParseError> {
+        let start = self.position;
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Err(self.error(start, "expected digits"));
+        }
+        digits
+            .parse()
+            .map_err(|_| self.error(start, "number is too large"))
+    }
+
+    fn number(&mut self, after_dot: bool) -> Result<Token, ParseError> {
+        let start = self.position;
+        self.take_while(|c| c.is_ascii_digit());
+        let mut float = false;
+        if !after_dot {
+            let mut chars = self.rest().chars();
+            if chars.next() == Some('.') && chars.next().is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+                self.take_while(|c| c.is_ascii_digit());
+                float = true;
+            }
+            let mut chars = self.rest().chars();
+            if matches!(chars.next(), Some('e' | 'E')) {
+                let mut next = chars.next();
+                if matches!(next, Some('+' | '-')) {
+                    next = chars.next();
+                }
+                if next.is_some_and(|c| c.is_ascii_digit()) {
+                    self.bump();
+                    if matches!(self.peek(), Some('+' | '-')) {
+                        self.bump();
+                    }
+                    self.take_while(|c| c.is_ascii_digit());
+                    float = true;
+                }
+            }
+        }
+
+        let text = &self.source[start..self.position];
+        if float {
+            let value = text
+                .parse()
+                .map_err(|_| self.error(start, "invalid float literal"))?;
+            Ok(Token::Float(value))
+        } else {
+            let value = text
+                .parse()
+                .map_err(|_| self.error(start, "integer literal is too large"))?;
+            Ok(Token::Integer(value))
+        }
+    }
+
+    fn string(&mut self) -> Result<Token, ParseError> {
+        let start = self.position;
+        self.bump();
+        let mut text = String::new();
+        loop {
+            let escape = self.position;
+            match self.bump() {
+                None => return Err(self.error(start, "unterminated string literal")),
+                Some('"') => return Ok(Token::String(text)),
+                Some('\\') => text.push(match self.bump() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('u') => self.unicode_escape(escape)?,
+                    _ => return Err(self.error(escape, "unknown escape sequence")),
+                }),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, start: usize) -> Result<char, ParseError> {
+        if self.bump() != Some('{') {
+            return Err(self.error(start, "expected `{` in unicode escape"));
+        }
+        let digits = self.take_while(|c| c.is_ascii_hexdigit()).to_owned();
+        if self.bump() != Some('}') {
+            return Err(self.error(start, "expected `}` in unicode escape"));
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(start, "invalid unicode escape"))
+    }
+
+    fn address(&mut self) -> Result<Token, ParseError> {
+        let start = self.position;
+        self.bump();
+        if self.peek().is_some_and(is_name_start) {
+            return Ok(Token::Reactive(self.take_while(is_name_char).to_owned()));
+        }
+        let node = self.digits()?;
+        if self.bump() != Some('.') {
+            return Err(self.error(start, "expected `.` in reactive address"));
+        }
+        let id = self.digits()?;
+        if self.bump() != Some('@') {
+            return Err(self.error(start, "expected `@` in reactive address"));
+        }
+        let version = self.digits()?;
+        Ok(Token::Address { node, id, version })
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
@@ -0,0 +1,968 @@
+//! Prints programs and values in the syntax of [`super::parse`], such that parsing the printed form
+//! gives back what was printed.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::node::VersionedReactiveAddress;
+
+use super::{Action, BinaryOp, Expr, Ident, Name, UnaryOp, Upgrade, Value};
+
+// How tightly an expression binds, for deciding when it needs parentheses. Binary operators lie in
+// between, at their own precedence.
+const OPEN: usize = 0;
+const UNARY: usize = 6;
+const POSTFIX: usize = 7;
+const ATOM: usize = 8;
+
+const INDENT: &str = "    ";
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Display for VersionedReactiveAddress {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "${}.{}@{}",
+            self.address.index(),
+            self.id.0,
+            self.version.number()
+        )
+    }
+}
+
+// Reactives are written as `$name` in expressions, where a bare name could refer to a local.
+impl Display for Ident {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Ident::New(name) => write!(f, "${name}"),
+            Ident::Existing(address) => address.fmt(f),
+        }
+    }
+}
+
+impl Display for Upgrade {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.print(f, 0)
+    }
+}
+
+impl Upgrade {
+    /// The statements of the upgrade, following sequences to the right, which is how they nest
+    /// when parsed.
+    fn statements(&self) -> Vec<&Upgrade> {
+        let mut statements = Vec::new();
+        let mut rest = self;
+        while let Upgrade::Seq(statement, next) = rest {
+            statements.push(&**statement);
+            rest = next;
+        }
+        statements.push(rest);
+        statements
+    }
+
+    /// Prints the statements of the upgrade, with lines after the first indented by `indent`
+    /// levels.
+    fn print(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        for (i, statement) in self.statements().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ";\n{}", INDENT.repeat(indent))?;
+            }
+            match statement {
+                Upgrade::Var(target, expr) => {
+                    f.write_str("var ")?;
+                    print_target(f, target)?;
+                    f.write_str(" = ")?;
+                    expr.print(f, OPEN, indent)?;
+                }
+                Upgrade::Def(target, expr) => {
+                    f.write_str("def ")?;
+                    print_target(f, target)?;
+                    f.write_str(" = ")?;
+                    expr.print(f, OPEN, indent)?;
+                }
+                Upgrade::Del(target) => write!(f, "del {target}")?,
+                Upgrade::Migrate(target, expr) => {
+                    write!(f, "migrate {target} = ")?;
+                    expr.print(f, OPEN, indent)?;
+                }
+                // Sequences nested to the left and empty statements would not be parsed back as
+                // such without a block of their own.
+                Upgrade::Seq(..) | Upgrade::Nil => statement.print_block(f, indent)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn print_block(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        if let Upgrade::Nil = self {
+            return f.write_str("{}");
+        }
+
+        write!(f, "{{\n{}", INDENT.repeat(indent + 1))?;
+        self.print(f, indent + 1)?;
+        write!(f, "\n{}}}", INDENT.repeat(indent))
+    }
+}
+
+/// Prints the reactive declared by a `var` or `def`, which is a new one by its bare name.
+fn print_target(f: &mut Formatter, target: &Ident) -> fmt::Result {
+    match target {
+        Ident::New(name) => name.fmt(f),
+        Ident::Existing(address) => address.fmt(f),
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.print(f, 0)
+    }
+}
+
+impl Action {
+    /// The statements of the action, following sequences to the right, which is how they nest
+    /// when parsed.
+    fn statements(&self) -> Vec<&Action> {
+        let mut statements = Vec::new();
+        let mut rest = self;
+        while let Action::Seq(statement, next) = rest {
+            statements.push(&**statement);
+            rest = next;
+        }
+        statements.push(rest);
+        statements
+    }
+
+    /// Prints the statements of the action, with lines after the first indented by `indent`
+    /// levels.
+    fn print(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        let statements = self.statements();
+        let last = statements.len() - 1;
+        for (i, statement) in statements.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ";\n{}", INDENT.repeat(indent))?;
+            }
+            match statement {
+                Action::Write(target, expr) => {
+                    write!(f, "{target} := ")?;
+                    expr.print(f, OPEN, indent)?;
+                }
+                Action::If(condition, then, otherwise) => {
+                    f.write_str("if ")?;
+                    condition.print(f, OPEN, indent)?;
+                    f.write_str(" ")?;
+                    then.print_block(f, indent)?;
+                    match &**otherwise {
+                        Action::Nil => {}
+                        Action::If(..) => {
+                            f.write_str(" else ")?;
+                            otherwise.print(f, indent)?;
+                        }
+                        _ => {
+                            f.write_str(" else ")?;
+                            otherwise.print_block(f, indent)?;
+                        }
+                    }
+                }
+                Action::While(condition, body, bound) => {
+                    f.write_str("while ")?;
+                    condition.print(f, OPEN, indent)?;
+                    write!(f, " limit {bound} ")?;
+                    body.print_block(f, indent)?;
+                }
+                // The body of a `let` extends over the statements after it, so it has to be put in
+                // a block of its own when there are any.
+                Action::Let(..) if i < last => statement.print_block(f, indent)?,
+                Action::Let(name, value, body) => {
+                    write!(f, "let {name} = ")?;
+                    value.print(f, OPEN, indent)?;
+                    f.write_str(" in")?;
+                    if !matches!(**body, Action::Nil) {
+                        write!(f, "\n{}", INDENT.repeat(indent))?;
+                        body.print(f, indent)?;
+                    }
+                }
+                // Sequences nested to the left and empty statements would not be parsed back as
+                // such without a block of their own.
+                Action::Seq(..) | Action::Nil => statement.print_block(f, indent)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn print_block(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        if let Action::Nil = self {
+            return f.write_str("{}");
+        }
+
+        write!(f, "{{\n{}", INDENT.repeat(indent + 1))?;
+        self.print(f, indent + 1)?;
+        write!(f, "\n{}}}", INDENT.repeat(indent))
+    }
+}
+
+impl<I: Display> Display for Expr<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.print(f, OPEN, 0)
+    }
+}
+
+impl<I: Display> Expr<I> {
+    fn precedence(&self) -> usize {
+        match self {
+            Expr::Let(..) | Expr::Lambda(..) | Expr::If(..) => OPEN,
+            Expr::Binary(op, ..) => op.precedence(),
+            // Numbers may start with a minus, and a variant or quoted value would take the
+            // parentheses of an application as its own.
+            Expr::Unary(..) | Expr::Variant(..) => UNARY,
+            Expr::Value(Value::String(_) | Value::Boolean(_) | Value::Unit) => ATOM,
+            Expr::Value(_) => UNARY,
+            Expr::Field(..) | Expr::Proj(..) | Expr::Index(..) | Expr::Apply(..) => POSTFIX,
+            Expr::Tuple(_)
+            | Expr::Read(_)
+            | Expr::Record(_)
+            | Expr::Match(..)
+            | Expr::List(_)
+            | Expr::Map(..)
+            | Expr::Filter(..)
+            | Expr::Fold(..)
+            | Expr::Len(_)
+            | Expr::Concat(_)
+            | Expr::Local(_) => ATOM,
+        }
+    }
+
+    /// Prints the expression in a position that requires it to bind at least as tightly as
+    /// `context`, with lines after the first indented by `indent` levels.
+    fn print(&self, f: &mut Formatter, context: usize, indent: usize) -> fmt::Result {
+        if self.precedence() < context {
+            f.write_str("(")?;
+            self.print(f, OPEN, indent)?;
+            return f.write_str(")");
+        }
+
+        match self {
+            Expr::Tuple(items) => {
+                f.write_str("(")?;
+                if items.is_empty() {
+                    f.write_str(",")?;
+                }
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.print(f, OPEN, indent)?;
+                }
+                if items.len() == 1 {
+                    f.write_str(",")?;
+                }
+                f.write_str(")")
+            }
+            Expr::Read(ident) => ident.fmt(f),
+            Expr::Value(value @ (Value::Integer(_) | Value::Float(_))) => value.fmt(f),
+            Expr::Value(value @ (Value::String(_) | Value::Boolean(_) | Value::Unit)) => {
+                value.fmt(f)
+            }
+            Expr::Value(value) => write!(f, "'{value}"),
+            Expr::Unary(op, operand) => {
+                f.write_str(match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                })?;
+                // The minus of a negated number would otherwise become part of the number.
+                let context = match (op, &**operand) {
+                    (UnaryOp::Neg, Expr::Value(Value::Integer(_) | Value::Float(_))) => ATOM,
+                    _ => UNARY,
+                };
+                operand.print(f, context, indent)
+            }
+            Expr::Binary(op, left, right) => {
+                let precedence = op.precedence();
+                let left_context = if op.is_comparison() {
+                    precedence + 1
+                } else {
+                    precedence
+                };
+                left.print(f, left_context, indent)?;
+                write!(f, " {} ", op.symbol())?;
+                right.print(f, precedence + 1, indent)
+            }
+            Expr::If(condition, then, otherwise) => {
+                f.write_str("if ")?;
+                condition.print(f, OPEN, indent)?;
+                f.write_str(" then ")?;
+                then.print(f, OPEN, indent)?;
+                f.write_str(" else ")?;
+                otherwise.print(f, OPEN, indent)
+            }
+            Expr::Record(fields) => {
+                f.write_str("{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, " {name} = ")?;
+                    value.print(f, OPEN, indent)?;
+                }
+                if !fields.is_empty() {
+                    f.write_str(" ")?;
+                }
+                f.write_str("}")
+            }
+            Expr::Field(record, name) => {
+                record.print(f, POSTFIX, indent)?;
+                write!(f, ".{name}")
+            }
+            Expr::Variant(tag, payload) => {
+                write!(f, "#{tag}")?;
+                if !matches!(**payload, Expr::Value(Value::Unit)) {
+                    f.write_str("(")?;
+                    payload.print(f, OPEN, indent)?;
+                    f.write_str(")")?;
+                }
+                Ok(())
+            }
+            Expr::Match(scrutinee, arms) => {
+                f.write_str("match ")?;
+                scrutinee.print(f, OPEN, indent)?;
+                f.write_str(" {\n")?;
+                for arm in arms.iter() {
+                    write!(
+                        f,
+                        "{}#{} {} => ",
+                        INDENT.repeat(indent + 1),
+                        arm.tag,
+                        arm.binding
+                    )?;
+                    arm.body.print(f, OPEN, indent + 1)?;
+                    f.write_str(",\n")?;
+                }
+                write!(f, "{}}}", INDENT.repeat(indent))
+            }
+            Expr::Let(name, value, body) => {
+                write!(f, "let {name} = ")?;
+                value.print(f, OPEN, indent)?;
+                f.write_str(" in ")?;
+                body.print(f, OPEN, indent)
+            }
+            Expr::Lambda(parameter, body) => {
+                write!(f, "\\{parameter} -> ")?;
+                body.print(f, OPEN, indent)
+            }
+            Expr::Apply(function, argument) => {
+                function.print(f, POSTFIX, indent)?;
+                f.write_str("(")?;
+                argument.print(f, OPEN, indent)?;
+                f.write_str(")")
+            }
+            Expr::List(items) => {
+                f.write_str("[")?;
+                print_arguments(f, items.iter(), indent)?;
+                f.write_str("]")
+            }
+            Expr::Map(list, function) => print_builtin(f, "map", [list, function], indent),
+            Expr::Filter(list, predicate) => print_builtin(f, "filter", [list, predicate], indent),
+            Expr::Fold(list, initial, function) => {
+                print_builtin(f, "fold", [list, initial, function], indent)
+            }
+            Expr::Len(operand) => print_builtin(f, "len", [operand], indent),
+            Expr::Concat(operands) => {
+                f.write_str("concat(")?;
+                print_arguments(f, operands.iter(), indent)?;
+                f.write_str(")")
+            }
+            Expr::Index(collection, index) => {
+                collection.print(f, POSTFIX, indent)?;
+                f.write_str("[")?;
+                index.print(f, OPEN, indent)?;
+                f.write_str("]")
+            }
+            Expr::Proj(tuple, index) => {
+                tuple.print(f, POSTFIX, indent)?;
+                write!(f, ".{index}")
+            }
+            Expr::Local(name) => name.fmt(f),
+        }
+    }
+}
+
+fn print_arguments<'a, I: Display + 'a>(
+    f: &mut Formatter,
+    arguments: impl Iterator<Item = &'a Expr<I>>,
+    indent: usize,
+) -> fmt::Result {
+    for (i, argument) in arguments.enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        argument.print(f, OPEN, indent)?;
+    }
+    Ok(())
+}
+
+fn print_builtin<I: Display, const N: usize>(
+    f: &mut Formatter,
+    name: &str,
+    arguments: [&Expr<I>; N],
+    indent: usize,
+) -> fmt::Result {
+    write!(f, "{name}(")?;
+    print_arguments(f, arguments.into_iter(), indent)?;
+    f.write_str(")")
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Tuple(items) => {
+                f.write_str("(")?;
+                if items.is_empty() {
+                    f.write_str(",")?;
+                }
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.fmt(f)?;
+                }
+                if items.len() == 1 {
+                    f.write_str(",")?;
+                }
+                f.write_str(")")
+            }
+            Value::Integer(value) => value.fmt(f),
+            Value::Boolean(value) => value.fmt(f),
+            Value::String(text) => {
+                f.write_str("\"")?;
+                for c in text.chars() {
+                    match c {
+                        '\\' => f.write_str("\\\\")?,
+                        '"' => f.write_str("\\\"")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        '\r' => f.write_str("\\r")?,
+                        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Value::Float(value) if value.is_nan() => f.write_str(if value.is_sign_negative() {
+                "-nan"
+            } else {
+                "nan"
+            }),
+            Value::Float(value) if value.is_infinite() => {
+                f.write_str(if *value > 0.0 { "inf" } else { "-inf" })
+            }
+            // Unlike `Display`, `Debug` always marks floats as such, e.g. `1.0` rather than `1`.
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Unit => f.write_str("()"),
+            Value::List(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.fmt(f)?;
+                }
+                f.write_str("]")
+            }
+            Value::Map(entries) if entries.is_empty() => f.write_str("{:}"),
+            Value::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, " {key}: {value}")?;
+                }
+                f.write_str(" }")
+            }
+            Value::Record(fields) if fields.is_empty() => f.write_str("{}"),
+            Value::Record(fields) => {
+                f.write_str("{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, " {name} = {value}")?;
+                }
+                f.write_str(" }")
+            }
+            Value::Variant(tag, payload) => {
+                write!(f, "#{tag}")?;
+                if !matches!(**payload, Value::Unit) {
+                    write!(f, "({payload})")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        actor::{Address, Version},
+        expr::{
+            parse::{parse_action, parse_upgrade, parse_value},
+            Action, BinaryOp, Expr, Ident, MatchArm, Name, UnaryOp, Upgrade, Value,
+        },
+        node::{ReactiveId, VersionedReactiveAddress},
+    };
+
+    const NAMES: &[&str] = &["x", "y", "z", "f"];
+    const TAGS: &[&str] = &["a", "b", "c"];
+    const FLOATS: &[f64] = &[
+        0.0,
+        -0.0,
+        1.5,
+        -2.25,
+        1e300,
+        1e-300,
+        f64::MIN_POSITIVE,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+    ];
+    const BINARY_OPS: &[BinaryOp] = &[
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::Rem,
+        BinaryOp::Eq,
+        BinaryOp::Ne,
+        BinaryOp::Lt,
+        BinaryOp::Le,
+        BinaryOp::Gt,
+        BinaryOp::Ge,
+        BinaryOp::And,
+        BinaryOp::Or,
+    ];
+
+    /// Generates programs that the parser accepts, from a fixed seed so that failures reproduce.
+    struct Generator {
+        state: u64,
+        /// The locals in scope.
+        locals: Vec<Name>,
+        /// The new reactives declared so far by the upgrade being generated.
+        declared: Vec<Name>,
+    }
+
+    impl Generator {
+        fn new(seed: u64) -> Generator {
+            Generator {
+                state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+                locals: Vec::new(),
+                declared: Vec::new(),
+            }
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            // xorshift64
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            (self.state % bound as u64) as usize
+        }
+
+        fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+            &items[self.below(items.len())]
+        }
+
+        fn name(&mut self) -> Name {
+            let text = *self.pick(NAMES);
+            name(text)
+        }
+
+        fn tag(&mut self) -> Name {
+            let text = *self.pick(TAGS);
+            name(text)
+        }
+
+        fn address(&mut self) -> VersionedReactiveAddress {
+            VersionedReactiveAddress {
+                address: Address::from_index(self.below(3)),
+                id: ReactiveId(self.below(3)),
+                version: Version::new(self.below(3)),
+            }
+        }
+
+        fn float(&mut self) -> f64 {
+            let value = *self.pick(FLOATS);
+            if self.below(2) == 0 {
+                -value
+            } else {
+                value
+            }
+        }
+
+        fn string(&mut self) -> String {
+            [
+                "",
+                "plain",
+                "with \"quotes\"",
+                "back\\slash",
+                "line\nbreak",
+                "tab\t\u{1}",
+            ][self.below(6)]
+            .to_owned()
+        }
+
+        /// A value nested at most `depth` levels deep.
+        fn value(&mut self, depth: usize) -> Value {
+            let kind = self.below(if depth == 0 { 5 } else { 10 });
+            self.value_of(kind, depth)
+        }
+
+        /// A value of the given kind, so that the items of lists can be kept to one type.
+        fn value_of(&mut self, kind: usize, depth: usize) -> Value {
+            match kind {
+                0 => Value::Integer([0, 7, -7, isize::MAX, isize::MIN][self.below(5)]),
+                1 => Value::Float(self.float()),
+                2 => Value::String(self.string()),
+                3 => Value::Boolean(self.below(2) == 0),
+                4 => Value::Unit,
+                5 => {
+                    let kind = self.below(5);
+                    let len = self.below(3);
+                    Value::List((0..len).map(|_| self.value_of(kind, 0)).collect())
+                }
+                6 => {
+                    let len = self.below(3);
+                    Value::Map(
+                        (0..len)
+                            .map(|_| (self.value_of(0, 0), self.value_of(2, 0)))
+                            .collect::<BTreeMap<_, _>>(),
+                    )
+                }
+                7 => {
+                    let len = self.below(3);
+                    let fields = NAMES[..len].iter().map(|field| name(field));
+                    Value::Record(fields.map(|field| (field, self.value(depth - 1))).collect())
+                }
+                8 => {
+                    let tag = self.tag();
+                    Value::Variant(tag, Box::new(self.value(depth - 1)))
+                }
+                _ => {
+                    let len = self.below(4);
+                    Value::Tuple((0..len).map(|_| self.value(depth - 1)).collect())
+                }
+            }
+        }
+
+        fn exprs<I>(
+            &mut self,
+            len: usize,
+            depth: usize,
+            read: &mut impl FnMut(&mut Self) -> Option<I>,
+        ) -> Box<[Expr<I>]> {
+            (0..len).map(|_| self.expr(depth - 1, read)).collect()
+        }
+
+        fn boxed<I>(
+            &mut self,
+            depth: usize,
+            read: &mut impl FnMut(&mut Self) -> Option<I>,
+        ) -> Box<Expr<I>> {
+            Box::new(self.expr(depth - 1, read))
+        }
+
+        /// Binds `name` while generating the expression that `generate` makes.
+        fn scoped<T>(&mut self, name: &Name, generate: impl FnOnce(&mut Self) -> T) -> T {
+            self.locals.push(name.clone());
+            let result = generate(self);
+            self.locals.pop();
+            result
+        }
+
+        /// An expression whose reads of reactives are made by `read`, when it makes any.
+        fn expr<I>(
+            &mut self,
+            depth: usize,
+            read: &mut impl FnMut(&mut Self) -> Option<I>,
+        ) -> Expr<I> {
+            if depth == 0 {
+                return match self.below(3) {
+                    0 if !self.locals.is_empty() => {
+                        let local = self.pick(&self.locals.clone()).clone();
+                        Expr::Local(local)
+                    }
+                    1 => match read(self) {
+                        Some(ident) => Expr::Read(ident),
+                        None => Expr::Value(self.value(0)),
+                    },
+                    _ => Expr::Value(self.value(0)),
+                };
+            }
+
+            match self.below(21) {
+                0 => {
+                    let len = [0, 1, 2, 3][self.below(4)];
+                    Expr::Tuple(self.exprs(len, depth, read))
+                }
+                1 => Expr::Value(self.value(2)),
+                2 => {
+                    let op = *self.pick(&[UnaryOp::Neg, UnaryOp::Not]);
+                    Expr::Unary(op, self.boxed(depth, read))
+                }
+                3..=5 => {
+                    let op = *self.pick(BINARY_OPS);
+                    Expr::Binary(op, self.boxed(depth, read), self.boxed(depth, read))
+                }
+                6 => Expr::If(
+                    self.boxed(depth, read),
+                    self.boxed(depth, read),
+                    self.boxed(depth, read),
+                ),
+                7 => {
+                    let len = self.below(3);
+                    let fields = NAMES[..len].iter().map(|field| name(field));
+                    Expr::Record(
+                        fields
+                            .map(|field| (field, self.expr(depth - 1, read)))
+                            .collect(),
+                    )
+                }
+                8 => Expr::Field(self.boxed(depth, read), self.name()),
+                9 => {
+                    let tag = self.tag();
+                    Expr::Variant(tag, self.boxed(depth, read))
+                }
+                10 => {
+                    let scrutinee = self.boxed(depth, read);
+                    let len = 1 + self.below(TAGS.len());
+                    let arms = TAGS[..len]
+                        .iter()
+                        .map(|tag| {
+                            let binding = self.name();
+                            let body = self.scoped(&binding, |g| g.expr(depth - 1, read));
+                            MatchArm {
+                                tag: name(tag),
+                                binding,
+                                body,
+                            }
+                        })
+                        .collect();
+                    Expr::Match(scrutinee, arms)
+                }
+                11 => {
+                    let local = self.name();
+                    let value = self.boxed(depth, read);
+                    let body = self.scoped(&local, |g| g.boxed(depth, read));
+                    Expr::Let(local, value, body)
+                }
+                12 => {
+                    let parameter = self.name();
+                    let body = self.scoped(&parameter, |g| g.boxed(depth, read));
+                    Expr::Lambda(parameter, body)
+                }
+                13 => Expr::Apply(self.boxed(depth, read), self.boxed(depth, read)),
+                14 => {
+                    let len = self.below(3);
+                    Expr::List(self.exprs(len, depth, read))
+                }
+                15 => Expr::Map(self.boxed(depth, read), self.boxed(depth, read)),
+                16 => Expr::Filter(self.boxed(depth, read), self.boxed(depth, read)),
+                17 => Expr::Fold(
+                    self.boxed(depth, read),
+                    self.boxed(depth, read),
+                    self.boxed(depth, read),
+                ),
+                18 => {
+                    let len = self.below(3);
+                    if len == 1 {
+                        Expr::Len(self.boxed(depth, read))
+                    } else {
+                        Expr::Concat(self.exprs(len, depth, read))
+                    }
+                }
+                19 => Expr::Index(self.boxed(depth, read), self.boxed(depth, read)),
+                _ => Expr::Proj(self.boxed(depth, read), self.below(3)),
+            }
+        }
+
+        fn action(&mut self, depth: usize) -> Action {
+            let mut read = |g: &mut Self| Some(g.address());
+            match self.below(if depth == 0 { 2 } else { 7 }) {
+                0 => Action::Nil,
+                1 => Action::Write(self.address(), self.expr(2, &mut read)),
+                2 | 3 => Action::Seq(
+                    Box::new(self.action(depth - 1)),
+                    Box::new(self.action(depth - 1)),
+                ),
+                4 => Action::If(
+                    self.expr(2, &mut read),
+                    Box::new(self.action(depth - 1)),
+                    Box::new(self.action(depth - 1)),
+                ),
+                5 => Action::While(
+                    self.expr(2, &mut read),
+                    Box::new(self.action(depth - 1)),
+                    self.below(100),
+                ),
+                _ => {
+                    let local = self.name();
+                    let value = self.expr(2, &mut read);
+                    let body = self.scoped(&local, |g| g.action(depth - 1));
+                    Action::Let(local, value, Box::new(body))
+                }
+            }
+        }
+
+        fn upgrade(&mut self, depth: usize) -> Upgrade {
+            let mut read = |g: &mut Self| {
+                if !g.declared.is_empty() && g.below(2) == 0 {
+                    let name = g.pick(&g.declared.clone()).clone();
+                    Some(Ident::New(name))
+                } else {
+                    Some(Ident::Existing(g.address()))
+                }
+            };
+            match self.below(if depth == 0 { 5 } else { 7 }) {
+                0 => Upgrade::Nil,
+                1 | 2 => {
+                    let expr = self.expr(2, &mut read);
+                    let fresh = NAMES
+                        .iter()
+                        .map(|text| name(text))
+                        .find(|name| !self.declared.contains(name));
+                    let target = match fresh {
+                        Some(name) if self.below(2) == 0 => {
+                            self.declared.push(name.clone());
+                            Ident::New(name)
+                        }
+                        _ => Ident::Existing(self.address()),
+                    };
+                    if self.below(2) == 0 {
+                        Upgrade::Var(target, expr)
+                    } else {
+                        Upgrade::Def(target, expr)
+                    }
+                }
+                3 => Upgrade::Del(self.address()),
+                4 => Upgrade::Migrate(self.address(), self.expr(2, &mut read)),
+                _ => {
+                    let first = Box::new(self.upgrade(depth - 1));
+                    Upgrade::Seq(first, Box::new(self.upgrade(depth - 1)))
+                }
+            }
+        }
+    }
+
+    fn name(text: &str) -> Name {
+        Name {
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn values_print_as_they_parse() {
+        for source in [
+            "(1,)",
+            "(-1, true, ())",
+            r#""a \"quoted\"\nline""#,
+            "[1.0, -2.5, inf, nan]",
+            r#"{ [1]: "one", [1, 2]: "two" }"#,
+            "{:}",
+            "{ x = 1, y = #some(2) }",
+            "#none",
+        ] {
+            let value = parse_value(source).unwrap();
+            assert_eq!(value.to_string(), source);
+        }
+    }
+
+    #[test]
+    fn expressions_print_with_only_the_parentheses_they_need() {
+        let source = r"var x = 1;
+def y = ($x + 2) * -$x - (1 - $x);
+def z = if $x < 2 then \f -> f($x) else \f -> f(0);
+def w = match #some(($x, 0)) {
+    #none _ => 0,
+    #some n => n,
+}.0";
+        let printed = parse_upgrade(source, |_| None).unwrap().to_string();
+        assert_eq!(printed, source);
+    }
+
+    #[test]
+    fn printed_programs_parse_back_as_they_were() {
+        for seed in 0..2000 {
+            let mut generator = Generator::new(seed);
+            let value = generator.value(3);
+            let printed = value.to_string();
+            assert_eq!(parse_value(&printed), Ok(value), "seed {seed}: {printed}");
+
+            let action = generator.action(3);
+            let printed = action.to_string();
+            assert_eq!(
+                parse_action(&printed, |_| None),
+                Ok(action),
+                "seed {seed}:\n{printed}"
+            );
+
+            let upgrade = generator.upgrade(3);
+            let printed = upgrade.to_string();
+            assert_eq!(
+                parse_upgrade(&printed, |_| None),
+                Ok(upgrade),
+                "seed {seed}:\n{printed}"
+            );
+        }
+    }
+
+    #[test]
+    fn printing_keeps_what_parsing_would_lose() {
+        let x = name("x");
+        let read = Expr::Read(Ident::New(x.clone()));
+        let upgrade = Upgrade::Seq(
+            Box::new(Upgrade::Seq(
+                Box::new(Upgrade::Var(
+                    Ident::New(x.clone()),
+                    Expr::Value(Value::Float(-f64::NAN)),
+                )),
+                Box::new(Upgrade::Nil),
+            )),
+            Box::new(Upgrade::Def(
+                Ident::New(name("y")),
+                Expr::Let(x.clone(), Box::new(read.clone()), Box::new(read)),
+            )),
+        );
+        let printed = upgrade.to_string();
+        assert_eq!(
+            printed,
+            "{\n    var x = -nan;\n    {}\n};\ndef y = let x = $x in $x"
+        );
+        assert_eq!(parse_upgrade(&printed, |_| None), Ok(upgrade));
+        assert_eq!(Upgrade::Nil.to_string(), "{}");
+    }
+}
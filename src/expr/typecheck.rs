@@ -0,0 +1,1181 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::node::VersionedReactiveAddress;
+
+use super::{
+    parse::{self, Spans},
+    Action, BinaryOp, Expr, Ident, Name, Type, UnaryOp, Upgrade,
+};
+
+/// The position of a node within a checked upgrade or action, given by the indices of the
+/// children taken from the root. The children of a node are numbered in the order in which its
+/// subexpressions (or substatements) appear in its variant, e.g. `[1, 0]` is the first child of
+/// the second child of the root.
+pub type Location = Vec<usize>;
+
+/// How deeply applications may nest while checking. Lambdas are checked anew at each application,
+/// so checking one that keeps applying itself would otherwise never end.
+const APPLICATION_DEPTH_LIMIT: usize = 64;
+
+/// How many times the accumulator of a fold may be widened to the type its step produces.
+const FOLD_WIDENING_LIMIT: usize = 16;
+
+/// How many subexpressions may be checked in total. Lambdas applied to each other can take
+/// exponentially many applications to check without nesting deeply or repeating an application,
+/// as in `t(t)(t)(t)` where `t = \f -> \x -> f(f(x))`.
+const WORK_LIMIT: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub location: Location,
+    pub kind: TypeErrorKind,
+}
+
+impl TypeError {
+    /// The span of the source the error is about, given the spans recorded while parsing it.
+    pub fn span(&self, spans: &Spans) -> Range<usize> {
+        spans.get(&self.location)
+    }
+
+    /// Formats the error with the line of the source it is about, underlining its span.
+    pub fn render(&self, source: &str, spans: &Spans) -> String {
+        parse::render(source, &self.span(spans), &format!("{:?}", self.kind))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeErrorKind {
+    Mismatch {
+        expected: Type,
+        found: Type,
+    },
+    /// An operation was applied to a value of a type it does not support.
+    Unsupported {
+        operation: &'static str,
+        found: Type,
+    },
+    UnknownReactive(Ident),
+    UnboundLocal(Name),
+    /// A lambda was found where a value was expected.
+    ExpectedValue,
+    /// A value was applied as if it were a lambda.
+    ExpectedFunction(Type),
+    /// A lambda is applied within its own application without end, as in
+    /// `(\x -> x(x))(\x -> x(x))`, or applications nest more deeply than checking allows.
+    RecursiveFunction,
+    /// Checking takes more work than allowed, as for lambdas that are applied to each other so
+    /// often that checking each application anew would take too long.
+    TooComplex,
+    /// A list or map literal holds items of different types.
+    MixedCollection,
+    MissingField(Name),
+    DuplicateField(Name),
+    UnknownTag(Name),
+    DuplicateArm(Name),
+    /// A match lacks arms for the given tags of its scrutinee's type.
+    NonExhaustive(Vec<Name>),
+    ProjectionOutOfBounds {
+        index: usize,
+        len: usize,
+    },
+    /// A migration reads a reactive other than the one it migrates. Migrations are evaluated
+    /// where the migrated reactive lives, with only its own value at hand.
+    MigrationRead(Ident),
+    /// The reactive changed or deleted by the statement is read by a live reactive the upgrade
+    /// leaves as it is, which would no longer be well-typed.
+    BrokenReader {
+        reader: VersionedReactiveAddress,
+        error: Box<TypeErrorKind>,
+    },
+}
+
+impl Upgrade {
+    /// Checks that this upgrade is well-typed, given the declared type of each live reactive and
+    /// the definitions of the live reactives reading each reactive.
+    ///
+    /// New reactives can only be referred to after the statement defining them, and reactives
+    /// redefined, migrated or deleted by earlier statements are resolved accordingly. Reactives
+    /// that read one whose type the upgrade changes, or that it deletes, are checked again
+    /// against the outcome of the upgrade, be they defined by the upgrade or left as they are.
+    ///
+    /// Returns the type that each reactive the upgrade declares, migrates or deletes is left
+    /// with, or `None` for those it deletes.
+    pub fn check_types(
+        &self,
+        reactives: impl Fn(&VersionedReactiveAddress) -> Option<Type>,
+        readers: impl Fn(&VersionedReactiveAddress) -> Vec<(VersionedReactiveAddress, Expr<Ident>)>,
+    ) -> Result<HashMap<Ident, Option<Type>>, TypeError> {
+        let mut declared = HashMap::new();
+        let mut changes = Changes::default();
+        self.check_types_at(&Vec::new(), &mut declared, &mut changes, &reactives)?;
+
+        let checker = upgrade_checker(&declared, &reactives);
+        for (location, (ident, expr)) in &changes.definitions {
+            let Some(Some(ty)) = declared.get(ident) else {
+                unreachable!("definitions are forgotten once their reactive is redeclared");
+            };
+
+            let location = child(location, 0);
+            let found = checker.infer_value(expr, &location, &Locals::new())?;
+            conform(&found, ty, &location)?;
+        }
+
+        let mut changed = changes
+            .statements
+            .iter()
+            .filter_map(|(ident, location)| match ident {
+                Ident::Existing(address) if declared[ident] != reactives(address) => {
+                    Some((location, address))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        changed.sort_by_key(|(location, _)| *location);
+
+        for (location, address) in changed {
+            for (reader, expr) in readers(address) {
+                // Readers that the upgrade redefines or deletes have been dealt with already.
+                if declared.contains_key(&Ident::Existing(reader.clone())) {
+                    continue;
+                }
+
+                let broken = |error: TypeError| TypeError {
+                    location: location.clone(),
+                    kind: TypeErrorKind::BrokenReader {
+                        reader: reader.clone(),
+                        error: Box::new(error.kind),
+                    },
+                };
+
+                let found = checker
+                    .infer_value(&expr, &Vec::new(), &Locals::new())
+                    .map_err(broken)?;
+                if let Some(ty) = reactives(&reader) {
+                    conform(&found, &ty, &Vec::new()).map_err(broken)?;
+                }
+            }
+        }
+
+        drop(checker);
+        Ok(declared)
+    }
+
+    fn check_types_at<'u>(
+        &'u self,
+        location: &Location,
+        declared: &mut HashMap<Ident, Option<Type>>,
+        changes: &mut Changes<'u>,
+        reactives: &dyn Fn(&VersionedReactiveAddress) -> Option<Type>,
+    ) -> Result<(), TypeError> {
+        match self {
+            Upgrade::Seq(a, b) => {
+                a.check_types_at(&child(location, 0), declared, changes, reactives)?;
+                b.check_types_at(&child(location, 1), declared, changes, reactives)?;
+            }
+            Upgrade::Var(ident, expr) | Upgrade::Def(ident, expr) => {
+                let ty = upgrade_checker(declared, reactives).infer_value(
+                    expr,
+                    &child(location, 0),
+                    &Locals::new(),
+                )?;
+                declared.insert(ident.clone(), Some(ty));
+                changes.record(ident, location, Some(expr));
+            }
+            Upgrade::Migrate(address, expr) => {
+                let mut foreign = None;
+                expr.visit_reads(&mut |ident, _| match ident {
+                    Ident::Existing(read)
+                        if read.address == address.address && read.id == address.id => {}
+                    _ => foreign = foreign.take().or_else(|| Some(ident.clone())),
+                });
+                if let Some(ident) = foreign {
+                    return Err(TypeError {
+                        location: child(location, 0),
+                        kind: TypeErrorKind::MigrationRead(ident),
+                    });
+                }
+
+                let ty = upgrade_checker(declared, reactives).infer_value(
+                    expr,
+                    &child(location, 0),
+                    &Locals::new(),
+                )?;
+                let ident = Ident::Existing(address.clone());
+                declared.insert(ident.clone(), Some(ty));
+                changes.record(&ident, location, None);
+            }
+            Upgrade::Del(address) => {
+                let ident = Ident::Existing(address.clone());
+                if (upgrade_checker(declared, reactives).read)(&ident).is_none() {
+                    return Err(TypeError {
+                        location: location.clone(),
+                        kind: TypeErrorKind::UnknownReactive(ident),
+                    });
+                }
+
+                declared.insert(ident.clone(), None);
+                changes.record(&ident, location, None);
+            }
+            Upgrade::Nil => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// What the statements of an upgrade checked so far have done to its reactives.
+#[derive(Default)]
+struct Changes<'u> {
+    /// The location of the statement that last declared, migrated or deleted each reactive.
+    statements: HashMap<Ident, Location>,
+    /// The definitions that reactives are left with by the statements so far, by location.
+    definitions: BTreeMap<Location, (Ident, &'u Expr<Ident>)>,
+}
+
+impl<'u> Changes<'u> {
+    fn record(&mut self, ident: &Ident, location: &Location, definition: Option<&'u Expr<Ident>>) {
+        self.statements.insert(ident.clone(), location.clone());
+        self.definitions.retain(|_, (defined, _)| defined != ident);
+        if let Some(expr) = definition {
+            self.definitions
+                .insert(location.clone(), (ident.clone(), expr));
+        }
+    }
+}
+
+fn upgrade_checker<'a>(
+    declared: &'a HashMap<Ident, Option<Type>>,
+    reactives: &'a dyn Fn(&VersionedReactiveAddress) -> Option<Type>,
+) -> Checker<'a, Ident> {
+    Checker {
+        read: Box::new(move |ident| match declared.get(ident) {
+            Some(ty) => ty.clone(),
+            None => match ident {
+                Ident::Existing(address) => reactives(address),
+                Ident::New(_) => None,
+            },
+        }),
+        applications: RefCell::new(Vec::new()),
+        work: Cell::new(0),
+    }
+}
+
+impl Action {
+    /// Checks that this action is well-typed, given the declared type of each live reactive.
+    pub fn check_types(
+        &self,
+        reactives: impl Fn(&VersionedReactiveAddress) -> Option<Type>,
+    ) -> Result<(), TypeError> {
+        let checker = Checker {
+            read: Box::new(&reactives),
+            applications: RefCell::new(Vec::new()),
+            work: Cell::new(0),
+        };
+
+        self.check_types_at(&Vec::new(), &checker, &Locals::new())
+    }
+
+    fn check_types_at<'e>(
+        &'e self,
+        location: &Location,
+        checker: &Checker<VersionedReactiveAddress>,
+        locals: &Locals<'e, VersionedReactiveAddress>,
+    ) -> Result<(), TypeError> {
+        match self {
+            Action::Seq(a, b) => {
+                a.check_types_at(&child(location, 0), checker, locals)?;
+                b.check_types_at(&child(location, 1), checker, locals)?;
+            }
+            Action::Write(address, expr) => {
+                let Some(declared) = (checker.read)(address) else {
+                    return Err(TypeError {
+                        location: location.clone(),
+                        kind: TypeErrorKind::UnknownReactive(address.clone().into()),
+                    });
+                };
+
+                let location = child(location, 0);
+                let ty = checker.infer_value(expr, &location, locals)?;
+                conform(&ty, &declared, &location)?;
+            }
+            Action::If(cond, then, otherwise) => {
+                let cond_location = child(location, 0);
+                let cond = checker.infer_value(cond, &cond_location, locals)?;
+                unify(&Type::Boolean, &cond, &cond_location)?;
+
+                then.check_types_at(&child(location, 1), checker, locals)?;
+                otherwise.check_types_at(&child(location, 2), checker, locals)?;
+            }
+            Action::While(cond, body, _) => {
+                let cond_location = child(location, 0);
+                let cond = checker.infer_value(cond, &cond_location, locals)?;
+                unify(&Type::Boolean, &cond, &cond_location)?;
+
+                body.check_types_at(&child(location, 1), checker, locals)?;
+            }
+            Action::Let(name, bound, body) => {
+                let bound = checker.infer(bound, &child(location, 0), locals)?;
+
+                let mut locals = locals.clone();
+                locals.insert(name, bound);
+
+                body.check_types_at(&child(location, 1), checker, &locals)?;
+            }
+            Action::Nil => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of checking an expression. Lambdas are not values, so instead of having a type
+/// they are checked anew at each application, with the type of the argument at hand.
+#[derive(Clone)]
+enum Typed<'e, I> {
+    Value(Type),
+    Function(Closure<'e, I>),
+}
+
+#[derive(Clone)]
+struct Closure<'e, I> {
+    param: &'e Name,
+    body: &'e Expr<I>,
+    location: Location,
+    locals: Locals<'e, I>,
+}
+
+type Locals<'e, I> = HashMap<&'e Name, Typed<'e, I>>;
+
+/// Resolves the type of the reactive read through an identifier, if it exists.
+type Resolver<'a, I> = Box<dyn Fn(&I) -> Option<Type> + 'a>;
+
+struct Checker<'a, I> {
+    read: Resolver<'a, I>,
+    /// The applications being checked, innermost last, given by the signatures of the function
+    /// and of the argument.
+    applications: RefCell<Vec<(Signature, Signature)>>,
+    /// How many subexpressions have been checked so far.
+    work: Cell<usize>,
+}
+
+/// What checking a typed expression depends on: the type of a value, or the code of a closure,
+/// given by the location of its body, and the locals it captured. An application whose function
+/// and argument have the same signatures as one it is nested in would be checked the same way
+/// again, forever.
+#[derive(PartialEq)]
+enum Signature {
+    Value(Type),
+    Function(Location, Vec<(Name, Signature)>),
+}
+
+impl<I> Typed<'_, I> {
+    fn signature(&self) -> Signature {
+        match self {
+            Typed::Value(ty) => Signature::Value(ty.clone()),
+            Typed::Function(closure) => {
+                let mut locals = closure
+                    .locals
+                    .iter()
+                    .map(|(&name, typed)| (name.clone(), typed.signature()))
+                    .collect::<Vec<_>>();
+                locals.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                Signature::Function(closure.location.clone(), locals)
+            }
+        }
+    }
+}
+
+impl<'a, I: Clone + Into<Ident>> Checker<'a, I> {
+    fn infer_value<'e>(
+        &self,
+        expr: &'e Expr<I>,
+        location: &Location,
+        locals: &Locals<'e, I>,
+    ) -> Result<Type, TypeError> {
+        let typed = self.infer(expr, location, locals)?;
+        value_type(typed, location)
+    }
+
+    fn infer<'e>(
+        &self,
+        expr: &'e Expr<I>,
+        location: &Location,
+        locals: &Locals<'e, I>,
+    ) -> Result<Typed<'e, I>, TypeError> {
+        let error = |kind| {
+            Err(TypeError {
+                location: location.clone(),
+                kind,
+            })
+        };
+
+        self.work.set(self.work.get() + 1);
+        if self.work.get() > WORK_LIMIT {
+            return error(TypeErrorKind::TooComplex);
+        }
+
+        let ty = match expr {
+            Expr::Tuple(items) => Type::Tuple(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| self.infer_value(item, &child(location, i), locals))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Read(ident) => match (self.read)(ident) {
+                Some(ty) => ty,
+                None => return error(TypeErrorKind::UnknownReactive(ident.clone().into())),
+            },
+            Expr::Value(value) => match value.compute_type() {
+                Some(ty) => ty,
+                None => return error(TypeErrorKind::MixedCollection),
+            },
+            Expr::Unary(op, operand) => {
+                let location = child(location, 0);
+                let ty = self.infer_value(operand, &location, locals)?;
+
+                match op {
+                    UnaryOp::Neg => supported("negation", ty, is_numeric, &location)?,
+                    UnaryOp::Not => unify(&Type::Boolean, &ty, &location)?,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs_location = child(location, 0);
+                let rhs_location = child(location, 1);
+                let lhs = self.infer_value(lhs, &lhs_location, locals)?;
+                let rhs = self.infer_value(rhs, &rhs_location, locals)?;
+
+                match op {
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Rem => {
+                        let ty = unify(&lhs, &rhs, &rhs_location)?;
+                        supported("arithmetic", ty, is_numeric, location)?
+                    }
+                    BinaryOp::Eq
+                    | BinaryOp::Ne
+                    | BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge => {
+                        unify(&lhs, &rhs, &rhs_location)?;
+                        Type::Boolean
+                    }
+                    BinaryOp::And | BinaryOp::Or => {
+                        unify(&Type::Boolean, &lhs, &lhs_location)?;
+                        unify(&Type::Boolean, &rhs, &rhs_location)?
+                    }
+                }
+            }
+            Expr::If(cond, then, otherwise) => {
+                let cond_location = child(location, 0);
+                let cond = self.infer_value(cond, &cond_location, locals)?;
+                unify(&Type::Boolean, &cond, &cond_location)?;
+
+                let then = self.infer_value(then, &child(location, 1), locals)?;
+                let otherwise_location = child(location, 2);
+                let otherwise = self.infer_value(otherwise, &otherwise_location, locals)?;
+                unify(&then, &otherwise, &otherwise_location)?
+            }
+            Expr::Record(fields) => {
+                let mut types = BTreeMap::new();
+                for (i, (name, field)) in fields.iter().enumerate() {
+                    let location = child(location, i);
+                    let ty = self.infer_value(field, &location, locals)?;
+
+                    if types.insert(name.clone(), ty).is_some() {
+                        return Err(TypeError {
+                            location,
+                            kind: TypeErrorKind::DuplicateField(name.clone()),
+                        });
+                    }
+                }
+
+                Type::Record(types)
+            }
+            Expr::Field(record, name) => {
+                match self.infer_value(record, &child(location, 0), locals)? {
+                    Type::Record(mut fields) => match fields.remove(name) {
+                        Some(ty) => ty,
+                        None => return error(TypeErrorKind::MissingField(name.clone())),
+                    },
+                    Type::Never => Type::Never,
+                    ty => return unsupported("field access", ty, location),
+                }
+            }
+            Expr::Variant(tag, payload) => {
+                let payload = self.infer_value(payload, &child(location, 0), locals)?;
+                Type::Variant(BTreeMap::from([(tag.clone(), payload)]))
+            }
+            Expr::Match(scrutinee, arms) => {
+                let scrutinee = self.infer_value(scrutinee, &child(location, 0), locals)?;
+                let tags = match &scrutinee {
+                    Type::Variant(tags) => tags.clone(),
+                    Type::Never => BTreeMap::new(),
+                    ty => return unsupported("match", ty.clone(), location),
+                };
+
+                let mut covered = HashSet::new();
+                let mut ty = Type::Never;
+
+                for (i, arm) in arms.iter().enumerate() {
+                    let location = child(location, i + 1);
+                    let arm_error = |kind| TypeError {
+                        location: location.clone(),
+                        kind,
+                    };
+
+                    if !covered.insert(&arm.tag) {
+                        return Err(arm_error(TypeErrorKind::DuplicateArm(arm.tag.clone())));
+                    }
+
+                    let payload = match tags.get(&arm.tag) {
+                        Some(payload) => payload.clone(),
+                        None if scrutinee == Type::Never => Type::Never,
+                        None => return Err(arm_error(TypeErrorKind::UnknownTag(arm.tag.clone()))),
+                    };
+
+                    let mut locals = locals.clone();
+                    locals.insert(&arm.binding, Typed::Value(payload));
+
+                    let body = self.infer_value(&arm.body, &location, &locals)?;
+                    ty = unify(&ty, &body, &location)?;
+                }
+
+                let uncovered = scrutinee.uncovered_tags(covered);
+                if !uncovered.is_empty() {
+                    return error(TypeErrorKind::NonExhaustive(uncovered));
+                }
+
+                ty
+            }
+            Expr::Let(name, bound, body) => {
+                let bound = self.infer(bound, &child(location, 0), locals)?;
+
+                let mut locals = locals.clone();
+                locals.insert(name, bound);
+
+                return self.infer(body, &child(location, 1), &locals);
+            }
+            Expr::Lambda(param, body) => {
+                return Ok(Typed::Function(Closure {
+                    param,
+                    body,
+                    location: child(location, 0),
+                    locals: locals.clone(),
+                }))
+            }
+            Expr::Apply(function, argument) => {
+                let function = self.infer(function, &child(location, 0), locals)?;
+                let argument = self.infer(argument, &child(location, 1), locals)?;
+
+                return self.apply(function, argument, location);
+            }
+            Expr::Local(name) => match locals.get(name) {
+                Some(typed) => return Ok(typed.clone()),
+                None => return error(TypeErrorKind::UnboundLocal(name.clone())),
+            },
+            Expr::List(items) => {
+                let mut ty = Type::Never;
+                for (i, item) in items.iter().enumerate() {
+                    let location = child(location, i);
+                    let item = self.infer_value(item, &location, locals)?;
+                    ty = unify(&ty, &item, &location)?;
+                }
+
+                Type::List(Box::new(ty))
+            }
+            Expr::Map(list, function) => {
+                let item = self.list_item("map", list, &child(location, 0), locals)?;
+                let function = self.infer(function, &child(location, 1), locals)?;
+                let mapped = self.apply(function, Typed::Value(item), location)?;
+
+                Type::List(Box::new(value_type(mapped, location)?))
+            }
+            Expr::Filter(list, predicate) => {
+                let item = self.list_item("filter", list, &child(location, 0), locals)?;
+                let predicate = self.infer(predicate, &child(location, 1), locals)?;
+                let keep = self.apply(predicate, Typed::Value(item.clone()), location)?;
+                unify(&Type::Boolean, &value_type(keep, location)?, location)?;
+
+                Type::List(Box::new(item))
+            }
+            Expr::Fold(list, accumulator, function) => {
+                let item = self.list_item("fold", list, &child(location, 0), locals)?;
+                let mut ty = self.infer_value(accumulator, &child(location, 1), locals)?;
+                let function = self.infer(function, &child(location, 2), locals)?;
+
+                // The accumulator may be of a more specific type than the steps produce, e.g.
+                // when it starts as an empty list, so it is widened until the step preserves it.
+                // A step that wraps the accumulator, as in `\acc -> \x -> [acc]`, would widen it
+                // forever, so it only gets so many tries.
+                let mut widenings = 0;
+                loop {
+                    let partial =
+                        self.apply(function.clone(), Typed::Value(ty.clone()), location)?;
+                    let step = self.apply(partial, Typed::Value(item.clone()), location)?;
+                    let step = value_type(step, location)?;
+                    let widened = unify(&ty, &step, location)?;
+
+                    if widened == ty {
+                        break ty;
+                    }
+
+                    widenings += 1;
+                    if widenings == FOLD_WIDENING_LIMIT {
+                        return error(TypeErrorKind::Mismatch {
+                            expected: ty,
+                            found: step,
+                        });
+                    }
+
+                    ty = widened;
+                }
+            }
+            Expr::Len(collection) => {
+                let location = child(location, 0);
+                let ty = self.infer_value(collection, &location, locals)?;
+                supported(
+                    "length",
+                    ty,
+                    |ty| {
+                        matches!(
+                            ty,
+                            Type::List(_) | Type::Map(..) | Type::String | Type::Never
+                        )
+                    },
+                    &location,
+                )?;
+
+                Type::Integer
+            }
+            Expr::Index(collection, key) => {
+                let collection = self.infer_value(collection, &child(location, 0), locals)?;
+                let key_location = child(location, 1);
+                let key = self.infer_value(key, &key_location, locals)?;
+
+                match collection {
+                    Type::List(item) => {
+                        unify(&Type::Integer, &key, &key_location)?;
+                        *item
+                    }
+                    Type::Map(key_type, value) => {
+                        unify(&key_type, &key, &key_location)?;
+                        *value
+                    }
+                    Type::Never => Type::Never,
+                    ty => return unsupported("indexing", ty, location),
+                }
+            }
+            Expr::Concat(parts) => {
+                let mut ty = Type::Never;
+                for (i, part) in parts.iter().enumerate() {
+                    let location = child(location, i);
+                    let part = self.infer_value(part, &location, locals)?;
+                    let part = supported(
+                        "concatenation",
+                        part,
+                        |ty| matches!(ty, Type::List(_) | Type::String | Type::Never),
+                        &location,
+                    )?;
+                    ty = unify(&ty, &part, &location)?;
+                }
+
+                match ty {
+                    Type::Never => Type::List(Box::new(Type::Never)),
+                    ty => ty,
+                }
+            }
+            Expr::Proj(tuple, index) => {
+                match self.infer_value(tuple, &child(location, 0), locals)? {
+                    Type::Tuple(items) => match items.get(*index) {
+                        Some(ty) => ty.clone(),
+                        None => {
+                            return error(TypeErrorKind::ProjectionOutOfBounds {
+                                index: *index,
+                                len: items.len(),
+                            })
+                        }
+                    },
+                    Type::Never => Type::Never,
+                    ty => return unsupported("projection", ty, location),
+                }
+            }
+        };
+
+        Ok(Typed::Value(ty))
+    }
+
+    fn apply<'e>(
+        &self,
+        function: Typed<'e, I>,
+        argument: Typed<'e, I>,
+        location: &Location,
+    ) -> Result<Typed<'e, I>, TypeError> {
+        let error = |kind| {
+            Err(TypeError {
+                location: location.clone(),
+                kind,
+            })
+        };
+
+        let application = (function.signature(), argument.signature());
+        let closure = match function {
+            Typed::Function(closure) => closure,
+            Typed::Value(ty) => return error(TypeErrorKind::ExpectedFunction(ty)),
+        };
+
+        let applications = self.applications.borrow();
+        if applications.len() >= APPLICATION_DEPTH_LIMIT || applications.contains(&application) {
+            return error(TypeErrorKind::RecursiveFunction);
+        }
+        drop(applications);
+
+        let mut locals = closure.locals;
+        locals.insert(closure.param, argument);
+
+        self.applications.borrow_mut().push(application);
+        let result = self.infer(closure.body, &closure.location, &locals);
+        self.applications.borrow_mut().pop();
+
+        result
+    }
+
+    fn list_item<'e>(
+        &self,
+        operation: &'static str,
+        list: &'e Expr<I>,
+        location: &Location,
+        locals: &Locals<'e, I>,
+    ) -> Result<Type, TypeError> {
+        match self.infer_value(list, location, locals)? {
+            Type::List(item) => Ok(*item),
+            Type::Never => Ok(Type::Never),
+            ty => unsupported(operation, ty, location),
+        }
+    }
+}
+
+fn child(location: &Location, index: usize) -> Location {
+    let mut location = location.clone();
+    location.push(index);
+    location
+}
+
+fn value_type<I>(typed: Typed<I>, location: &Location) -> Result<Type, TypeError> {
+    match typed {
+        Typed::Value(ty) => Ok(ty),
+        Typed::Function(_) => Err(TypeError {
+            location: location.clone(),
+            kind: TypeErrorKind::ExpectedValue,
+        }),
+    }
+}
+
+fn unify(expected: &Type, found: &Type, location: &Location) -> Result<Type, TypeError> {
+    expected.unify(found).ok_or_else(|| TypeError {
+        location: location.clone(),
+        kind: TypeErrorKind::Mismatch {
+            expected: expected.clone(),
+            found: found.clone(),
+        },
+    })
+}
+
+/// Checks that a value of type `found` can be held by a reactive declared to be of type
+/// `declared`, which, unlike unification, does not widen the declared type.
+fn conform(found: &Type, declared: &Type, location: &Location) -> Result<(), TypeError> {
+    if found.conforms_to(declared) {
+        Ok(())
+    } else {
+        Err(TypeError {
+            location: location.clone(),
+            kind: TypeErrorKind::Mismatch {
+                expected: declared.clone(),
+                found: found.clone(),
+            },
+        })
+    }
+}
+
+fn is_numeric(ty: &Type) -> bool {
+    matches!(ty, Type::Integer | Type::Float | Type::Never)
+}
+
+fn supported(
+    operation: &'static str,
+    ty: Type,
+    predicate: impl Fn(&Type) -> bool,
+    location: &Location,
+) -> Result<Type, TypeError> {
+    if predicate(&ty) {
+        Ok(ty)
+    } else {
+        unsupported(operation, ty, location)
+    }
+}
+
+fn unsupported<T>(
+    operation: &'static str,
+    found: Type,
+    location: &Location,
+) -> Result<T, TypeError> {
+    Err(TypeError {
+        location: location.clone(),
+        kind: TypeErrorKind::Unsupported { operation, found },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        actor::{Address, Version},
+        expr::{
+            parse::{parse_action, parse_action_spanned, parse_upgrade, parse_upgrade_spanned},
+            Action, BinaryOp, Expr, Ident, Name, Type, Upgrade, Value,
+        },
+        node::{ReactiveId, VersionedReactiveAddress},
+    };
+
+    use super::{TypeError, TypeErrorKind};
+
+    /// Existing reactives `a`, `b`, ... of the given types, numbered in order.
+    fn reactives(types: &[Type]) -> HashMap<Name, (VersionedReactiveAddress, Type)> {
+        types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                let name = Name {
+                    text: ((b'a' + i as u8) as char).to_string(),
+                };
+                let address = VersionedReactiveAddress {
+                    address: Address::from_index(0),
+                    id: ReactiveId(i),
+                    version: Version::ZERO,
+                };
+                (name, (address, ty.clone()))
+            })
+            .collect()
+    }
+
+    fn check_upgrade(source: &str, types: &[Type]) -> Result<(), TypeError> {
+        check_upgrade_read_by(source, types, &[])
+    }
+
+    /// Checks an upgrade where some of the existing reactives are definitions, given by the name
+    /// of the reactive and the source of its expression.
+    fn check_upgrade_read_by(
+        source: &str,
+        types: &[Type],
+        definitions: &[(&str, &str)],
+    ) -> Result<(), TypeError> {
+        let reactives = reactives(types);
+        let resolve = |name: &Name| reactives.get(name).map(|(address, _)| address.clone());
+        let upgrade = parse_upgrade(source, resolve).unwrap();
+
+        let definitions = definitions
+            .iter()
+            .map(|(name, source)| {
+                let reader = resolve(&Name {
+                    text: name.to_string(),
+                });
+                let definition = parse_upgrade(&format!("def {name} = {source}"), resolve);
+                let Ok(Upgrade::Def(_, expr)) = definition else {
+                    panic!("invalid definition {source}");
+                };
+                (reader.unwrap(), expr)
+            })
+            .collect::<Vec<_>>();
+
+        upgrade
+            .check_types(
+                |address| {
+                    reactives
+                        .values()
+                        .find(|(a, _)| a == address)
+                        .map(|(_, ty)| ty.clone())
+                },
+                |address| {
+                    let mut readers = Vec::new();
+                    for (reader, expr) in &definitions {
+                        let mut reads = false;
+                        expr.visit_reads(&mut |ident, _| {
+                            reads |= ident == &Ident::Existing(address.clone());
+                        });
+                        if reads {
+                            readers.push((reader.clone(), expr.clone()));
+                        }
+                    }
+                    readers
+                },
+            )
+            .map(drop)
+    }
+
+    fn check_action(source: &str, types: &[Type]) -> Result<(), TypeError> {
+        let reactives = reactives(types);
+        let action = parse_action(source, |name| {
+            reactives.get(name).map(|(address, _)| address.clone())
+        })
+        .unwrap();
+
+        action.check_types(|address| {
+            reactives
+                .values()
+                .find(|(a, _)| a == address)
+                .map(|(_, ty)| ty.clone())
+        })
+    }
+
+    fn kind(result: Result<(), TypeError>) -> Option<TypeErrorKind> {
+        result.err().map(|error| error.kind)
+    }
+
+    /// The only existing reactive, an integer.
+    fn integer() -> VersionedReactiveAddress {
+        VersionedReactiveAddress {
+            address: Address::from_index(0),
+            id: ReactiveId(0),
+            version: Version::ZERO,
+        }
+    }
+
+    fn types(address: &VersionedReactiveAddress) -> Option<Type> {
+        (address == &integer()).then_some(Type::Integer)
+    }
+
+    #[test]
+    fn writes_must_conform_to_the_type_of_the_reactive() {
+        let value = |value| Box::new(Expr::Value(value));
+        let incremented = Expr::Binary(
+            BinaryOp::Add,
+            Box::new(Expr::Read(integer())),
+            value(Value::Integer(1)),
+        );
+        let action = Action::Write(integer(), incremented);
+        assert!(action.check_types(types).is_ok());
+
+        let concatenated = Expr::Binary(
+            BinaryOp::Add,
+            Box::new(Expr::Read(integer())),
+            value(Value::String("1".to_owned())),
+        );
+        let action = Action::Seq(
+            Box::new(action),
+            Box::new(Action::Write(integer(), concatenated)),
+        );
+        let Err(TypeError { location, kind }) = action.check_types(types) else {
+            panic!("adding a string to an integer was accepted")
+        };
+        assert_eq!(location, [1, 0, 1]);
+        assert!(matches!(
+            kind,
+            TypeErrorKind::Mismatch {
+                expected: Type::Integer,
+                found: Type::String,
+            }
+        ));
+    }
+
+    #[test]
+    fn upgrades_refer_to_reactives_declared_by_earlier_statements() {
+        let new = |text: &str| {
+            Ident::New(Name {
+                text: text.to_owned(),
+            })
+        };
+        let existing = || Ident::Existing(integer());
+        let read = |ident| Box::new(Expr::Read(ident));
+
+        // var x = true; def y = x && a == 1
+        let comparison = Expr::Binary(
+            BinaryOp::Eq,
+            read(existing()),
+            Box::new(Expr::Value(Value::Integer(1))),
+        );
+        let conjunction = Expr::Binary(BinaryOp::And, read(new("x")), Box::new(comparison));
+        let upgrade = Upgrade::Seq(
+            Box::new(Upgrade::Var(new("x"), Expr::Value(Value::Boolean(true)))),
+            Box::new(Upgrade::Def(new("y"), conjunction)),
+        );
+        assert!(upgrade.check_types(types, |_| Vec::new()).is_ok());
+
+        // del a; def y = a
+        let upgrade = Upgrade::Seq(
+            Box::new(Upgrade::Del(integer())),
+            Box::new(Upgrade::Def(new("y"), Expr::Read(existing()))),
+        );
+        let Err(TypeError { location, kind }) = upgrade.check_types(types, |_| Vec::new()) else {
+            panic!("reading a deleted reactive was accepted")
+        };
+        assert_eq!(location, [1, 0]);
+        assert!(matches!(kind, TypeErrorKind::UnknownReactive(ident) if ident == existing()));
+    }
+
+    #[test]
+    fn migrations_only_read_the_migrated_reactive() {
+        assert!(check_upgrade("migrate a = a + 1", &[Type::Integer]).is_ok());
+
+        let error = check_upgrade("migrate a = a + b", &[Type::Integer, Type::Integer]);
+        let Err(TypeError {
+            kind: TypeErrorKind::MigrationRead(Ident::Existing(address)),
+            ..
+        }) = error
+        else {
+            panic!("expected a migration read error, found {error:?}");
+        };
+        assert_eq!(address.id, ReactiveId(1));
+    }
+
+    #[test]
+    fn mixed_collections_are_rejected() {
+        let mixed = Value::List(Box::new([Value::Integer(1), Value::String("a".to_owned())]));
+        let reactives = reactives(&[Type::List(Box::new(Type::Integer))]);
+        let (address, ty) = reactives.values().next().unwrap().clone();
+        let action = Action::Write(address, Expr::Value(mixed));
+        let error = action.check_types(|_| Some(ty.clone()));
+        assert!(matches!(
+            error,
+            Err(TypeError {
+                kind: TypeErrorKind::MixedCollection,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn lambdas_applied_to_themselves_are_rejected() {
+        let error = check_upgrade(r"def d = (\x -> x(x))(\x -> x(x))", &[]);
+        assert!(matches!(
+            error,
+            Err(TypeError {
+                kind: TypeErrorKind::RecursiveFunction,
+                ..
+            })
+        ));
+
+        // Applying a lambda within its own application is fine as long as it ends.
+        let twice = r"let twice = \f -> \x -> f(f(x)) in twice(twice)(\y -> y + 1)(0)";
+        assert!(check_upgrade(&format!("def d = {twice}"), &[]).is_ok());
+        assert!(check_upgrade(r"def d = (\x -> x(x))(\y -> y)(1)", &[]).is_ok());
+    }
+
+    #[test]
+    fn lambdas_taking_too_many_applications_to_check_are_rejected() {
+        // Every application is different, but there are 2^16 of them.
+        let source = r"def c = let t = \f -> \x -> f(f(x)) in t(t)(t)(t)(\y -> y + 1)(0)";
+        assert_eq!(
+            kind(check_upgrade(source, &[])),
+            Some(TypeErrorKind::TooComplex)
+        );
+    }
+
+    #[test]
+    fn folds_whose_accumulator_keeps_growing_are_rejected() {
+        let growing = r"def d = fold([1], [], \acc -> \x -> [acc])";
+        assert!(matches!(
+            kind(check_upgrade(growing, &[])),
+            Some(TypeErrorKind::Mismatch { .. })
+        ));
+
+        let widening = r"def d = fold([1, 2], [], \acc -> \x -> concat(acc, [x]))";
+        assert!(check_upgrade(widening, &[]).is_ok());
+    }
+
+    #[test]
+    fn writes_conform_to_the_declared_type() {
+        let tag = |tag: &str| Name {
+            text: tag.to_owned(),
+        };
+        let variant = Type::Variant([(tag("A"), Type::Integer)].into());
+        let list = Type::List(Box::new(Type::Integer));
+
+        assert!(check_action("a := #A(1); b := []", &[variant.clone(), list]).is_ok());
+        assert_eq!(
+            kind(check_action("a := #B(1)", std::slice::from_ref(&variant))),
+            Some(TypeErrorKind::Mismatch {
+                expected: variant,
+                found: Type::Variant([(tag("B"), Type::Integer)].into()),
+            })
+        );
+    }
+
+    #[test]
+    fn readers_of_changed_reactives_are_checked_again() {
+        let types = [Type::Integer, Type::Integer];
+        let readers = [("b", "a + 1")];
+
+        // Reactive b reads a, which would become a string.
+        let error = check_upgrade_read_by(r#"var a = "x""#, &types, &readers);
+        let Err(TypeError {
+            kind: TypeErrorKind::BrokenReader { reader, .. },
+            ..
+        }) = error
+        else {
+            panic!("expected a broken reader, found {error:?}");
+        };
+        assert_eq!(reader.id, ReactiveId(1));
+
+        assert!(check_upgrade_read_by(r#"var a = "x"; def b = len(a)"#, &types, &readers).is_ok());
+        assert!(check_upgrade_read_by("var a = 2", &types, &readers).is_ok());
+        assert!(matches!(
+            kind(check_upgrade_read_by("del a", &types, &readers)),
+            Some(TypeErrorKind::BrokenReader { .. })
+        ));
+
+        // Reactives defined by the upgrade itself are checked again as well.
+        let error = check_upgrade(r#"def c = a + 1; var a = "x""#, &types);
+        assert!(error.unwrap_err().location.starts_with(&[0, 0]));
+    }
+
+    /// The part of the source that the type error in an upgrade or action is reported at.
+    fn reported_at<'s>(source: &'s str, types: &[Type]) -> &'s str {
+        let reactives = reactives(types);
+        let resolve = |name: &Name| reactives.get(name).map(|(address, _)| address.clone());
+        let declared = |address: &VersionedReactiveAddress| {
+            reactives
+                .values()
+                .find(|(a, _)| a == address)
+                .map(|(_, ty)| ty.clone())
+        };
+        let (error, spans) = match parse_upgrade_spanned(source, resolve) {
+            Ok((upgrade, spans)) => {
+                let error = upgrade.check_types(declared, |_| Vec::new()).map(drop);
+                (error, spans)
+            }
+            Err(_) => {
+                let (action, spans) = parse_action_spanned(source, resolve).unwrap();
+                (action.check_types(declared), spans)
+            }
+        };
+        &source[error.unwrap_err().span(&spans)]
+    }
+
+    #[test]
+    fn type_errors_are_reported_where_they_are_in_the_source() {
+        let types = [Type::Integer, Type::String];
+        for (source, expected) in [
+            ("a := 1; a := a + b", "b"),
+            ("a := 1; b := \"x\"; a := b", "b"),
+            ("if a > 0 { b := b } else { b := [a, 2] }", "[a, 2]"),
+            ("while a < b limit 3 { a := a + 1 }", "b"),
+            ("let x = a in a := (x, 2).1 + b", "b"),
+            ("def c = (1, 2).2", "(1, 2).2"),
+            ("def c = fold([1, 2], 0, \\s -> \\x -> s + b)", "b"),
+            ("def c = {x = a}.y; def d = 1", "{x = a}.y"),
+            ("var c = 1; def d = -(c == 1)", "c == 1"),
+            ("def c = let f = \\x -> x + 1 in f(b)", "1"),
+        ] {
+            assert_eq!(reported_at(source, &types), expected, "in {source}");
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod actor;
+pub mod expr;
+pub mod manager;
+pub mod message;
+pub mod node;
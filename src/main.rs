@@ -1,20 +1,26 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
 
-use crate::{
-    actor::{Actor, ActorConfiguration, Address, Context, System},
-    expr::{Expr, Ident, Value},
+use historiographer::{
+    actor::{self, Actor, ActorConfiguration, Address, Context, System, Version},
+    expr::{
+        parse::{self, Spans},
+        BinaryOp, Expr, Name, Type, Value,
+    },
+    manager::{Declaration, Manager},
     message::{
-        BasisStamp, ImportConfiguration, LockKind, Message, MonotonicTimestampGenerator,
-        ReactiveConfiguration, StampedValue, Timestamp, TxId, TxPriority,
+        BasisStamp, DefinitionOptions, LockKind, Message, MonotonicTimestampGenerator,
+        PropagationMode, ReactiveConfiguration, StampedValue, TransactionError, TxId, TxPriority,
     },
-    node::{Node, ReactiveAddress, ReactiveId},
+    node::{Node, ReactiveAddress, ReactiveId, VersionedReactiveAddress},
 };
 
-mod actor;
-mod expr;
-mod manager;
-mod message;
-mod node;
+/// The transactions the client requests once the reactives are configured, in order.
+const REQUESTS: &[Request] = &[
+    Request::Action("x := 2"),
+    Request::Action("x := x * 10; if x > 15 { x := x - 1 }"),
+    Request::Action("x := \"two\""),
+    Request::Upgrade("def w = total * 2"),
+];
 
 fn main() {
     let mut system = System::new();
@@ -27,7 +33,6 @@ fn main() {
 struct ScenarioConfiguration;
 
 struct Scenario {
-    gen: MonotonicTimestampGenerator,
     node1: Address,
     node2: Address,
     txid: TxId,
@@ -36,10 +41,23 @@ struct Scenario {
     basis: BasisStamp,
 }
 
-struct Stage2 {
-    txid: TxId,
-    node1: Address,
-    node2: Address,
+/// Prints the values streamed to it by the reactives it subscribed to.
+struct Printer;
+
+/// Requests transactions from a manager one at a time, and prints how each of them ended.
+struct Client {
+    manager: Address,
+    /// The reactives the sources of requests refer to by name.
+    names: HashMap<Name, VersionedReactiveAddress>,
+    requests: VecDeque<Request>,
+    /// The source of the request awaiting an answer, along with where its parts are in it.
+    current: Option<(&'static str, Spans)>,
+}
+
+#[derive(Clone, Copy)]
+enum Request {
+    Action(&'static str),
+    Upgrade(&'static str),
 }
 
 impl ActorConfiguration for ScenarioConfiguration {
@@ -56,25 +74,17 @@ impl ActorConfiguration for ScenarioConfiguration {
             timestamp,
             address: ctx.me().clone(),
         };
-        ctx.send(
-            &node1,
-            Message::Lock {
-                txid: txid.clone(),
-                kind: LockKind::Exclusive,
-            },
-        );
-        ctx.send(
-            &node2,
-            Message::Lock {
-                txid: txid.clone(),
-                kind: LockKind::Exclusive,
-            },
-        );
-
-        dbg!(&node1, &node2);
+        for node in [&node1, &node2] {
+            ctx.send(
+                node,
+                Message::Lock {
+                    txid: txid.clone(),
+                    kind: LockKind::Exclusive,
+                },
+            );
+        }
 
         Scenario {
-            gen,
             node1,
             node2,
             txid,
@@ -85,74 +95,90 @@ impl ActorConfiguration for ScenarioConfiguration {
     }
 }
 
+impl Scenario {
+    /// The reactives of the scenario by name: `x` is a variable, `y` is `x + 1` on the same node,
+    /// and on the other node `z` is `y` and `total` sums every value of `z`.
+    fn reactives(&self) -> [(&'static str, ReactiveAddress); 4] {
+        let reactive = |node: &Address, id| ReactiveAddress {
+            address: node.clone(),
+            id: ReactiveId(id),
+        };
+        [
+            ("x", reactive(&self.node1, 0)),
+            ("y", reactive(&self.node1, 1)),
+            ("z", reactive(&self.node2, 0)),
+            ("total", reactive(&self.node2, 1)),
+        ]
+    }
+
+    fn configure(&self, node: &Address) -> Message {
+        let [(_, x), (_, y), (_, z), (_, total)] = self.reactives();
+        let read = |address: &ReactiveAddress| Box::new(Expr::Read(address.clone()));
+
+        let reactives = if *node == self.node1 {
+            HashMap::from([
+                (
+                    x.id,
+                    Some(ReactiveConfiguration::Variable {
+                        value: StampedValue {
+                            value: Value::Integer(0),
+                            basis: BasisStamp::empty(),
+                        },
+                    }),
+                ),
+                (
+                    y.id,
+                    Some(ReactiveConfiguration::Definition {
+                        expr: Expr::Binary(
+                            BinaryOp::Add,
+                            read(&x),
+                            Box::new(Expr::Value(Value::Integer(1))),
+                        ),
+                        options: DefinitionOptions::default(),
+                    }),
+                ),
+            ])
+        } else {
+            HashMap::from([
+                (
+                    z.id,
+                    Some(ReactiveConfiguration::Definition {
+                        expr: *read(&y),
+                        // Only the latest value of node1's reactive is of interest.
+                        options: DefinitionOptions {
+                            propagation: PropagationMode::Latest,
+                            ..DefinitionOptions::default()
+                        },
+                    }),
+                ),
+                (
+                    total.id,
+                    Some(ReactiveConfiguration::Fold {
+                        init: Value::Integer(0),
+                        step: Expr::Binary(BinaryOp::Add, read(&total), read(&z)),
+                        options: DefinitionOptions::default(),
+                    }),
+                ),
+            ])
+        };
+
+        Message::Configure {
+            txid: self.txid.clone(),
+            // The import of node1's reactive and the matching export are negotiated by the nodes
+            // themselves.
+            imports: HashMap::new(),
+            reactives,
+            exports: HashMap::new(),
+        }
+    }
+}
+
 impl Actor for Scenario {
     fn handle(&mut self, message: Message, ctx: actor::Context) {
         match message {
             Message::LockGranted { txid, address } => {
-                assert_eq!(&txid, &self.txid);
-                if &address == &self.node1 {
-                    assert_eq!(&address, &self.node1);
-                    ctx.send(
-                        &address,
-                        Message::Configure {
-                            txid: self.txid.clone(),
-                            imports: HashMap::new(),
-                            reactives: HashMap::from([
-                                (
-                                    ReactiveId(0),
-                                    Some(ReactiveConfiguration::Variable {
-                                        value: StampedValue {
-                                            value: Value::Integer(0),
-                                            basis: BasisStamp::empty(),
-                                        },
-                                    }),
-                                ),
-                                (
-                                    ReactiveId(1),
-                                    Some(ReactiveConfiguration::Definition {
-                                        expr: Expr::Read(ReactiveAddress {
-                                            address: self.node1.clone(),
-                                            id: ReactiveId(0),
-                                        }),
-                                    }),
-                                ),
-                            ]),
-                            exports: HashMap::from([(
-                                ReactiveId(1),
-                                HashSet::from([self.node2.clone()]),
-                            )]),
-                        },
-                    );
-                } else {
-                    ctx.send(
-                        &address,
-                        Message::Configure {
-                            txid: self.txid.clone(),
-                            imports: HashMap::from([(
-                                ReactiveAddress {
-                                    address: self.node1.clone(),
-                                    id: ReactiveId(1),
-                                },
-                                Some(ImportConfiguration {
-                                    roots: HashSet::from([ReactiveAddress {
-                                        address: self.node1.clone(),
-                                        id: ReactiveId(1),
-                                    }]),
-                                }),
-                            )]),
-                            reactives: HashMap::from([(
-                                ReactiveId(0),
-                                Some(ReactiveConfiguration::Definition {
-                                    expr: Expr::Read(ReactiveAddress {
-                                        address: self.node1.clone(),
-                                        id: ReactiveId(1),
-                                    }),
-                                }),
-                            )]),
-                            exports: HashMap::new(),
-                        },
-                    );
-                }
+                assert_eq!(txid, self.txid);
+                ctx.send(&address, self.configure(&address));
                 ctx.send(
                     &address,
                     Message::PrepareCommit {
@@ -169,93 +195,159 @@ impl Actor for Scenario {
 
                 self.basis.merge_from(&basis);
 
-                if &address == &self.node1 {
+                if address == self.node1 {
                     assert!(!self.node1_prepared);
                     self.node1_prepared = true;
-                } else if &address == &self.node2 {
+                } else if address == self.node2 {
                     assert!(!self.node2_prepared);
                     self.node2_prepared = true;
                 } else {
                     unreachable!();
                 }
 
-                if self.node1_prepared && self.node2_prepared {
-                    ctx.send(
-                        &self.node1,
-                        Message::Commit {
-                            txid: self.txid.clone(),
-                            basis: self.basis.clone(),
-                        },
-                    );
+                if !(self.node1_prepared && self.node2_prepared) {
+                    return;
+                }
+
+                for node in [&self.node1, &self.node2] {
                     ctx.send(
-                        &self.node2,
+                        node,
                         Message::Commit {
                             txid: self.txid.clone(),
                             basis: self.basis.clone(),
                         },
                     );
+                }
 
-                    let t2 = TxId {
-                        priority: TxPriority::Low,
-                        timestamp: self.gen.generate_timestamp(),
-                        address: ctx.me().clone(),
-                    };
+                let printer = ctx.spawn(Printer);
+                for (_, reactive) in self.reactives() {
                     ctx.send(
-                        &self.node1,
-                        Message::Lock {
-                            txid: t2.clone(),
-                            kind: LockKind::Exclusive,
+                        &reactive.address,
+                        Message::Subscribe {
+                            reactive: reactive.id,
+                            subscriber: printer.clone(),
                         },
                     );
-                    ctx.shift(Stage2 {
-                        txid: t2,
-                        node1: self.node1.clone(),
-                        node2: self.node2.clone(),
-                    });
                 }
+
+                let names = self
+                    .reactives()
+                    .into_iter()
+                    .map(|(name, reactive)| {
+                        let name = Name {
+                            text: name.to_owned(),
+                        };
+                        let reactive = VersionedReactiveAddress {
+                            address: reactive.address,
+                            id: reactive.id,
+                            version: Version::ZERO,
+                        };
+                        (name, reactive)
+                    })
+                    .collect::<HashMap<_, _>>();
+                // The manager keeps the definitions so that upgrades changing their inputs can be
+                // checked against them.
+                let read = |name: &str| {
+                    let name = Name {
+                        text: name.to_owned(),
+                    };
+                    Box::new(Expr::Read(names[&name].clone()))
+                };
+                let one = Box::new(Expr::Value(Value::Integer(1)));
+                let definitions = HashMap::from([
+                    ("y", Expr::Binary(BinaryOp::Add, read("x"), one)),
+                    ("z", *read("y")),
+                ]);
+                let reactives = names
+                    .iter()
+                    .map(|(name, reactive)| {
+                        let declaration = Declaration {
+                            ty: Type::Integer,
+                            definition: definitions.get(name.text.as_str()).cloned(),
+                        };
+                        (reactive.clone(), declaration)
+                    })
+                    .collect();
+                let nodes = vec![self.node1.clone(), self.node2.clone()];
+
+                let mut client = Client {
+                    manager: ctx.spawn(Manager::new(nodes, reactives)),
+                    names,
+                    requests: REQUESTS.iter().copied().collect(),
+                    current: None,
+                };
+                client.request_next(&ctx);
+                ctx.shift(client);
             }
             _ => todo!("unexpected message for test scenario: {:?}", message),
         }
     }
 }
 
-impl Actor for Stage2 {
-    fn handle(&mut self, message: Message, ctx: Context) {
+impl Actor for Printer {
+    fn handle(&mut self, message: Message, _ctx: Context) {
         match message {
-            Message::LockGranted { txid, address } => {
-                assert_eq!(address, self.node1);
-                assert_eq!(txid, self.txid);
-                ctx.send(
-                    &address,
-                    Message::Write {
-                        txid: self.txid.clone(),
-                        reactive: ReactiveId(0),
-                        value: Value::Integer(2),
-                    },
-                );
-                ctx.send(
-                    &address,
-                    Message::PrepareCommit {
-                        txid: self.txid.clone(),
-                    },
-                );
+            Message::ValueChanged { reactive, value } => {
+                println!("new value for {reactive:?}: {value:?}");
             }
-            Message::CommitPrepared {
-                address,
-                txid,
-                basis,
-            } => {
-                assert_eq!(address, self.node1);
-                assert_eq!(txid, self.txid);
-                ctx.send(
-                    &address,
-                    Message::Commit {
-                        txid: self.txid.clone(),
-                        basis,
-                    },
-                );
+            _ => todo!("unexpected message for printer: {:?}", message),
+        }
+    }
+}
+
+impl Client {
+    /// Parses the next request and sends it to the manager, if there are any left.
+    fn request_next(&mut self, ctx: &Context) {
+        let Some(request) = self.requests.pop_front() else {
+            return;
+        };
+
+        let resolve = |name: &Name| self.names.get(name).cloned();
+        let requester = ctx.me().clone();
+        let (source, parsed) = match request {
+            Request::Action(source) => (
+                source,
+                parse::parse_action_spanned(source, resolve)
+                    .map(|(action, spans)| (Message::Do { action, requester }, spans)),
+            ),
+            Request::Upgrade(source) => (
+                source,
+                parse::parse_upgrade_spanned(source, resolve)
+                    .map(|(upgrade, spans)| (Message::Upgrade { upgrade, requester }, spans)),
+            ),
+        };
+
+        match parsed {
+            Ok((message, spans)) => {
+                println!("requesting {source}");
+                ctx.send(&self.manager, message);
+                self.current = Some((source, spans));
+            }
+            Err(error) => {
+                println!("could not parse request:\n{}", error.render(source));
+                self.request_next(ctx);
             }
-            _ => todo!("unexpected message for stage 2: {:?}", message),
         }
     }
 }
+
+impl Actor for Client {
+    fn handle(&mut self, message: Message, ctx: Context) {
+        let (source, spans) = self
+            .current
+            .take()
+            .expect("received an answer without a pending request");
+
+        match message {
+            Message::Committed { txid, .. } => println!("committed {txid:?}"),
+            Message::Aborted {
+                error: TransactionError::Type(error),
+                ..
+            } => println!("rejected:\n{}", error.render(source, &spans)),
+            Message::Aborted { error, .. } => println!("aborted: {error:?}"),
+            _ => todo!("unexpected message for client: {:?}", message),
+        }
+
+        self.request_next(&ctx);
+    }
+}
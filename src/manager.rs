@@ -1,23 +1,497 @@
 use std::collections::HashMap;
 
-use transaction::Transaction;
+use transaction::{Transaction, TransactionKind};
 
 use crate::{
-    actor::{Actor, Context},
-    message::{Message, MonotonicTimestampGenerator, TxId},
+    actor::{Actor, Address, Context, Version},
+    expr::{Expr, Ident, Name, Type, Upgrade},
+    message::{Message, MonotonicTimestampGenerator, TransactionError, TxId, TxPriority},
+    node::{ReactiveId, VersionedReactiveAddress},
 };
 
 mod transaction;
 
 pub struct Manager {
     timestamp_generator: MonotonicTimestampGenerator,
+    /// The nodes reactives created by upgrades may be placed on.
+    nodes: Vec<Address>,
+    /// The id the next reactive placed on each node is given. Ids are never reused, even once the
+    /// reactive holding one is deleted.
+    next_ids: HashMap<Address, usize>,
+    /// Each live reactive, which transactions are checked against.
+    reactives: HashMap<VersionedReactiveAddress, Declaration>,
     transactions: HashMap<TxId, Transaction>,
 }
 
+/// What the manager knows of a live reactive.
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub ty: Type,
+    /// The expression of a definition, or `None` for other reactives.
+    pub definition: Option<Expr>,
+}
+
+impl Manager {
+    /// Creates a manager of the given nodes, which hold `reactives` to begin with.
+    pub fn new(
+        nodes: Vec<Address>,
+        reactives: HashMap<VersionedReactiveAddress, Declaration>,
+    ) -> Manager {
+        assert!(
+            !nodes.is_empty(),
+            "a manager needs a node to place reactives on"
+        );
+
+        let mut next_ids = HashMap::new();
+        for address in reactives.keys() {
+            let next_id = next_ids.entry(address.address.clone()).or_insert(0);
+            *next_id = (*next_id).max(address.id.0 + 1);
+        }
+
+        Manager {
+            timestamp_generator: MonotonicTimestampGenerator::new(),
+            nodes,
+            next_ids,
+            reactives,
+            transactions: HashMap::new(),
+        }
+    }
+
+    /// Chooses where the reactives an upgrade creates are placed. Each is placed on the node of
+    /// the first reactive it reads, so that definitions tend to live next to their inputs, or on
+    /// the first node if it reads none.
+    fn place(&mut self, upgrade: &Upgrade) -> HashMap<Name, VersionedReactiveAddress> {
+        let mut placed = HashMap::new();
+        let mut statements = vec![upgrade];
+        while let Some(statement) = statements.pop() {
+            let (name, expr) = match statement {
+                Upgrade::Seq(a, b) => {
+                    statements.push(b);
+                    statements.push(a);
+                    continue;
+                }
+                Upgrade::Var(Ident::New(name), expr) | Upgrade::Def(Ident::New(name), expr) => {
+                    (name, expr)
+                }
+                _ => continue,
+            };
+
+            if placed.contains_key(name) {
+                continue;
+            }
+
+            let mut node = None;
+            expr.visit_reads(&mut |ident, _definite| {
+                if node.is_none() {
+                    node = match ident {
+                        Ident::Existing(address) => Some(address.address.clone()),
+                        Ident::New(name) => placed
+                            .get(name)
+                            .map(|address: &VersionedReactiveAddress| address.address.clone()),
+                    };
+                }
+            });
+
+            let address = node.unwrap_or_else(|| self.nodes[0].clone());
+            let next_id = self.next_ids.entry(address.clone()).or_insert(0);
+            let id = ReactiveId(*next_id);
+            *next_id += 1;
+            let reactive = VersionedReactiveAddress {
+                address,
+                id,
+                version: Version::ZERO,
+            };
+            placed.insert(name.clone(), reactive);
+        }
+
+        placed
+    }
+
+    fn start(&mut self, kind: TransactionKind, requester: Address, ctx: &Context) {
+        let txid = TxId {
+            priority: TxPriority::Low,
+            timestamp: self.timestamp_generator.generate_timestamp(),
+            address: ctx.me().clone(),
+        };
+
+        let tx = Transaction::new(txid.clone(), requester.clone(), kind, &self.reactives);
+
+        match tx {
+            Ok(tx) => {
+                self.transactions.insert(txid.clone(), tx);
+                self.eval_tx(&txid, ctx);
+            }
+            Err(error) => ctx.send(&requester, Message::Aborted { txid, error }),
+        }
+    }
+
+    fn eval_tx(&mut self, txid: &TxId, ctx: &Context) {
+        let is_done = self
+            .transactions
+            .get_mut(txid)
+            .expect("attempted to evaluate nonexistent transaction")
+            .eval(ctx);
+
+        if is_done {
+            self.transactions.remove(txid);
+        }
+    }
+}
+
 impl Actor for Manager {
     fn handle(&mut self, message: Message, ctx: Context) {
         match message {
-            _ => todo!(),
+            Message::Do { action, requester } => {
+                self.start(TransactionKind::Action(action), requester, &ctx)
+            }
+            Message::Upgrade { upgrade, requester } => {
+                let placed = self.place(&upgrade);
+                self.start(TransactionKind::Upgrade(upgrade, placed), requester, &ctx)
+            }
+            Message::LockGranted { txid, address } => match self.transactions.get_mut(&txid) {
+                Some(tx) => {
+                    tx.lock_granted(address, &ctx);
+                    self.eval_tx(&txid, &ctx);
+                }
+                // The transaction ended before this lock was granted, so it is released at once.
+                None => ctx.send(&address, Message::Abort { txid }),
+            },
+            Message::ReadResult {
+                txid,
+                reactive,
+                value,
+            } => {
+                if let Some(tx) = self.transactions.get_mut(&txid) {
+                    tx.read_result(reactive, value);
+                    self.eval_tx(&txid, &ctx);
+                }
+            }
+            Message::CommitPrepared {
+                address,
+                txid,
+                basis,
+            } => {
+                // A transaction that was aborted while preparing is no longer known.
+                if let Some(tx) = self.transactions.get_mut(&txid) {
+                    if tx.commit_prepared(address, basis, &ctx) {
+                        for (address, declaration) in tx.changes() {
+                            match declaration {
+                                Some(declaration) => self.reactives.insert(address, declaration),
+                                None => self.reactives.remove(&address),
+                            };
+                        }
+                        self.transactions.remove(&txid);
+                    }
+                }
+            }
+            Message::PrepareFailed {
+                address,
+                txid,
+                error,
+            } => {
+                if let Some(mut tx) = self.transactions.remove(&txid) {
+                    tx.abort(TransactionError::Prepare { address, error }, &ctx);
+                }
+            }
+            Message::Preempt { txid } => {
+                if let Some(tx) = self.transactions.get_mut(&txid) {
+                    tx.preempt(&ctx);
+                    self.eval_tx(&txid, &ctx);
+                }
+            }
+            // A node a transaction had to reach is gone, so the transaction cannot complete.
+            Message::Unreachable { message } => {
+                let txid = match *message {
+                    Message::Lock { txid, .. }
+                    | Message::Read { txid, .. }
+                    | Message::Write { txid, .. }
+                    | Message::PrepareCommit { txid }
+                    | Message::Commit { txid, .. }
+                    | Message::Abort { txid } => txid,
+                    _ => return,
+                };
+
+                if let Some(mut tx) = self.transactions.remove(&txid) {
+                    tx.abort(TransactionError::Unreachable, &ctx);
+                }
+            }
+            // Messages meant for nodes and for the requesters of transactions.
+            Message::PropagateBatch { .. }
+            | Message::Subscribe { .. }
+            | Message::Unsubscribe { .. }
+            | Message::ValueChanged { .. }
+            | Message::Diagnose { .. }
+            | Message::Diagnostics { .. }
+            | Message::RetainHistory { .. }
+            | Message::ReadAt { .. }
+            | Message::ValueAt { .. }
+            | Message::Lock { .. }
+            | Message::Read { .. }
+            | Message::Write { .. }
+            | Message::RegisterImporter { .. }
+            | Message::ImporterRegistered { .. }
+            | Message::UpdateRoots { .. }
+            | Message::RootsUpdated { .. }
+            | Message::ReadConfiguration { .. }
+            | Message::ReadConfigurationResult { .. }
+            | Message::Configure { .. }
+            | Message::Retire { .. }
+            | Message::Abort { .. }
+            | Message::PrepareCommit { .. }
+            | Message::Commit { .. }
+            | Message::CommitRoots { .. }
+            | Message::AbortRoots { .. }
+            | Message::Committed { .. }
+            | Message::Aborted { .. }
+            | Message::Directory { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        actor::Version,
+        expr::typecheck::{TypeError, TypeErrorKind},
+        expr::{
+            parse::{parse_action, parse_upgrade},
+            BinaryOp, Expr, Type, Value,
+        },
+        message::{LockKind, Message, TransactionError},
+        node::{
+            tests::{def, var, Network},
+            ReactiveId, VersionedReactiveAddress,
+        },
+    };
+
+    use super::{Declaration, Manager};
+
+    /// The values the requester was told of, panicking if any transaction was aborted.
+    fn values(network: &mut Network) -> Vec<Value> {
+        network
+            .received()
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::ValueChanged { value, .. } => Some(value.value),
+                Message::Aborted { error, .. } => panic!("transaction aborted: {error:?}"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn upgrades_configure_the_reactives_they_declare() {
+        let mut network = Network::new(2);
+        let txid = network.lock(&[1], LockKind::Exclusive);
+        network.configure(&txid, 1, vec![(0, var(Value::Integer(3)))]);
+        network.prepare(&txid, &[1]);
+        network.commit(&txid, &[1]);
+
+        let nodes = network.nodes.clone();
+        let reactive = |node: usize, id| VersionedReactiveAddress {
+            address: nodes[node].clone(),
+            id: ReactiveId(id),
+            version: Version::ZERO,
+        };
+        let x = reactive(1, 0);
+        let declaration = Declaration {
+            ty: Type::Integer,
+            definition: None,
+        };
+        let reactives = HashMap::from([(x.clone(), declaration)]);
+        let manager = network.system.spawn(Manager::new(nodes.clone(), reactives));
+        let requester = network.client.clone();
+
+        // New reactives are placed next to the first reactive they read, after the ones there,
+        // and on the first node if they read none. Variables read the variables declared before
+        // them.
+        let source = "var a = x + 1; var b = a * 10; def y = b + x; var c = 5";
+        let upgrade = parse_upgrade(source, |name| (name.text == "x").then(|| x.clone()));
+        network.send(
+            &manager,
+            Message::Upgrade {
+                upgrade: upgrade.unwrap(),
+                requester: requester.clone(),
+            },
+        );
+        network.system.run();
+
+        let (y, c) = (reactive(1, 3), reactive(0, 0));
+        for reactive in [&y, &c] {
+            network.send(
+                &reactive.address,
+                Message::Subscribe {
+                    reactive: reactive.id,
+                    subscriber: requester.clone(),
+                },
+            );
+        }
+
+        // The manager knows the declared reactives once the upgrade has committed.
+        let names = HashMap::from([("y", y), ("c", c)]);
+        let action = parse_action("c := y", |name| names.get(name.text.as_str()).cloned());
+        network.send(
+            &manager,
+            Message::Do {
+                action: action.unwrap(),
+                requester,
+            },
+        );
+        network.system.run();
+
+        let expected = [43, 5, 43].map(Value::Integer);
+        assert_eq!(values(&mut network), expected);
+    }
+
+    #[test]
+    fn upgrades_breaking_live_definitions_are_rejected() {
+        let mut network = Network::new(1);
+        let txid = network.lock(&[0], LockKind::Exclusive);
+        fn successor<I>(x: I) -> Expr<I> {
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Read(x)),
+                Box::new(Expr::Value(Value::Integer(1))),
+            )
+        }
+
+        let reactives = vec![
+            (0, var(Value::Integer(1))),
+            (1, def(successor(network.address(0, 0)))),
+        ];
+        network.configure(&txid, 0, reactives);
+        network.prepare(&txid, &[0]);
+        network.commit(&txid, &[0]);
+
+        let reactive = |id| VersionedReactiveAddress {
+            address: network.nodes[0].clone(),
+            id: ReactiveId(id),
+            version: Version::ZERO,
+        };
+        let (x, y) = (reactive(0), reactive(1));
+        let x_declaration = Declaration {
+            ty: Type::Integer,
+            definition: None,
+        };
+        let y_declaration = Declaration {
+            ty: Type::Integer,
+            definition: Some(successor(x.clone())),
+        };
+        let reactives = HashMap::from([(x.clone(), x_declaration), (y.clone(), y_declaration)]);
+        let manager = network
+            .system
+            .spawn(Manager::new(network.nodes.clone(), reactives));
+
+        let requester = network.client.clone();
+        for source in ["var x = \"one\"", "var x = 4"] {
+            let upgrade = parse_upgrade(source, |name| (name.text == "x").then(|| x.clone()));
+            network.send(
+                &manager,
+                Message::Upgrade {
+                    upgrade: upgrade.unwrap(),
+                    requester: requester.clone(),
+                },
+            );
+        }
+        network.system.run();
+
+        // Only the second upgrade took effect.
+        network.send(
+            &y.address,
+            Message::Subscribe {
+                reactive: y.id,
+                subscriber: requester,
+            },
+        );
+        network.system.run();
+
+        let received = network.received();
+        let [Message::Aborted { error, .. }, Message::Committed { .. }, Message::ValueChanged { value, .. }] =
+            received.as_slice()
+        else {
+            panic!("unexpected messages: {received:?}");
+        };
+        let TransactionError::Type(TypeError {
+            kind: TypeErrorKind::BrokenReader { reader, .. },
+            ..
+        }) = error
+        else {
+            panic!("unexpected error: {error:?}");
+        };
+        assert_eq!(reader, &y);
+        assert_eq!(value.value, Value::Integer(5));
+    }
+
+    #[test]
+    fn actions_read_their_own_writes() {
+        let mut network = Network::new(1);
+        let txid = network.lock(&[0], LockKind::Exclusive);
+        let doubled = Expr::Binary(
+            BinaryOp::Mul,
+            Box::new(Expr::Read(network.address(0, 0))),
+            Box::new(Expr::Value(Value::Integer(2))),
+        );
+        let reactives = vec![
+            (0, var(Value::Integer(1))),
+            (1, var(Value::Integer(0))),
+            (2, def(doubled)),
+        ];
+        network.configure(&txid, 0, reactives);
+        network.prepare(&txid, &[0]);
+        network.commit(&txid, &[0]);
+
+        let reactive = |id| VersionedReactiveAddress {
+            address: network.nodes[0].clone(),
+            id: ReactiveId(id),
+            version: Version::ZERO,
+        };
+        let names = HashMap::from([("x", reactive(0)), ("y", reactive(1)), ("z", reactive(2))]);
+        let reactives = names
+            .values()
+            .map(|address| {
+                let declaration = Declaration {
+                    ty: Type::Integer,
+                    definition: None,
+                };
+                (address.clone(), declaration)
+            })
+            .collect();
+        let manager = network
+            .system
+            .spawn(Manager::new(network.nodes.clone(), reactives));
+
+        let requester = network.client.clone();
+        network.send(
+            &network.nodes[0].clone(),
+            Message::Subscribe {
+                reactive: ReactiveId(1),
+                subscriber: requester.clone(),
+            },
+        );
+
+        // The first action reads its write at the coordinator, having read the reactive before,
+        // and the second at the node. The third reads a definition of what it has written, which
+        // is based on the committed value rather than on the write.
+        let sources = [
+            "x := x + 1; y := x",
+            "x := 5; y := x * 2",
+            "x := x + 1; y := z + 1",
+        ];
+        for source in sources {
+            let action = parse_action(source, |name| names.get(name.text.as_str()).cloned());
+            network.send(
+                &manager,
+                Message::Do {
+                    action: action.unwrap(),
+                    requester: requester.clone(),
+                },
+            );
         }
+        network.system.run();
+
+        let expected = [0, 2, 10, 11].map(Value::Integer);
+        assert_eq!(values(&mut network), expected);
     }
 }
@@ -1,47 +1,656 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
 
 use crate::{
-    actor::{Address, Version},
-    expr::{Action, Upgrade},
-    message::TxId,
-    node::ReactiveId,
+    actor::{Address, Context},
+    expr::{
+        eval::{ActionEvalContext, EvalError, ExprEvalContext, UpgradeEvalContext},
+        Action, Expr, Ident, Name, Type, Upgrade, Value,
+    },
+    message::{
+        BasisStamp, DefinitionOptions, LockKind, Message, ReactiveConfiguration, StampedValue,
+        TransactionError, TxId,
+    },
+    node::{ReactiveAddress, ReactiveId, VersionedReactiveAddress},
 };
 
+use super::Declaration;
+
 pub struct Transaction {
     kind: TransactionKind,
+    /// The transaction as submitted, from which evaluation starts over if it is preempted.
+    submitted: TransactionKind,
+    /// The types of the reactives an upgrade declares, or `None` for those it deletes.
+    declared: HashMap<Ident, Option<Type>>,
     state: TransactionState,
 }
 
+#[derive(Clone)]
 pub enum TransactionKind {
     Action(Action),
-    Upgrade(Upgrade),
+    /// An upgrade, along with the addresses at which the reactives it creates are placed.
+    Upgrade(Upgrade, HashMap<Name, VersionedReactiveAddress>),
 }
 
 struct TransactionState {
     id: TxId,
-    pending_locks: HashMap<Address, ExpectedVersions>,
+    requester: Address,
+    /// Nodes the transaction might write to. These are locked exclusively even when they are only
+    /// read from, since a shared lock cannot be upgraded.
+    may_write: HashSet<Address>,
+    pending_locks: HashMap<Address, LockKind>,
     locks: HashMap<Address, Lock>,
+    /// The values read so far, or `None` for reads that have been requested but not answered. A
+    /// reactive written by the transaction holds the value last written to it.
+    reads: HashMap<ReactiveAddress, Option<StampedValue>>,
+    /// The reactives written by the transaction so far.
+    written: HashSet<ReactiveAddress>,
+    /// The merged basis of the committed values read so far. Values the transaction wrote itself
+    /// are left out, since their stamps are iterations that only exist once it commits, and a
+    /// later read based on them would wait for them forever.
+    basis: BasisStamp,
+    /// The reactives an upgrade configures on each node, or `None` for those it deletes. Nodes are
+    /// sent their configuration once evaluation is complete.
+    configurations: HashMap<Address, HashMap<ReactiveId, Option<ReactiveConfiguration>>>,
+    /// The definitions an upgrade declares, which the coordinator keeps to check later upgrades.
+    definitions: HashMap<VersionedReactiveAddress, Expr>,
+    /// Set once evaluation is complete and the locked nodes have been asked to prepare.
+    preparing: Option<Preparing>,
+}
+
+struct Lock {
+    kind: LockKind,
 }
 
-type ExpectedVersions = HashMap<ReactiveId, Version>;
+struct Preparing {
+    awaiting: HashSet<Address>,
+    basis: BasisStamp,
+}
+
+struct EvalContext<'a, 'c> {
+    state: &'a mut TransactionState,
+    ctx: &'a Context<'c>,
+}
 
-struct Lock {}
+struct UpgradeContext<'a, 'c> {
+    state: &'a mut TransactionState,
+    placed: &'a HashMap<Name, VersionedReactiveAddress>,
+    ctx: &'a Context<'c>,
+}
 
 impl Transaction {
-    pub fn new(id: TxId, kind: TransactionKind) -> Transaction {
-        Transaction {
-            kind: kind,
-            state: TransactionState::new(id),
+    /// Creates a transaction, given the declaration of each live reactive. Ill-typed actions and
+    /// upgrades are rejected here, before any lock is requested on their behalf, as are upgrades
+    /// that would leave a live definition ill-typed.
+    pub fn new(
+        id: TxId,
+        requester: Address,
+        kind: TransactionKind,
+        reactives: &HashMap<VersionedReactiveAddress, Declaration>,
+    ) -> Result<Transaction, TransactionError> {
+        let types = |address: &VersionedReactiveAddress| {
+            reactives
+                .get(address)
+                .map(|declaration| declaration.ty.clone())
+        };
+
+        let declared = match &kind {
+            TransactionKind::Action(action) => {
+                action.check_types(types).map_err(TransactionError::Type)?;
+                HashMap::new()
+            }
+            TransactionKind::Upgrade(upgrade, _) => upgrade
+                .check_types(types, |address| readers(reactives, address))
+                .map_err(TransactionError::Type)?,
+        };
+
+        Ok(Transaction {
+            submitted: kind.clone(),
+            kind,
+            declared,
+            state: TransactionState::new(id, requester),
+        })
+    }
+
+    /// Evaluates the transaction as far as the locks and reads obtained so far allow, and starts
+    /// committing it once evaluation is complete.
+    ///
+    /// Returns true once the transaction has ended, after which it can be forgotten.
+    pub fn eval(&mut self, ctx: &Context) -> bool {
+        if self.state.preparing.is_some() {
+            return false;
+        }
+
+        let result = match &mut self.kind {
+            TransactionKind::Action(action) => {
+                let mut definite_writes = Vec::new();
+                action.visit_writes(&mut |address, definite| {
+                    self.state.may_write.insert(address.address.clone());
+                    if definite {
+                        definite_writes.push(address.address.clone());
+                    }
+                });
+
+                // Lock the nodes that will certainly be written to right away, rather than once
+                // their values have been computed.
+                for address in definite_writes {
+                    self.state.lock(&address, ctx);
+                }
+
+                action
+                    .eval(&mut EvalContext {
+                        state: &mut self.state,
+                        ctx,
+                    })
+                    .map(|()| matches!(action, Action::Nil))
+                    .map_err(|error| error.map_reactive(Ident::Existing))
+            }
+            TransactionKind::Upgrade(upgrade, placed) => {
+                // Every node the upgrade configures is locked exclusively right away, as are the
+                // nodes of the reactives its definitions read, which must register them as
+                // importers. The submitted upgrade is visited since evaluation consumes it.
+                let TransactionKind::Upgrade(submitted, _) = &self.submitted else {
+                    unreachable!()
+                };
+                let mut nodes = HashSet::new();
+                visit_configured(submitted, placed, &mut |address| {
+                    nodes.insert(address.clone());
+                });
+                for address in &nodes {
+                    self.state.may_write.insert(address.clone());
+                    self.state.lock(address, ctx);
+                }
+
+                upgrade
+                    .eval(&mut UpgradeContext {
+                        state: &mut self.state,
+                        placed,
+                        ctx,
+                    })
+                    .map(|()| {
+                        matches!(upgrade, Upgrade::Nil)
+                            && nodes
+                                .iter()
+                                .all(|address| self.state.locks.contains_key(address))
+                    })
+            }
+        };
+
+        match result {
+            Ok(true) => self.prepare(ctx),
+            Ok(false) => false,
+            Err(error) => {
+                self.abort(TransactionError::Eval(error), ctx);
+                true
+            }
+        }
+    }
+
+    pub fn lock_granted(&mut self, address: Address, ctx: &Context) {
+        let Some(kind) = self.state.pending_locks.remove(&address) else {
+            panic!("we were granted a lock we did not request")
+        };
+
+        // A lock that is granted once evaluation is complete was only requested in case of a
+        // write that did not happen, so it is of no use anymore.
+        if self.state.preparing.is_some() {
+            ctx.send(
+                &address,
+                Message::Abort {
+                    txid: self.state.id.clone(),
+                },
+            );
+            return;
+        }
+
+        self.state.locks.insert(address, Lock { kind });
+    }
+
+    pub fn read_result(&mut self, reactive: ReactiveAddress, value: StampedValue) {
+        // Results of reads requested before the transaction was preempted are not awaited.
+        if let Some(read @ None) = self.state.reads.get_mut(&reactive) {
+            if !self.state.written.contains(&reactive) {
+                self.state.basis.merge_from(&value.basis);
+            }
+            *read = Some(value);
+        }
+    }
+
+    /// Records that a locked node has prepared to commit.
+    ///
+    /// Returns true once every node has prepared and the transaction has committed.
+    pub fn commit_prepared(&mut self, address: Address, basis: BasisStamp, ctx: &Context) -> bool {
+        let preparing = self
+            .state
+            .preparing
+            .as_mut()
+            .expect("a node prepared to commit a transaction that is not committing");
+
+        preparing.awaiting.remove(&address);
+        preparing.basis.merge_from(&basis);
+        if !preparing.awaiting.is_empty() {
+            return false;
         }
+
+        let basis = preparing.basis.clone();
+        for address in self.state.locks.keys() {
+            ctx.send(
+                address,
+                Message::Commit {
+                    txid: self.state.id.clone(),
+                    basis: basis.clone(),
+                },
+            );
+        }
+
+        ctx.send(
+            &self.state.requester,
+            Message::Committed {
+                txid: self.state.id.clone(),
+                basis,
+            },
+        );
+
+        true
+    }
+
+    /// Releases the locks held by the transaction so that an older transaction can take them,
+    /// and starts evaluating it over. The locks that are still pending remain requested.
+    pub fn preempt(&mut self, ctx: &Context) {
+        // Once committing, the transaction is about to release its locks anyway.
+        if self.state.preparing.is_some() {
+            return;
+        }
+
+        self.state.release(ctx);
+        self.state.reads.clear();
+        self.state.written.clear();
+        self.state.basis = BasisStamp::empty();
+        self.state.configurations.clear();
+        self.state.definitions.clear();
+        self.kind = self.submitted.clone();
+    }
+
+    /// The reactives a committed upgrade declared, with their declarations, or `None` for those it
+    /// deleted.
+    pub fn changes(&self) -> Vec<(VersionedReactiveAddress, Option<Declaration>)> {
+        let TransactionKind::Upgrade(_, placed) = &self.submitted else {
+            return Vec::new();
+        };
+
+        self.declared
+            .iter()
+            .map(|(ident, ty)| {
+                let address = resolve(placed, ident);
+                let declaration = ty.clone().map(|ty| Declaration {
+                    ty,
+                    definition: self.state.definitions.get(&address).cloned(),
+                });
+                (address, declaration)
+            })
+            .collect()
+    }
+
+    fn prepare(&mut self, ctx: &Context) -> bool {
+        // The nodes an upgrade configures are locked, so they receive their configuration before
+        // they are asked to prepare.
+        for (address, reactives) in mem::take(&mut self.state.configurations) {
+            ctx.send(
+                &address,
+                Message::Configure {
+                    txid: self.state.id.clone(),
+                    imports: HashMap::new(),
+                    reactives,
+                    exports: HashMap::new(),
+                },
+            );
+        }
+
+        if self.state.locks.is_empty() {
+            ctx.send(
+                &self.state.requester,
+                Message::Committed {
+                    txid: self.state.id.clone(),
+                    basis: BasisStamp::empty(),
+                },
+            );
+            return true;
+        }
+
+        for address in self.state.locks.keys() {
+            ctx.send(
+                address,
+                Message::PrepareCommit {
+                    txid: self.state.id.clone(),
+                },
+            );
+        }
+
+        self.state.preparing = Some(Preparing {
+            awaiting: self.state.locks.keys().cloned().collect(),
+            basis: BasisStamp::empty(),
+        });
+
+        false
+    }
+
+    /// Aborts the transaction, releasing its locks and reporting `error` to its requester.
+    pub fn abort(&mut self, error: TransactionError, ctx: &Context) {
+        self.state.release(ctx);
+        ctx.send(
+            &self.state.requester,
+            Message::Aborted {
+                txid: self.state.id.clone(),
+                error,
+            },
+        );
     }
 }
 
 impl TransactionState {
-    fn new(id: TxId) -> TransactionState {
+    fn new(id: TxId, requester: Address) -> TransactionState {
         TransactionState {
             id,
+            requester,
+            may_write: HashSet::new(),
             pending_locks: HashMap::new(),
             locks: HashMap::new(),
+            reads: HashMap::new(),
+            written: HashSet::new(),
+            basis: BasisStamp::empty(),
+            configurations: HashMap::new(),
+            definitions: HashMap::new(),
+            preparing: None,
+        }
+    }
+
+    /// Requests a lock on the node at `address` unless one is held or pending already.
+    ///
+    /// Returns true if the lock is held.
+    fn lock(&mut self, address: &Address, ctx: &Context) -> bool {
+        if self.locks.contains_key(address) {
+            return true;
+        }
+
+        if !self.pending_locks.contains_key(address) {
+            let kind = if self.may_write.contains(address) {
+                LockKind::Exclusive
+            } else {
+                LockKind::Shared
+            };
+
+            ctx.send(
+                address,
+                Message::Lock {
+                    txid: self.id.clone(),
+                    kind,
+                },
+            );
+            self.pending_locks.insert(address.clone(), kind);
+        }
+
+        false
+    }
+
+    /// Aborts every held lock.
+    fn release(&mut self, ctx: &Context) {
+        for address in self.locks.keys() {
+            ctx.send(
+                address,
+                Message::Abort {
+                    txid: self.id.clone(),
+                },
+            );
+        }
+
+        self.locks.clear();
+    }
+
+    fn read(
+        &mut self,
+        address: &VersionedReactiveAddress,
+        ctx: &Context,
+    ) -> Result<Option<&Value>, EvalError> {
+        let reactive = ReactiveAddress {
+            address: address.address.clone(),
+            id: address.id,
+        };
+
+        if self.reads.contains_key(&reactive) {
+            return Ok(self.reads[&reactive].as_ref().map(|value| &value.value));
+        }
+
+        if !self.lock(&address.address, ctx) {
+            return Ok(None);
+        }
+
+        // Reading no earlier than the values read so far keeps the reads consistent with each
+        // other on the roots they share.
+        ctx.send(
+            &address.address,
+            Message::Read {
+                txid: self.id.clone(),
+                reactive: address.id,
+                basis: self.basis.clone(),
+            },
+        );
+        self.reads.insert(reactive, None);
+
+        Ok(None)
+    }
+
+    fn write(&mut self, address: &VersionedReactiveAddress, value: &Value, ctx: &Context) -> bool {
+        if !self.lock(&address.address, ctx) {
+            // cannot perform this write until the node is locked
+            return false;
+        }
+
+        debug_assert!(matches!(
+            self.locks[&address.address].kind,
+            LockKind::Exclusive
+        ));
+
+        ctx.send(
+            &address.address,
+            Message::Write {
+                txid: self.id.clone(),
+                reactive: address.id,
+                value: value.clone(),
+            },
+        );
+
+        // Later reads of the reactive see the value written rather than the one it held before.
+        // The value is computed from the values read so far, so it is based on what they are
+        // based on, and the reactive will hold it at its next iteration. That iteration is only
+        // known here if the reactive was read or written before; otherwise later reads ask its
+        // node, which reads the write back with the right stamp.
+        let reactive = ReactiveAddress {
+            address: address.address.clone(),
+            id: address.id,
+        };
+        let first = self.written.insert(reactive.clone());
+        let next = match self.reads.get(&reactive) {
+            Some(Some(prior)) if first => prior.basis.latest(&reactive).increment(),
+            Some(Some(prior)) => prior.basis.latest(&reactive),
+            _ => {
+                self.reads.remove(&reactive);
+                return true;
+            }
+        };
+
+        let mut basis = self.basis.clone();
+        basis.add(reactive.clone(), next);
+        let value = StampedValue {
+            value: value.clone(),
+            basis,
+        };
+        self.reads.insert(reactive, Some(value));
+
+        true
+    }
+}
+
+impl ExprEvalContext<VersionedReactiveAddress> for EvalContext<'_, '_> {
+    fn read(&mut self, address: &VersionedReactiveAddress) -> Result<Option<&Value>, EvalError> {
+        self.state.read(address, self.ctx)
+    }
+}
+
+impl ActionEvalContext for EvalContext<'_, '_> {
+    fn write(
+        &mut self,
+        address: &VersionedReactiveAddress,
+        value: &Value,
+    ) -> Result<bool, EvalError> {
+        Ok(self.state.write(address, value, self.ctx))
+    }
+}
+
+impl UpgradeContext<'_, '_> {
+    fn resolve(&self, ident: &Ident) -> VersionedReactiveAddress {
+        resolve(self.placed, ident)
+    }
+
+    fn configure(
+        &mut self,
+        address: VersionedReactiveAddress,
+        configuration: Option<ReactiveConfiguration>,
+    ) {
+        self.state
+            .configurations
+            .entry(address.address)
+            .or_default()
+            .insert(address.id, configuration);
+    }
+
+    fn configured(
+        &self,
+        address: &VersionedReactiveAddress,
+    ) -> Option<&Option<ReactiveConfiguration>> {
+        self.state
+            .configurations
+            .get(&address.address)
+            .and_then(|reactives| reactives.get(&address.id))
+    }
+}
+
+impl ExprEvalContext<Ident> for UpgradeContext<'_, '_> {
+    fn read(&mut self, ident: &Ident) -> Result<Option<&Value>, EvalError<Ident>> {
+        // Reactives the upgrade has configured already are read as configured. Definitions have no
+        // value until the nodes evaluate them once the upgrade commits.
+        let address = self.resolve(ident);
+        match self.configured(&address) {
+            Some(Some(ReactiveConfiguration::Variable { .. })) => {}
+            Some(Some(_)) => return Err(EvalError::Stalled),
+            Some(None) => return Err(EvalError::MissingReactive(ident.clone())),
+            None => {
+                return self
+                    .state
+                    .read(&address, self.ctx)
+                    .map_err(|error| error.map_reactive(Ident::Existing))
+            }
+        }
+
+        let Some(Some(ReactiveConfiguration::Variable { value })) = self.configured(&address)
+        else {
+            unreachable!()
+        };
+        Ok(Some(&value.value))
+    }
+}
+
+impl UpgradeEvalContext for UpgradeContext<'_, '_> {
+    fn var(&mut self, ident: Ident, value: Value) {
+        let address = self.resolve(&ident);
+        self.state.definitions.remove(&address);
+        let value = StampedValue {
+            value,
+            basis: BasisStamp::empty(),
+        };
+        self.configure(address, Some(ReactiveConfiguration::Variable { value }));
+    }
+
+    fn def(&mut self, ident: Ident, expr: Expr<Ident>) {
+        let address = self.resolve(&ident);
+        let expr = expr.map_reads(&mut |ident| self.resolve(&ident));
+        self.state.definitions.insert(address.clone(), expr.clone());
+        let expr = expr.map_reads(&mut unversioned);
+        let options = DefinitionOptions::default();
+        self.configure(
+            address,
+            Some(ReactiveConfiguration::Definition { expr, options }),
+        );
+    }
+
+    fn del(&mut self, address: VersionedReactiveAddress) {
+        self.state.definitions.remove(&address);
+        self.configure(address, None);
+    }
+
+    fn migrate(&mut self, address: VersionedReactiveAddress, migration: Expr<Ident>) {
+        self.state.definitions.remove(&address);
+        let migration = migration.map_reads(&mut |ident| unversioned(self.resolve(&ident)));
+        self.configure(
+            address,
+            Some(ReactiveConfiguration::Migration { migration }),
+        );
+    }
+}
+
+/// The live definitions reading the reactive at `address`.
+fn readers(
+    reactives: &HashMap<VersionedReactiveAddress, Declaration>,
+    address: &VersionedReactiveAddress,
+) -> Vec<(VersionedReactiveAddress, Expr<Ident>)> {
+    reactives
+        .iter()
+        .filter_map(|(reader, declaration)| {
+            let expr = declaration.definition.as_ref()?;
+            let mut reads = false;
+            expr.visit_reads(&mut |read, _definite| reads |= read == address);
+            reads.then(|| (reader.clone(), expr.clone().map_reads(&mut Ident::Existing)))
+        })
+        .collect()
+}
+
+/// The address of `ident`, placing new reactives as the manager chose to.
+fn resolve(
+    placed: &HashMap<Name, VersionedReactiveAddress>,
+    ident: &Ident,
+) -> VersionedReactiveAddress {
+    match ident {
+        Ident::Existing(address) => address.clone(),
+        Ident::New(name) => placed[name].clone(),
+    }
+}
+
+fn unversioned(address: VersionedReactiveAddress) -> ReactiveAddress {
+    ReactiveAddress {
+        address: address.address,
+        id: address.id,
+    }
+}
+
+/// Visits the nodes an upgrade configures, along with the nodes of the reactives its definitions
+/// read.
+fn visit_configured(
+    upgrade: &Upgrade,
+    placed: &HashMap<Name, VersionedReactiveAddress>,
+    visitor: &mut impl FnMut(&Address),
+) {
+    match upgrade {
+        Upgrade::Seq(a, b) => {
+            visit_configured(a, placed, visitor);
+            visit_configured(b, placed, visitor);
+        }
+        Upgrade::Var(ident, _) => visitor(&resolve(placed, ident).address),
+        Upgrade::Def(ident, expr) => {
+            visitor(&resolve(placed, ident).address);
+            expr.visit_reads(&mut |ident, _definite| visitor(&resolve(placed, ident).address));
         }
+        Upgrade::Del(address) | Upgrade::Migrate(address, _) => visitor(&address.address),
+        Upgrade::Nil => {}
     }
 }
@@ -1,12 +1,12 @@
 use std::{
-    cmp::Ordering,
     collections::{hash_map::Entry, HashMap, HashSet},
+    num::NonZeroUsize,
     time::SystemTime,
 };
 
 use crate::{
     actor::{Address, Version},
-    expr::{Action, Expr, Name, Type, Upgrade, Value},
+    expr::{eval::EvalError, typecheck::TypeError, Action, Expr, Ident, Name, Upgrade, Value},
     node::{Import, ReactiveAddress, ReactiveId},
 };
 
@@ -18,10 +18,51 @@ pub enum Message {
     },
 
     // propagation
-    Propagate {
-        sender: ReactiveAddress,
+    PropagateBatch {
+        updates: Vec<(ReactiveAddress, StampedValue)>,
+    },
+
+    // observation - streams the values of a reactive to arbitrary actors
+    Subscribe {
+        reactive: ReactiveId,
+        subscriber: Address,
+    },
+    Unsubscribe {
+        reactive: ReactiveId,
+        subscriber: Address,
+    },
+    ValueChanged {
+        reactive: ReactiveAddress,
         value: StampedValue,
     },
+    Diagnose {
+        reactive: ReactiveId,
+        requester: Address,
+    },
+    /// The state of a reactive's inputs. A reactive that could not be found is reported as having
+    /// no inputs and not being pending.
+    Diagnostics {
+        reactive: ReactiveAddress,
+        inputs: HashMap<ReactiveAddress, InputDiagnostics>,
+        blocked_on: Option<ReactiveAddress>,
+        pending: bool,
+        /// Why the latest evaluation of the reactive's definition failed, if it did.
+        error: Option<EvalError<ReactiveAddress>>,
+    },
+    RetainHistory {
+        reactive: ReactiveId,
+        limit: usize,
+    },
+    ReadAt {
+        reactive: ReactiveId,
+        basis: BasisStamp,
+        requester: Address,
+    },
+    ValueAt {
+        reactive: ReactiveAddress,
+        basis: BasisStamp,
+        value: Option<StampedValue>,
+    },
 
     // transaction - initial lock request
     Lock {
@@ -51,6 +92,30 @@ pub enum Message {
         reactive: ReactiveId,
         value: Value,
     },
+    /// Sent by a node configuring a reactive that reads `reactive` of the receiving node, which
+    /// answers with [`Message::ImporterRegistered`]. The coordinator of the transaction has to
+    /// lock the exporter exclusively as well, since the export is configured under that lock;
+    /// registrations arriving before it is granted are queued until then.
+    RegisterImporter {
+        txid: TxId,
+        reactive: ReactiveId,
+        importer: Address,
+        mode: PropagationMode,
+    },
+    ImporterRegistered {
+        txid: TxId,
+        reactive: ReactiveAddress,
+        roots: HashSet<ReactiveAddress>,
+        basis: BasisStamp,
+    },
+    UpdateRoots {
+        txid: TxId,
+        reactive: ReactiveAddress,
+        roots: HashSet<ReactiveAddress>,
+    },
+    RootsUpdated {
+        txid: TxId,
+    },
     ReadConfiguration {
         txid: TxId,
     },
@@ -61,7 +126,7 @@ pub enum Message {
         txid: TxId,
         imports: HashMap<ReactiveAddress, Option<ImportConfiguration>>,
         reactives: HashMap<ReactiveId, Option<ReactiveConfiguration>>,
-        exports: HashMap<ReactiveId, HashSet<Address>>,
+        exports: HashMap<ReactiveId, HashMap<Address, PropagationMode>>,
     },
     Retire {
         txid: TxId,
@@ -82,35 +147,90 @@ pub enum Message {
         txid: TxId,
         basis: BasisStamp,
     },
+    /// Sent instead of [`Message::CommitPrepared`] by a node on which a migration or definition
+    /// configured by the transaction fails to evaluate, so that the coordinator aborts it.
+    PrepareFailed {
+        address: Address,
+        txid: TxId,
+        error: EvalError<ReactiveAddress>,
+    },
     Commit {
         txid: TxId,
         basis: BasisStamp,
     },
 
+    // transaction - root changes staged on importers that hold no lock for the transaction
+    CommitRoots {
+        txid: TxId,
+    },
+    AbortRoots {
+        txid: TxId,
+    },
+
     // messages sent/received by managers
     Do {
         action: Action,
+        requester: Address,
+    },
+    /// Reports to the requester of a transaction that it committed.
+    Committed {
+        txid: TxId,
+        basis: BasisStamp,
+    },
+    /// Reports to the requester of a transaction that it was aborted, and why.
+    Aborted {
+        txid: TxId,
+        error: TransactionError,
     },
     Upgrade {
         upgrade: Upgrade,
+        requester: Address,
     },
     Directory {
         state: DirectoryState,
     },
 }
 
+/// Why a transaction was aborted by its coordinator.
+#[derive(Debug, Clone)]
+pub enum TransactionError {
+    /// The transaction was rejected before it took any locks.
+    Type(TypeError),
+    /// Evaluating the action, or the variables declared by the upgrade, failed.
+    Eval(EvalError<Ident>),
+    /// A node could not prepare to commit, since a migration or definition configured by the
+    /// transaction fails to evaluate there.
+    Prepare {
+        address: Address,
+        error: EvalError<ReactiveAddress>,
+    },
+    /// A node the transaction involves could not be reached.
+    Unreachable,
+}
+
 #[derive(Debug, Clone)]
 pub struct ImportConfiguration {
     pub roots: HashSet<ReactiveAddress>,
 }
 
+/// How the values of an export are propagated to one of its importers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PropagationMode {
+    /// Every value is sent, so the importer observes each iteration of the export.
+    #[default]
+    All,
+    /// Only the latest value produced by a propagation pass is sent. Intermediate iterations are
+    /// skipped, which is still glitch-free since the latest value's basis subsumes theirs.
+    Latest,
+}
+
 #[derive(Debug, Clone)]
 pub struct StampedValue {
     pub value: Value,
     pub basis: BasisStamp,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct BasisStamp {
     pub roots: HashMap<ReactiveAddress, Iteration>,
 }
@@ -151,6 +271,12 @@ impl BasisStamp {
         self.roots.clear();
     }
 
+    pub fn prec_eq(&self, other: &BasisStamp) -> bool {
+        self.roots
+            .iter()
+            .all(|(address, iteration)| *iteration <= other.latest(address))
+    }
+
     pub fn prec_eq_wrt_roots(&self, other: &BasisStamp, roots: &HashSet<ReactiveAddress>) -> bool {
         for root in roots {
             if self.latest(root) > other.latest(root) {
@@ -176,8 +302,57 @@ impl Iteration {
 
 #[derive(Debug, Clone)]
 pub enum ReactiveConfiguration {
-    Variable { value: StampedValue },
-    Definition { expr: Expr<ReactiveAddress> },
+    Variable {
+        value: StampedValue,
+    },
+    Definition {
+        expr: Expr<ReactiveAddress>,
+        options: DefinitionOptions,
+    },
+    /// A definition whose state is threaded through each applied batch of updates. The `step`
+    /// computes the next state, reading the prior state through the fold's own address. Note
+    /// that updates discarded by a queue limit never reach the step.
+    Fold {
+        init: Value,
+        step: Expr<ReactiveAddress>,
+        options: DefinitionOptions,
+    },
+    /// Turns an existing reactive into a variable whose value is computed by `migration` from
+    /// its current value, which the migration reads through the reactive's own address. Unlike
+    /// configuring a fresh variable, the migrated value is applied like a write, so the
+    /// reactive's iterations and the reads it has served carry over.
+    Migration {
+        migration: Expr<ReactiveAddress>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DefinitionOptions {
+    /// The maximum number of updates queued per input. When exceeded, the oldest updates are
+    /// discarded, skipping intermediate values rather than letting the queue grow without bound
+    /// while a batch cannot be formed. At least the latest update of each input is always kept.
+    pub queue_limit: Option<NonZeroUsize>,
+    /// Whether a recomputation yielding the same value as before is suppressed. The basis of the
+    /// definition still advances and is passed on to consumers so that their batches can close,
+    /// but no new value is reported to subscribers.
+    pub suppress_unchanged: bool,
+    /// The value presented to consumers, subscribers and readers while the definition is pending,
+    /// that is, before it has computed a value from its inputs. Without a default, reads of a
+    /// pending definition wait until it has a value.
+    pub default: Option<Value>,
+    /// How the values of remote inputs are propagated to this node. The mode is requested from
+    /// the exporter when an input is first imported, and is shared by every reactive of the node
+    /// reading that input, so all of them must tolerate skipped iterations for it to be
+    /// [`PropagationMode::Latest`].
+    pub propagation: PropagationMode,
+}
+
+#[derive(Debug, Clone)]
+pub struct InputDiagnostics {
+    /// The number of updates waiting to be included in a batch.
+    pub queued: usize,
+    /// The number of updates discarded by compaction so far.
+    pub dropped: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -211,6 +386,12 @@ pub struct MonotonicTimestampGenerator {
     latest: Timestamp,
 }
 
+impl Default for MonotonicTimestampGenerator {
+    fn default() -> MonotonicTimestampGenerator {
+        MonotonicTimestampGenerator::new()
+    }
+}
+
 impl MonotonicTimestampGenerator {
     pub fn new() -> MonotonicTimestampGenerator {
         MonotonicTimestampGenerator {
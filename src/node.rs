@@ -1,15 +1,26 @@
-use std::collections::{btree_map::Entry, hash_map, BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::{
+    btree_map::Entry, hash_map, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque,
+};
 
 use held_locks::{ExclusiveLockState, HeldLocks, Read, SharedLockState};
 use reactive::Reactive;
 
 use crate::{
     actor::{Actor, Address, Context, Version},
-    message::{BasisStamp, Iteration, LockKind, Message, StampedValue, TxId},
+    expr::{
+        eval::{EvalError, ExprEvalContext},
+        Value,
+    },
+    message::{
+        BasisStamp, ImportConfiguration, Iteration, LockKind, Message, PropagationMode,
+        ReactiveConfiguration, StampedValue, TxId,
+    },
 };
 
 mod held_locks;
 mod reactive;
+#[cfg(test)]
+pub mod tests;
 
 pub struct Node {
     queued: BTreeMap<TxId, LockKind>,
@@ -20,10 +31,24 @@ pub struct Node {
     reactives: HashMap<ReactiveId, Reactive>,
     iterations: HashMap<ReactiveId, Iteration>,
     exports: HashMap<ReactiveId, Export>,
+    /// External actors observing the values of local reactives.
+    subscribers: HashMap<ReactiveId, HashSet<Address>>,
+    /// Import root changes announced by transactions that hold no lock on this node. These are
+    /// tracked in the same form as the changes made under a held exclusive lock.
+    staged_roots: HashMap<TxId, ExclusiveLockState>,
+    /// New roots of imports from staged changes that committed while this node held locks. They
+    /// are applied once no lock is held anymore, so that no transaction observes the roots of
+    /// its imports change.
+    committed_roots: HashMap<ReactiveAddress, HashSet<ReactiveAddress>>,
+    /// Importer registrations received before the exclusive lock of their transaction was
+    /// granted, which are handled once it is.
+    queued_registrations: HashMap<TxId, Vec<(ReactiveId, Address, PropagationMode)>>,
 
     subscriptions: HashMap<ReactiveId, HashSet<ReactiveId>>,
     roots: HashMap<ReactiveId, HashSet<ReactiveAddress>>,
     topo: VecDeque<ReactiveId>,
+    /// The position of each reactive in `topo`, used to order the dirty queue in `propagate`.
+    ranks: HashMap<ReactiveId, usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -46,18 +71,35 @@ pub struct ReactiveId(pub usize);
 pub struct Import {
     pub roots: HashSet<ReactiveAddress>,
     pub importers: HashSet<ReactiveId>,
+    /// The latest value received from the exporter, used to seed reactives that start importing it.
+    pub value: Option<StampedValue>,
 }
 
 pub struct Export {
     /// Exports' roots only contain cross-network roots, since they are themselves sources standing
     /// in for each of the local reactive state variables (if any).
     pub roots: HashSet<ReactiveAddress>,
-    pub importers: HashSet<Address>,
+    pub importers: HashMap<Address, PropagationMode>,
 }
 
 #[derive(Debug)]
 struct Cyclical;
 
+/// The values reactives hold as an exclusive lock prepares to commit, with the lock's own writes
+/// applied. Reactives the lock reconfigures have no value yet.
+struct PreparedValues<'a> {
+    me: &'a Address,
+    reactives: &'a HashMap<ReactiveId, Reactive>,
+    imports: &'a HashMap<ReactiveAddress, Import>,
+    exclusive: &'a ExclusiveLockState,
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        Node::new()
+    }
+}
+
 impl Node {
     pub fn new() -> Node {
         Node {
@@ -68,13 +110,22 @@ impl Node {
             reactives: HashMap::new(),
             iterations: HashMap::new(),
             exports: HashMap::new(),
+            subscribers: HashMap::new(),
+            staged_roots: HashMap::new(),
+            committed_roots: HashMap::new(),
+            queued_registrations: HashMap::new(),
             subscriptions: HashMap::new(),
             roots: HashMap::new(),
             topo: VecDeque::new(),
+            ranks: HashMap::new(),
         }
     }
 
     fn grant_locks(&mut self, ctx: &Context) {
+        if let HeldLocks::None = self.held {
+            self.apply_committed_roots(ctx);
+        }
+
         let mut granted = Vec::new();
 
         for (txid, kind) in self.queued.iter() {
@@ -91,7 +142,7 @@ impl Node {
                         *held = HeldLocks::Exclusive(
                             txid.clone(),
                             SharedLockState::default(),
-                            ExclusiveLockState::default(),
+                            Box::default(),
                         );
                     }
                 },
@@ -140,18 +191,27 @@ impl Node {
                     address: ctx.me().clone(),
                 },
             );
+
+            if self.held.exclusive(&txid).is_some() {
+                for (reactive, importer, mode) in
+                    self.queued_registrations.remove(&txid).unwrap_or_default()
+                {
+                    self.register_importer(&txid, reactive, importer, mode, ctx);
+                }
+            }
         }
     }
 
     fn commit<'a>(
         &mut self,
+        txid: &TxId,
         mut basis: BasisStamp,
         shared_state: SharedLockState,
-        exclusive_state: ExclusiveLockState,
+        mut exclusive_state: ExclusiveLockState,
         ctx: Context<'a>,
     ) -> Option<Context<'a>> {
         for (id, read) in shared_state.reads {
-            if !read.complete.is_empty() {
+            if read.complete.is_some() {
                 self.reactives.get_mut(&id).unwrap().finished_read(&basis);
             }
         }
@@ -180,17 +240,26 @@ impl Node {
             });
         }
 
+        let imports_changed = !exclusive_state.imports.is_empty();
+
         for (address, config) in exclusive_state.imports {
             if let Some(config) = config {
+                // Roots committed by an earlier transaction are superseded by these.
+                self.committed_roots.remove(&address);
+
                 match self.imports.entry(address) {
                     hash_map::Entry::Vacant(e) => {
                         e.insert(Import {
                             roots: config.roots,
                             importers: HashSet::new(),
+                            value: None,
                         });
                     }
                     hash_map::Entry::Occupied(e) => {
-                        e.into_mut().roots = config.roots;
+                        // Importers that were stalled on the prior roots may now form a batch.
+                        let import = e.into_mut();
+                        import.roots = config.roots;
+                        modified.extend(import.importers.iter().copied());
                     }
                 }
             } else if let Some(removed) = self.imports.remove(&address) {
@@ -207,17 +276,43 @@ impl Node {
 
         let reactives_changed = !exclusive_state.reactives.is_empty();
 
+        // Newly added inputs of a reactive will only receive updates once their source changes
+        // again, so they are seeded with the source's current value (if any) after configuration.
+        let mut seeds = Vec::new();
+
         for (id, config) in exclusive_state.reactives {
             if let Some(config) = config {
-                self.subscriptions.entry(id).or_insert_with(HashSet::new);
+                self.subscriptions.entry(id).or_default();
                 self.iterations.entry(id).or_insert(Iteration::ZERO);
 
+                let address = ReactiveAddress {
+                    address: ctx.me().clone(),
+                    id,
+                };
+
                 let (reactive, mut prior_inputs) = match self.reactives.entry(id) {
-                    hash_map::Entry::Vacant(e) => (e.insert(Reactive::new(config)), HashSet::new()),
+                    hash_map::Entry::Vacant(e) => {
+                        (e.insert(Reactive::new(address, config)), HashSet::new())
+                    }
                     hash_map::Entry::Occupied(e) => {
                         let reactive = e.into_mut();
                         let prior_inputs = reactive.inputs().cloned().collect::<HashSet<_>>();
-                        reactive.reconfigure(config);
+
+                        // Like a direct write, a migrated value is stamped with the reactive's
+                        // prepared iteration.
+                        let mut basis = basis.clone();
+                        if let Some(iteration) = exclusive_state.prepared_iterations.get(&id) {
+                            basis.roots.insert(address.clone(), *iteration);
+                        }
+
+                        // Migrations were evaluated when preparing, which is where they fail.
+                        match exclusive_state.migrated.remove(&id) {
+                            Some(value) => reactive.migrate(value, basis),
+                            None => reactive.reconfigure(address, config, basis).expect(
+                                "migrations of existing reactives are evaluated when preparing",
+                            ),
+                        }
+
                         (reactive, prior_inputs)
                     }
                 };
@@ -229,10 +324,7 @@ impl Node {
                     }
 
                     if &input.address == ctx.me() {
-                        self.subscriptions
-                            .entry(input.id)
-                            .or_insert_with(HashSet::new)
-                            .insert(id);
+                        self.subscriptions.entry(input.id).or_default().insert(id);
                     } else {
                         self.imports
                             .get_mut(input)
@@ -240,6 +332,8 @@ impl Node {
                             .importers
                             .insert(id);
                     }
+
+                    seeds.push((id, input.clone()));
                 }
 
                 for removed in prior_inputs {
@@ -257,6 +351,7 @@ impl Node {
                 modified.insert(id);
             } else if let Some(removed) = self.reactives.remove(&id) {
                 self.iterations.remove(&id);
+                self.subscribers.remove(&id);
 
                 for input in removed.inputs() {
                     if &input.address == ctx.me() {
@@ -268,15 +363,58 @@ impl Node {
             }
         }
 
+        for (id, input) in seeds {
+            // Sources modified by this transaction will propagate their value regardless.
+            if &input.address == ctx.me() && modified.contains(&input.id) {
+                continue;
+            }
+
+            let value = if &input.address == ctx.me() {
+                self.reactives.get(&input.id).and_then(|r| r.value())
+            } else {
+                self.imports[&input].value.as_ref()
+            };
+
+            if let (Some(value), Some(reactive)) = (value.cloned(), self.reactives.get_mut(&id)) {
+                reactive.add_update(input, value);
+            }
+        }
+
+        // Values held back for imports created by this transaction are delivered now that the
+        // imports exist. Only values for imports the transaction ended up not creating are left
+        // without a recipient.
+        for (sender, value) in exclusive_state.propagations {
+            modified.extend(self.receive_propagation(sender, value));
+        }
+
         if reactives_changed {
             self.recompute_topo();
+        }
+
+        if reactives_changed || imports_changed {
             self.recompute_roots(&ctx);
         }
 
+        for addr in &exclusive_state.root_importers {
+            ctx.send(addr, Message::CommitRoots { txid: txid.clone() });
+        }
+
+        let mut handshakes = HashMap::<_, Vec<_>>::new();
+
         for (id, addrs) in exclusive_state.exports {
             if addrs.is_empty() {
                 self.exports.remove(&id);
             } else {
+                for addr in addrs.keys() {
+                    if !self
+                        .exports
+                        .get(&id)
+                        .is_some_and(|e| e.importers.contains_key(addr))
+                    {
+                        handshakes.entry(addr.clone()).or_default().push(id);
+                    }
+                }
+
                 self.exports.insert(
                     id,
                     Export {
@@ -291,6 +429,30 @@ impl Node {
             }
         }
 
+        // Push the current value of each export to its new importers, since they would otherwise
+        // only learn a value the next time the exported reactive changes. Exports modified by
+        // this transaction are skipped, since propagate() will send them their value anyway.
+        for (addr, ids) in handshakes {
+            let updates = ids
+                .into_iter()
+                .filter(|id| !modified.contains(id))
+                .filter_map(|id| {
+                    let value = self.reactives[&id].value()?.clone();
+                    Some((
+                        ReactiveAddress {
+                            address: ctx.me().clone(),
+                            id,
+                        },
+                        self.without_local_only_bases(value, &ctx),
+                    ))
+                })
+                .collect::<Vec<_>>();
+
+            if !updates.is_empty() {
+                ctx.send(&addr, Message::PropagateBatch { updates });
+            }
+        }
+
         self.iterations.extend(exclusive_state.prepared_iterations);
 
         self.propagate(modified, &ctx);
@@ -298,6 +460,387 @@ impl Node {
         Some(ctx)
     }
 
+    fn prepare_commit(&mut self, txid: &TxId, ctx: &Context) {
+        if let Err(error) = self.evaluate_configurations(txid, ctx) {
+            ctx.send(
+                &txid.address,
+                Message::PrepareFailed {
+                    address: ctx.me().clone(),
+                    txid: txid.clone(),
+                    error,
+                },
+            );
+            return;
+        }
+
+        let state = self
+            .held
+            .shared(txid)
+            .expect("attempted to prepare commit for unheld lock");
+
+        let mut basis = state
+            .reads
+            .values()
+            .filter_map(|read| read.complete.as_ref())
+            .fold(BasisStamp::empty(), |mut basis, complete| {
+                basis.merge_from(complete);
+                basis
+            });
+
+        if let Some(exclusive) = self.held.exclusive_mut(txid) {
+            // For any direct writes to local reactives, we want to increment the iterations
+            // of all transitively dependent local reactives, including the written nodes
+            // themselves. Migrations count as writes.
+            for id in &self.topo {
+                let written = exclusive.writes.contains_key(id)
+                    || matches!(
+                        exclusive.reactives.get(id),
+                        Some(Some(ReactiveConfiguration::Migration { .. }))
+                    );
+                if written
+                    || self.reactives[id].inputs().any(|input| {
+                        input.address == *ctx.me()
+                            && exclusive.prepared_iterations.contains_key(&input.id)
+                    })
+                {
+                    exclusive
+                        .prepared_iterations
+                        .insert(*id, self.iterations[id].increment());
+                }
+            }
+
+            // Include the roots contributed by the exporters of any imports registered by this
+            // transaction, which might have prepared before learning of the new import.
+            basis.merge_from(&exclusive.granted_basis);
+
+            // Only include exported reactives as roots in the basis. Note that we have to
+            // take care to respect the set of exports that will be set following commit of
+            // the transaction, rather than the current self.exports.
+            basis.roots.extend(
+                exclusive
+                    .prepared_iterations
+                    .iter()
+                    .filter(|(id, _)| {
+                        exclusive
+                            .exports
+                            .get(id)
+                            .map_or(self.exports.contains_key(id), |export| !export.is_empty())
+                    })
+                    .map(|(id, iter)| {
+                        (
+                            ReactiveAddress {
+                                address: ctx.me().clone(),
+                                id: *id,
+                            },
+                            *iter,
+                        )
+                    }),
+            );
+        }
+
+        ctx.send(
+            &txid.address,
+            Message::CommitPrepared {
+                address: ctx.me().clone(),
+                txid: txid.clone(),
+                basis,
+            },
+        );
+    }
+
+    /// Evaluates the migrations and definitions configured by the exclusive lock `txid` against
+    /// the values they would start from, so that the transaction is aborted rather than committed
+    /// if one of them fails. The migrated values are kept for the commit.
+    fn evaluate_configurations(
+        &mut self,
+        txid: &TxId,
+        ctx: &Context,
+    ) -> Result<(), EvalError<ReactiveAddress>> {
+        let Some(exclusive) = self.held.exclusive(txid) else {
+            return Ok(());
+        };
+
+        let mut migrated = HashMap::new();
+        for (id, config) in &exclusive.reactives {
+            match config {
+                Some(ReactiveConfiguration::Migration { migration }) => {
+                    // A new reactive has no value to migrate.
+                    if let Some(reactive) = self.reactives.get(id) {
+                        let address = ReactiveAddress {
+                            address: ctx.me().clone(),
+                            id: *id,
+                        };
+                        let value = reactive.migrated_value(&address, migration.clone())?;
+                        migrated.insert(*id, value);
+                    }
+                }
+                // Inputs without a value yet leave the definition partially evaluated, which
+                // is not an error.
+                Some(ReactiveConfiguration::Definition { expr, .. }) => {
+                    expr.clone().eval(&mut PreparedValues {
+                        me: ctx.me(),
+                        reactives: &self.reactives,
+                        imports: &self.imports,
+                        exclusive,
+                    })?;
+                }
+                _ => (),
+            }
+        }
+
+        self.held.exclusive_mut(txid).unwrap().migrated = migrated;
+
+        Ok(())
+    }
+
+    /// Applies the roots committed for imports while this node held locks.
+    fn apply_committed_roots(&mut self, ctx: &Context) {
+        if self.committed_roots.is_empty() {
+            return;
+        }
+
+        let mut modified = HashSet::new();
+        for (address, roots) in std::mem::take(&mut self.committed_roots) {
+            // Importers that were stalled on the prior roots may now form a batch.
+            if let Some(import) = self.imports.get_mut(&address) {
+                import.roots = roots;
+                modified.extend(import.importers.iter().copied());
+            }
+        }
+
+        self.recompute_roots(ctx);
+        self.propagate(modified, ctx);
+    }
+
+    /// Adds `importer` to the importers of the export of `reactive` under the exclusive lock
+    /// `txid`, answering it once the roots of the export are known.
+    fn register_importer(
+        &mut self,
+        txid: &TxId,
+        reactive: ReactiveId,
+        importer: Address,
+        mode: PropagationMode,
+        ctx: &Context,
+    ) {
+        let state = self.held.exclusive_mut(txid).unwrap();
+
+        state
+            .exports
+            .entry(reactive)
+            .or_insert_with(|| {
+                self.exports
+                    .get(&reactive)
+                    .map(|e| e.importers.clone())
+                    .unwrap_or_default()
+            })
+            .insert(importer.clone(), mode);
+
+        state.importer_registrations.push((reactive, importer));
+
+        self.grant_importer_registrations(txid, ctx);
+    }
+
+    /// Answers the importer registrations received by the exclusive lock `txid` whose export
+    /// roots can already be determined.
+    fn grant_importer_registrations(&mut self, txid: &TxId, ctx: &Context) {
+        let Some(state) = self.held.exclusive(txid) else {
+            return;
+        };
+
+        let mut granted = Vec::new();
+        for (i, (id, importer)) in state.importer_registrations.iter().enumerate() {
+            let Some(mut roots) = self.prospective_roots(state, *id, ctx) else {
+                continue;
+            };
+
+            let address = ReactiveAddress {
+                address: ctx.me().clone(),
+                id: *id,
+            };
+
+            // If we have already prepared, the basis we sent did not include this export, so the
+            // importer has to contribute it instead.
+            let mut basis = BasisStamp::empty();
+            if let Some(iteration) = state.prepared_iterations.get(id) {
+                basis.add(address.clone(), *iteration);
+            }
+
+            roots.insert(address.clone());
+
+            ctx.send(
+                importer,
+                Message::ImporterRegistered {
+                    txid: txid.clone(),
+                    reactive: address,
+                    roots,
+                    basis,
+                },
+            );
+
+            granted.push(i);
+        }
+
+        let state = self.held.exclusive_mut(txid).unwrap();
+        for i in granted.into_iter().rev() {
+            state.importer_registrations.remove(i);
+        }
+    }
+
+    /// Sends CommitPrepared for the exclusive lock `txid` once it has been requested and every
+    /// import and root change of the transaction has been settled with the other nodes.
+    fn try_prepare_commit(&mut self, txid: &TxId, ctx: &Context) {
+        let Some(state) = self.held.exclusive(txid) else {
+            return;
+        };
+
+        if !state.prepare_requested || !state.awaiting_imports.is_empty() {
+            return;
+        }
+
+        self.announce_roots(txid, ctx);
+
+        let state = self.held.exclusive_mut(txid).unwrap();
+        if state.awaiting_root_acks == 0 {
+            state.prepare_requested = false;
+            self.prepare_commit(txid, ctx);
+        }
+    }
+
+    /// Announces the new roots of every export whose roots are changed by the transaction `txid`
+    /// to the export's importers, which will stage the change until the transaction ends.
+    ///
+    /// Announcements are only sent for roots that differ from those last announced, so this can
+    /// be called again whenever more of the transaction's configuration becomes known.
+    fn announce_roots(&mut self, txid: &TxId, ctx: &Context) {
+        let Some(state) = self
+            .held
+            .exclusive(txid)
+            .or_else(|| self.staged_roots.get(txid))
+        else {
+            return;
+        };
+
+        let mut announcements = Vec::new();
+        for (id, export) in &self.exports {
+            if let Some(None) = state.reactives.get(id) {
+                continue;
+            }
+
+            let Some(mut roots) = self.prospective_roots(state, *id, ctx) else {
+                continue;
+            };
+
+            let address = ReactiveAddress {
+                address: ctx.me().clone(),
+                id: *id,
+            };
+            roots.insert(address.clone());
+
+            let announced = match state.announced_roots.get(id) {
+                Some(announced) => announced == &roots,
+                None => roots.len() == export.roots.len() + 1 && export.roots.is_subset(&roots),
+            };
+
+            if !announced {
+                let importers = state.exports.get(id).unwrap_or(&export.importers).clone();
+                announcements.push((*id, address, roots, importers));
+            }
+        }
+
+        let state = match self.held.exclusive_mut(txid) {
+            Some(state) => state,
+            None => self.staged_roots.get_mut(txid).unwrap(),
+        };
+
+        for (id, address, roots, importers) in announcements {
+            for addr in importers.into_keys() {
+                ctx.send(
+                    &addr,
+                    Message::UpdateRoots {
+                        txid: txid.clone(),
+                        reactive: address.clone(),
+                        roots: roots.clone(),
+                    },
+                );
+
+                state.awaiting_root_acks += 1;
+                state.root_importers.insert(addr);
+            }
+
+            state.announced_roots.insert(id, roots);
+        }
+    }
+
+    /// Acknowledges the root announcements received for the transaction `txid` once all of the
+    /// announcements they caused have themselves been acknowledged.
+    fn acknowledge_roots(&mut self, txid: &TxId, ctx: &Context) {
+        let state = match self.held.exclusive_mut(txid) {
+            Some(state) => state,
+            None => match self.staged_roots.get_mut(txid) {
+                Some(state) => state,
+                None => return,
+            },
+        };
+
+        if state.awaiting_root_acks == 0 {
+            for addr in state.root_requesters.drain(..) {
+                ctx.send(&addr, Message::RootsUpdated { txid: txid.clone() });
+            }
+        }
+    }
+
+    /// Computes the cross-network roots that the reactive `id` will have once `state` commits.
+    ///
+    /// Returns `None` if they depend on an import whose roots are not known yet.
+    fn prospective_roots(
+        &self,
+        state: &ExclusiveLockState,
+        id: ReactiveId,
+        ctx: &Context,
+    ) -> Option<HashSet<ReactiveAddress>> {
+        let inputs = match state.reactives.get(&id) {
+            Some(Some(
+                ReactiveConfiguration::Variable { .. } | ReactiveConfiguration::Migration { .. },
+            )) => Vec::new(),
+            Some(Some(
+                ReactiveConfiguration::Definition { expr, .. }
+                | ReactiveConfiguration::Fold { step: expr, .. },
+            )) => {
+                let mut inputs = Vec::new();
+                expr.visit_reads(&mut |address, _| {
+                    // A fold reading its own address refers to its state rather than an input.
+                    if &address.address != ctx.me() || address.id != id {
+                        inputs.push(address.clone());
+                    }
+                });
+                inputs
+            }
+            Some(None) => panic!("attempted to import reactive that is being removed"),
+            None => self
+                .reactives
+                .get(&id)
+                .expect("attempted to import reactive that could not be found")
+                .inputs()
+                .cloned()
+                .collect(),
+        };
+
+        let mut roots = HashSet::new();
+        for input in inputs {
+            if &input.address == ctx.me() {
+                roots.extend(self.prospective_roots(state, input.id, ctx)?);
+            } else if state.awaiting_imports.contains(&input) {
+                return None;
+            } else if let Some(Some(config)) = state.imports.get(&input) {
+                roots.extend(config.roots.iter().cloned());
+            } else {
+                roots.extend(self.imports[&input].roots.iter().cloned());
+            }
+        }
+
+        Some(roots)
+    }
+
     fn recompute_topo(&mut self) {
         let mut visited = HashMap::new();
         self.topo.clear();
@@ -305,6 +848,10 @@ impl Node {
             Self::topo_dfs(&self.subscriptions, &mut self.topo, &mut visited, *id)
                 .expect("dependency graph is locally cyclical");
         }
+
+        self.ranks.clear();
+        self.ranks
+            .extend(self.topo.iter().enumerate().map(|(rank, id)| (*id, rank)));
     }
 
     fn topo_dfs(
@@ -364,6 +911,17 @@ impl Node {
                 .cloned()
                 .collect();
         }
+
+        let roots = |address: &ReactiveAddress| {
+            if &address.address == ctx.me() {
+                self.roots.get(&address.id)
+            } else {
+                self.imports.get(address).map(|i| &i.roots)
+            }
+        };
+        for reactive in self.reactives.values_mut() {
+            reactive.index_roots(roots);
+        }
     }
 
     fn preempt(preempted: &mut HashSet<TxId>, txid: &TxId, ctx: &Context) {
@@ -373,32 +931,27 @@ impl Node {
     }
 
     fn propagate(&mut self, modified: HashSet<ReactiveId>, ctx: &Context) {
-        let mut found = false;
-        for id in &self.topo {
-            if !found {
-                if modified.contains(id) {
-                    found = true;
-                } else {
-                    continue;
-                }
-            }
+        // Only reactives with pending updates are visited. Since every update flows from a
+        // reactive to reactives of strictly higher rank, visiting the dirty reactives in rank
+        // order means each one is visited only once all of its local inputs have settled.
+        let mut dirty = modified
+            .iter()
+            .filter_map(|id| self.ranks.get(id).copied())
+            .collect::<BTreeSet<_>>();
 
-            let roots = |address: &ReactiveAddress| {
-                if &address.address == ctx.me() {
-                    self.roots.get(&address.id)
-                } else {
-                    self.imports.get(address).map(|i| &i.roots)
-                }
-            };
+        // The updates produced by this pass are coalesced into a single batch per importer.
+        let mut batches = HashMap::<Address, Vec<(ReactiveAddress, StampedValue)>>::new();
 
-            while let Some(value) = self
+        while let Some(rank) = dirty.pop_first() {
+            let id = &self.topo[rank];
+
+            while let Some((value, changed)) = self
                 .reactives
                 .get_mut(id)
                 .unwrap()
-                .next_value(roots)
-                .cloned()
+                .next_value()
+                .map(|(value, changed)| (value.clone(), changed))
             {
-                println!("new value for {id:?} on {:?}: {value:?}", ctx.me());
                 for sub in self.subscriptions.get(id).unwrap() {
                     self.reactives.get_mut(sub).unwrap().add_update(
                         ReactiveAddress {
@@ -407,59 +960,105 @@ impl Node {
                         },
                         value.clone(),
                     );
+                    dirty.insert(self.ranks[sub]);
                 }
 
-                let value_without_local_only_bases = StampedValue {
-                    value: value.value,
-                    basis: BasisStamp {
-                        roots: value
-                            .basis
-                            .roots
-                            .into_iter()
-                            .filter(|(a, _)| {
-                                &a.address != ctx.me() || self.exports.contains_key(&a.id)
-                            })
-                            .collect(),
-                    },
-                };
+                // Values that merely advance the basis are not reported.
+                if changed {
+                    for subscriber in self.subscribers.get(id).into_iter().flatten() {
+                        ctx.send(
+                            subscriber,
+                            Message::ValueChanged {
+                                reactive: ReactiveAddress {
+                                    address: ctx.me().clone(),
+                                    id: *id,
+                                },
+                                value: value.clone(),
+                            },
+                        );
+                    }
+                }
 
-                for addr in self
+                let value_without_local_only_bases = self.without_local_only_bases(value, ctx);
+
+                for (addr, mode) in self
                     .exports
                     .get(id)
                     .iter()
                     .copied()
                     .flat_map(|e| e.importers.iter())
                 {
-                    ctx.send(
-                        addr,
-                        Message::Propagate {
-                            sender: ReactiveAddress {
-                                address: ctx.me().clone(),
-                                id: *id,
-                            },
-                            value: value_without_local_only_bases.clone(),
+                    let batch = batches.entry(addr.clone()).or_default();
+
+                    if let PropagationMode::Latest = mode {
+                        batch.retain(|(sender, _)| sender.id != *id);
+                    }
+
+                    batch.push((
+                        ReactiveAddress {
+                            address: ctx.me().clone(),
+                            id: *id,
                         },
-                    );
+                        value_without_local_only_bases.clone(),
+                    ));
                 }
             }
         }
 
-        self.grant_reads(&ctx);
+        for (addr, updates) in batches {
+            ctx.send(&addr, Message::PropagateBatch { updates });
+        }
+
+        self.grant_reads(ctx);
+    }
+
+    /// Strips the roots of `value`'s basis that are local to this node and not exported, since
+    /// other network nodes cannot refer to them.
+    fn without_local_only_bases(&self, value: StampedValue, ctx: &Context) -> StampedValue {
+        StampedValue {
+            value: value.value,
+            basis: BasisStamp {
+                roots: value
+                    .basis
+                    .roots
+                    .into_iter()
+                    .filter(|(a, _)| &a.address != ctx.me() || self.exports.contains_key(&a.id))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Hands a value propagated from another node to the importers of its import, returning the
+    /// importers that were updated.
+    fn receive_propagation(
+        &mut self,
+        sender: ReactiveAddress,
+        value: StampedValue,
+    ) -> HashSet<ReactiveId> {
+        let Some(import) = self.imports.get_mut(&sender) else {
+            return HashSet::new();
+        };
+
+        for id in &import.importers {
+            self.reactives
+                .get_mut(id)
+                .unwrap()
+                .add_update(sender.clone(), value.clone());
+        }
+
+        import.value = Some(value);
+
+        import.importers.clone()
     }
 
     fn grant_reads(&mut self, ctx: &Context) {
         self.held.visit_shared(|txid, state| {
             for (id, read) in &mut state.reads {
-                // If a read is completed, the `complete` stamp has to have SOMETHING in it -- no
-                // value can have an empty basis stamp. Hence if `complete` is empty, the read is
-                // pending and needs to be sent out.
-                //
-                // Alternatively, we may have already completed a read, but another is pending.
-                if read.complete.is_empty() || !read.pending.is_empty() {
-                    if let Some(value) = self.reactives.get(&id).unwrap().value() {
+                if let Some(pending) = &read.pending {
+                    if let Some(value) = self.reactives.get(id).unwrap().value() {
                         let roots = self.roots.get(id).unwrap();
 
-                        if read.pending.prec_eq_wrt_roots(&value.basis, roots) {
+                        if pending.prec_eq_wrt_roots(&value.basis, roots) {
                             ctx.send(
                                 &txid.address,
                                 Message::ReadResult {
@@ -472,7 +1071,10 @@ impl Node {
                                 },
                             );
 
-                            read.complete.merge_from(&value.basis);
+                            read.complete
+                                .get_or_insert_with(BasisStamp::empty)
+                                .merge_from(&value.basis);
+                            read.pending = None;
                         }
                     }
                 }
@@ -481,6 +1083,34 @@ impl Node {
     }
 }
 
+impl ExprEvalContext<ReactiveAddress> for PreparedValues<'_> {
+    fn read(
+        &mut self,
+        address: &ReactiveAddress,
+    ) -> Result<Option<&Value>, EvalError<ReactiveAddress>> {
+        if &address.address != self.me {
+            return Ok(self
+                .imports
+                .get(address)
+                .and_then(|import| import.value.as_ref())
+                .map(|value| &value.value));
+        }
+
+        if let Some(value) = self.exclusive.writes.get(&address.id) {
+            return Ok(Some(value));
+        }
+        if self.exclusive.reactives.contains_key(&address.id) {
+            return Ok(None);
+        }
+
+        Ok(self
+            .reactives
+            .get(&address.id)
+            .and_then(|reactive| reactive.value())
+            .map(|value| &value.value))
+    }
+}
+
 impl Actor for Node {
     fn handle(&mut self, message: Message, mut ctx: Context) {
         match message {
@@ -494,6 +1124,8 @@ impl Actor for Node {
                 self.grant_locks(&ctx);
             }
             Message::Abort { txid } => {
+                self.queued_registrations.remove(&txid);
+
                 match &mut self.held {
                     HeldLocks::None => panic!("abort of unheld lock requested"),
                     HeldLocks::Shared(held) => {
@@ -505,8 +1137,12 @@ impl Actor for Node {
                             self.held = HeldLocks::None;
                         }
                     }
-                    HeldLocks::Exclusive(held_txid, _, _) => {
+                    HeldLocks::Exclusive(held_txid, _, exclusive) => {
                         if held_txid == &txid {
+                            for addr in &exclusive.root_importers {
+                                ctx.send(addr, Message::AbortRoots { txid: txid.clone() });
+                            }
+
                             self.held = HeldLocks::None;
                         } else {
                             panic!("abort of unheld lock requested")
@@ -517,80 +1153,16 @@ impl Actor for Node {
                 self.grant_locks(&ctx);
             }
             Message::PrepareCommit { txid } => {
-                let state = self
-                    .held
-                    .shared(&txid)
-                    .expect("attempted to prepare commit for unheld lock");
-
-                let mut basis =
-                    state
-                        .reads
-                        .values()
-                        .fold(BasisStamp::empty(), |mut basis, read| {
-                            basis.merge_from(&read.complete);
-                            basis
-                        });
-
                 if let Some(exclusive) = self.held.exclusive_mut(&txid) {
-                    // For any direct writes to local reactives, we want to increment the iterations
-                    // of all transitively dependent local reactives, including the written nodes
-                    // themselves.
-                    for id in &self.topo {
-                        if exclusive.writes.contains_key(id) {
-                            exclusive
-                                .prepared_iterations
-                                .insert(*id, self.iterations[id].increment());
-                        } else if self.reactives[id].inputs().any(|input| {
-                            &input.address == ctx.me()
-                                && exclusive.prepared_iterations.contains_key(&input.id)
-                        }) {
-                            exclusive
-                                .prepared_iterations
-                                .insert(*id, self.iterations[id].increment());
-                        }
-                    }
-
-                    // Only include exported reactives as roots in the basis. Note that we have to
-                    // take care to respect the set of exports that will be set following commit of
-                    // the transaction, rather than the current self.exports.
-                    basis.roots.extend(
-                        exclusive
-                            .prepared_iterations
-                            .iter()
-                            .filter(|(id, _)| {
-                                exclusive
-                                    .exports
-                                    .get(id)
-                                    .map_or(self.exports.contains_key(id), |export| {
-                                        !export.is_empty()
-                                    })
-                            })
-                            .map(|(id, iter)| {
-                                (
-                                    ReactiveAddress {
-                                        address: ctx.me().clone(),
-                                        id: *id,
-                                    },
-                                    *iter,
-                                )
-                            }),
-                    );
+                    exclusive.prepare_requested = true;
+                    self.try_prepare_commit(&txid, &ctx);
+                } else {
+                    self.prepare_commit(&txid, &ctx);
                 }
-
-                // TODO: **comprehensively** validate the update (ideally equivalent to fully
-                // executing it), perhaps by doing it and adding an 'undo log' entry, so that no
-                // can occur after CommitPrepared is sent
-
-                ctx.send(
-                    &txid.address,
-                    Message::CommitPrepared {
-                        address: ctx.me().clone(),
-                        txid: txid.clone(),
-                        basis,
-                    },
-                );
             }
             Message::Commit { txid, basis } => {
+                self.queued_registrations.remove(&txid);
+
                 match std::mem::replace(&mut self.held, HeldLocks::None) {
                     HeldLocks::None => panic!("release of unheld lock requested"),
                     HeldLocks::Shared(mut held) => {
@@ -603,7 +1175,7 @@ impl Actor for Node {
 
                         if let Some(data) = data {
                             if let Some(returned) =
-                                self.commit(basis, data, ExclusiveLockState::default(), ctx)
+                                self.commit(&txid, basis, data, ExclusiveLockState::default(), ctx)
                             {
                                 ctx = returned;
                             } else {
@@ -616,7 +1188,7 @@ impl Actor for Node {
                     HeldLocks::Exclusive(held_txid, shared_data, exclusive_data) => {
                         if held_txid == txid {
                             if let Some(returned) =
-                                self.commit(basis, shared_data, exclusive_data, ctx)
+                                self.commit(&txid, basis, shared_data, *exclusive_data, ctx)
                             {
                                 ctx = returned;
                             } else {
@@ -639,6 +1211,37 @@ impl Actor for Node {
                 reactive,
                 basis,
             } => {
+                if let Some(value) = self
+                    .held
+                    .exclusive(&txid)
+                    .and_then(|state| state.writes.get(&reactive))
+                {
+                    // The transaction reads its own write, which the reactive will hold at its
+                    // next iteration once the transaction commits. The written value was computed
+                    // from what the transaction has read, so it is based on the requested basis
+                    // as well. The read is not recorded, since it does not depend on any value
+                    // committed before.
+                    let address = ReactiveAddress {
+                        address: ctx.me().clone(),
+                        id: reactive,
+                    };
+                    let mut stamp = basis;
+                    stamp.add(address.clone(), self.iterations[&reactive].increment());
+
+                    ctx.send(
+                        &txid.address,
+                        Message::ReadResult {
+                            txid: txid.clone(),
+                            reactive: address,
+                            value: StampedValue {
+                                value: value.clone(),
+                                basis: stamp,
+                            },
+                        },
+                    );
+                    return;
+                }
+
                 let Some(lock) = self.held.shared_mut(&txid) else {
                     panic!("attempted to read without a lock")
                 };
@@ -650,15 +1253,14 @@ impl Actor for Node {
                 let e = lock.reads.entry(reactive);
 
                 if let hash_map::Entry::Occupied(e) = &e {
-                    let r = e.get();
-                    if r.complete.is_empty() || !r.pending.is_empty() {
+                    if e.get().pending.is_some() {
                         panic!("attempted to read while another read is still pending")
                     }
                 }
 
                 let read = e.or_insert(Read {
-                    pending: BasisStamp::empty(),
-                    complete: BasisStamp::empty(),
+                    pending: None,
+                    complete: None,
                 });
 
                 if let Some(value) = r.value() {
@@ -675,12 +1277,14 @@ impl Actor for Node {
                             },
                         );
 
-                        read.complete.merge_from(&value.basis);
+                        read.complete
+                            .get_or_insert_with(BasisStamp::empty)
+                            .merge_from(&value.basis);
                     } else {
-                        read.pending = basis;
+                        read.pending = Some(basis);
                     }
                 } else {
-                    read.pending = basis;
+                    read.pending = Some(basis);
                 }
             }
             Message::Write {
@@ -718,24 +1322,317 @@ impl Actor for Node {
                     .exclusive_mut(&txid)
                     .expect("attempted to configure without an exclusive lock");
                 state.imports.extend(imports);
-                state.reactives.extend(reactives);
                 state.exports.extend(exports);
+
+                // Register this node as an importer of every remote reactive read by the new
+                // configuration that is not imported yet. The exporter replies with the roots of
+                // the import, which completes its configuration. Only the latest values are
+                // requested for imports that none of their readers needs every value of.
+                let mut registrations = HashMap::<ReactiveAddress, PropagationMode>::new();
+                for config in reactives.values().flatten() {
+                    let (ReactiveConfiguration::Definition { expr, options }
+                    | ReactiveConfiguration::Fold {
+                        step: expr,
+                        options,
+                        ..
+                    }) = config
+                    else {
+                        continue;
+                    };
+
+                    expr.visit_reads(&mut |address, _| {
+                        if &address.address == ctx.me()
+                            || self.imports.contains_key(address)
+                            || state.imports.contains_key(address)
+                            || state.awaiting_imports.contains(address)
+                        {
+                            return;
+                        }
+
+                        let mode = registrations
+                            .entry(address.clone())
+                            .or_insert(options.propagation);
+                        if options.propagation == PropagationMode::All {
+                            *mode = PropagationMode::All;
+                        }
+                    });
+                }
+
+                for (address, mode) in registrations {
+                    ctx.send(
+                        &address.address,
+                        Message::RegisterImporter {
+                            txid: txid.clone(),
+                            reactive: address.id,
+                            importer: ctx.me().clone(),
+                            mode,
+                        },
+                    );
+                    state.awaiting_imports.insert(address);
+                }
+
+                state.reactives.extend(reactives);
+            }
+            Message::RegisterImporter {
+                txid,
+                reactive,
+                importer,
+                mode,
+            } => {
+                if self.held.exclusive(&txid).is_some() {
+                    self.register_importer(&txid, reactive, importer, mode, &ctx);
+                } else {
+                    // The coordinator locks the exporter too, but the importer may ask before
+                    // the lock is granted.
+                    self.queued_registrations
+                        .entry(txid)
+                        .or_default()
+                        .push((reactive, importer, mode));
+                }
+            }
+            Message::ImporterRegistered {
+                txid,
+                reactive,
+                roots,
+                basis,
+            } => {
+                let state = self
+                    .held
+                    .exclusive_mut(&txid)
+                    .expect("received importer registration without an exclusive lock");
+
+                assert!(
+                    state.awaiting_imports.remove(&reactive),
+                    "received importer registration that was not requested"
+                );
+                state
+                    .imports
+                    .insert(reactive, Some(ImportConfiguration { roots }));
+                state.granted_basis.merge_from(&basis);
+
+                // Our own exports may have been waiting on the roots of this import.
+                self.grant_importer_registrations(&txid, &ctx);
+                self.try_prepare_commit(&txid, &ctx);
+            }
+            Message::UpdateRoots {
+                txid,
+                reactive,
+                roots,
+            } => {
+                let state = match self.held.exclusive_mut(&txid) {
+                    Some(state) => state,
+                    None => self.staged_roots.entry(txid.clone()).or_default(),
+                };
+
+                // Imports that are not kept past this transaction need no new roots.
+                if self.imports.contains_key(&reactive) || state.imports.contains_key(&reactive) {
+                    if let Some(config) =
+                        state
+                            .imports
+                            .entry(reactive.clone())
+                            .or_insert(Some(ImportConfiguration {
+                                roots: HashSet::new(),
+                            }))
+                    {
+                        config.roots = roots;
+                    }
+                }
+
+                state.root_requesters.push(reactive.address);
+
+                self.announce_roots(&txid, &ctx);
+                self.acknowledge_roots(&txid, &ctx);
+            }
+            Message::RootsUpdated { txid } => {
+                let state = match self.held.exclusive_mut(&txid) {
+                    Some(state) => state,
+                    None => self
+                        .staged_roots
+                        .get_mut(&txid)
+                        .expect("received acknowledgement of roots that were not announced"),
+                };
+
+                state.awaiting_root_acks -= 1;
+
+                self.acknowledge_roots(&txid, &ctx);
+                self.try_prepare_commit(&txid, &ctx);
+            }
+            Message::CommitRoots { txid } => {
+                // Nodes that take part in the transaction apply the new roots on Commit instead,
+                // and a node that was announced roots by several exporters only applies them once.
+                let Some(staged) = self.staged_roots.remove(&txid) else {
+                    return;
+                };
+
+                for addr in &staged.root_importers {
+                    ctx.send(addr, Message::CommitRoots { txid: txid.clone() });
+                }
+
+                for (address, config) in staged.imports {
+                    if let Some(config) = config {
+                        self.committed_roots.insert(address, config.roots);
+                    }
+                }
+
+                if let HeldLocks::None = self.held {
+                    self.apply_committed_roots(&ctx);
+                }
             }
-            Message::Propagate { sender, value } => {
-                let Some(import) = self.imports.get(&sender) else {
+            Message::AbortRoots { txid } => {
+                let Some(staged) = self.staged_roots.remove(&txid) else {
                     return;
                 };
 
-                for id in &import.importers {
-                    self.reactives
-                        .get_mut(&id)
-                        .unwrap()
-                        .add_update(sender.clone(), value.clone());
+                for addr in &staged.root_importers {
+                    ctx.send(addr, Message::AbortRoots { txid: txid.clone() });
+                }
+            }
+            Message::PropagateBatch { updates } => {
+                let mut modified = HashSet::new();
+
+                for (sender, value) in updates {
+                    if !self.imports.contains_key(&sender) {
+                        // The exporter may have committed a newly configured export before we
+                        // have committed the matching import, in which case the value is held
+                        // back until the import exists. Since the configuration of the import may
+                        // still change before the lock commits, every such value is held back.
+                        if let HeldLocks::Exclusive(_, _, exclusive) = &mut self.held {
+                            exclusive.propagations.push((sender, value));
+                        }
+
+                        continue;
+                    }
+
+                    modified.extend(self.receive_propagation(sender, value));
                 }
 
-                self.propagate(import.importers.clone(), &ctx);
+                self.propagate(modified, &ctx);
+            }
+            Message::Subscribe {
+                reactive,
+                subscriber,
+            } => {
+                // A reactive that does not exist (anymore) has no values to stream.
+                let Some(r) = self.reactives.get(&reactive) else {
+                    return;
+                };
+
+                if !self
+                    .subscribers
+                    .entry(reactive)
+                    .or_default()
+                    .insert(subscriber.clone())
+                {
+                    return;
+                }
+
+                // Start the stream with the current value so that the subscriber does not have to
+                // wait for the next propagation to learn it.
+                if let Some(value) = r.value() {
+                    ctx.send(
+                        &subscriber,
+                        Message::ValueChanged {
+                            reactive: ReactiveAddress {
+                                address: ctx.me().clone(),
+                                id: reactive,
+                            },
+                            value: value.clone(),
+                        },
+                    );
+                }
+            }
+            Message::Diagnose {
+                reactive,
+                requester,
+            } => {
+                let (inputs, blocked_on, error, pending) = match self.reactives.get(&reactive) {
+                    Some(r) => {
+                        let (inputs, blocked_on, error) = r.diagnostics();
+
+                        (inputs, blocked_on, error, r.is_pending())
+                    }
+                    None => (HashMap::new(), None, None, false),
+                };
+
+                ctx.send(
+                    &requester,
+                    Message::Diagnostics {
+                        reactive: ReactiveAddress {
+                            address: ctx.me().clone(),
+                            id: reactive,
+                        },
+                        inputs,
+                        blocked_on,
+                        pending,
+                        error,
+                    },
+                );
+            }
+            Message::Unsubscribe {
+                reactive,
+                subscriber,
+            } => {
+                if let hash_map::Entry::Occupied(mut e) = self.subscribers.entry(reactive) {
+                    e.get_mut().remove(&subscriber);
+                    if e.get().is_empty() {
+                        e.remove();
+                    }
+                }
+            }
+            Message::RetainHistory { reactive, limit } => {
+                if let Some(r) = self.reactives.get_mut(&reactive) {
+                    r.retain_history(limit);
+                }
+            }
+            Message::ReadAt {
+                reactive,
+                basis,
+                requester,
+            } => {
+                // A reactive that does not exist has no value at any basis.
+                let value = self
+                    .reactives
+                    .get(&reactive)
+                    .and_then(|r| r.value_at(&basis).cloned());
+
+                ctx.send(
+                    &requester,
+                    Message::ValueAt {
+                        reactive: ReactiveAddress {
+                            address: ctx.me().clone(),
+                            id: reactive,
+                        },
+                        value,
+                        basis,
+                    },
+                );
+            }
+            // A coordinator that is gone cannot release the lock it was granted, so it is released
+            // here instead. Other messages that could not be delivered need no follow-up.
+            Message::Unreachable { message } => {
+                if let Message::LockGranted { txid, .. } = *message {
+                    if self.held.exclusive(&txid).is_some() || self.held.shared(&txid).is_some() {
+                        self.handle(Message::Abort { txid }, ctx);
+                    }
+                }
             }
-            _ => todo!(),
+            // Messages meant for coordinators, for the requesters of transactions and for
+            // subscribers.
+            Message::ValueChanged { .. }
+            | Message::Diagnostics { .. }
+            | Message::ValueAt { .. }
+            | Message::LockGranted { .. }
+            | Message::ReadResult { .. }
+            | Message::ReadConfigurationResult { .. }
+            | Message::Retire { .. }
+            | Message::Preempt { .. }
+            | Message::CommitPrepared { .. }
+            | Message::PrepareFailed { .. }
+            | Message::Do { .. }
+            | Message::Upgrade { .. }
+            | Message::Committed { .. }
+            | Message::Aborted { .. }
+            | Message::Directory { .. } => {}
         }
     }
 }
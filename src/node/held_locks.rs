@@ -3,7 +3,10 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use crate::{
     actor::Address,
     expr::Value,
-    message::{BasisStamp, ImportConfiguration, Iteration, ReactiveConfiguration, TxId},
+    message::{
+        BasisStamp, ImportConfiguration, Iteration, PropagationMode, ReactiveConfiguration,
+        StampedValue, TxId,
+    },
 };
 
 use super::{ReactiveAddress, ReactiveId};
@@ -11,7 +14,8 @@ use super::{ReactiveAddress, ReactiveId};
 pub enum HeldLocks {
     None,
     Shared(BTreeMap<TxId, SharedLockState>),
-    Exclusive(TxId, SharedLockState, ExclusiveLockState),
+    // Boxed, since the exclusive state is much larger than that of the other variants.
+    Exclusive(TxId, SharedLockState, Box<ExclusiveLockState>),
 }
 
 #[derive(Default)]
@@ -20,8 +24,12 @@ pub struct SharedLockState {
 }
 
 pub struct Read {
-    pub pending: BasisStamp,
-    pub complete: BasisStamp,
+    /// The basis the latest requested read has to be served at, until it is served.
+    pub pending: Option<BasisStamp>,
+    /// The merged basis of the values served, or `None` if none has been served yet. Values can
+    /// have an empty basis, such as the default of a pending definition, so whether a value was
+    /// served cannot be told from the basis alone.
+    pub complete: Option<BasisStamp>,
 }
 
 #[derive(Debug, Default)]
@@ -29,8 +37,30 @@ pub struct ExclusiveLockState {
     pub writes: HashMap<ReactiveId, Value>,
     pub imports: HashMap<ReactiveAddress, Option<ImportConfiguration>>,
     pub reactives: HashMap<ReactiveId, Option<ReactiveConfiguration>>,
-    pub exports: HashMap<ReactiveId, HashSet<Address>>,
+    pub exports: HashMap<ReactiveId, HashMap<Address, PropagationMode>>,
     pub prepared_iterations: HashMap<ReactiveId, Iteration>,
+    /// The values the migrations of existing reactives evaluated to when preparing, which the
+    /// reactives are given on commit.
+    pub migrated: HashMap<ReactiveId, Option<Value>>,
+    /// Values propagated to imports that do not exist yet, which this lock may create.
+    pub propagations: Vec<(ReactiveAddress, StampedValue)>,
+    /// Imports registered with their exporters whose roots have not been received yet.
+    pub awaiting_imports: HashSet<ReactiveAddress>,
+    /// Importer registrations received from other nodes that have not been answered yet.
+    pub importer_registrations: Vec<(ReactiveId, Address)>,
+    /// Roots contributed by exporters that granted an importer registration.
+    pub granted_basis: BasisStamp,
+    /// Whether a PrepareCommit is being held back until the imports and root changes of the
+    /// transaction are settled.
+    pub prepare_requested: bool,
+    /// Roots most recently announced to the importers of each export whose roots are changing.
+    pub announced_roots: HashMap<ReactiveId, HashSet<ReactiveAddress>>,
+    /// Number of root announcements that have not been acknowledged yet.
+    pub awaiting_root_acks: usize,
+    /// Exporters waiting for us to acknowledge their root announcements.
+    pub root_requesters: Vec<Address>,
+    /// Importers that were announced root changes, which have to learn how the transaction ends.
+    pub root_importers: HashSet<Address>,
 }
 
 impl HeldLocks {
@@ -1,8 +1,16 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+};
 
 use crate::{
-    expr::{eval::ExprEvalContext, Expr, Value},
-    message::{BasisStamp, ReactiveConfiguration, StampedValue},
+    expr::{
+        eval::{EvalError, ExprEvalContext},
+        Expr, Value,
+    },
+    message::{
+        BasisStamp, DefinitionOptions, InputDiagnostics, ReactiveConfiguration, StampedValue,
+    },
 };
 
 use super::ReactiveAddress;
@@ -12,15 +20,24 @@ pub struct Reactive {
     value: Option<StampedValue>,
     read_by: BasisStamp,
 
+    // past values, oldest first, including the current one once it has been produced
+    history: VecDeque<StampedValue>,
+    history_limit: usize,
+
+    // whether this is a definition that has not yet computed a value from its inputs
+    pending: bool,
     changed: bool,
 }
 
 impl Reactive {
-    pub fn new(config: ReactiveConfiguration) -> Reactive {
+    pub fn new(address: ReactiveAddress, config: ReactiveConfiguration) -> Reactive {
         let mut reactive = Reactive {
             definition: None,
             value: None,
             read_by: BasisStamp::empty(),
+            history: VecDeque::new(),
+            history_limit: 0,
+            pending: false,
             changed: false,
         };
 
@@ -29,39 +46,183 @@ impl Reactive {
                 reactive.value = Some(value);
                 reactive.changed = true;
             }
-            ReactiveConfiguration::Definition { expr } => {
-                reactive.definition = Some(Definition::new(expr));
+            // There is no value to migrate, so the reactive is a variable that stays pending until
+            // it is written.
+            ReactiveConfiguration::Migration { .. } => {
+                reactive.pending = true;
+            }
+            ReactiveConfiguration::Definition { expr, options } => {
+                let definition = reactive
+                    .definition
+                    .insert(Definition::new(expr, options, None));
+
+                reactive.value = definition.default_value();
+                reactive.pending = true;
+                reactive.changed = reactive.value.is_some();
+            }
+            ReactiveConfiguration::Fold {
+                init,
+                step,
+                options,
+            } => {
+                let definition = reactive.definition.insert(Definition::new(
+                    step,
+                    options,
+                    Some(Fold {
+                        address,
+                        state: init,
+                    }),
+                ));
+
+                reactive.value = definition.default_value();
+                reactive.pending = true;
+                reactive.changed = reactive.value.is_some();
             }
         }
 
         reactive
     }
 
-    pub fn reconfigure(&mut self, config: ReactiveConfiguration) {
+    /// Changes the configuration of this reactive. A migrated value is stamped with `basis`.
+    ///
+    /// If the configuration is a migration that fails, the reactive is left as it was.
+    pub fn reconfigure(
+        &mut self,
+        address: ReactiveAddress,
+        config: ReactiveConfiguration,
+        basis: BasisStamp,
+    ) -> Result<(), EvalError<ReactiveAddress>> {
         match config {
             ReactiveConfiguration::Variable { value } => {
                 self.definition = None;
                 self.value = Some(value);
+                self.pending = false;
+            }
+            ReactiveConfiguration::Migration { migration } => {
+                let value = self.migrated_value(&address, migration)?;
+                self.migrate(value, basis);
+                return Ok(());
+            }
+            ReactiveConfiguration::Definition { expr, options } => {
+                let definition = if let Some(definition) = &mut self.definition {
+                    definition.reconfigure(expr, options, None);
+                    definition
+                } else {
+                    self.definition.insert(Definition::new(expr, options, None))
+                };
+
+                // The new expression may read inputs that have not received a value yet, in which
+                // case the definition is pending until they do.
+                match definition.compute() {
+                    Some(value) => {
+                        self.value = Some(value);
+                        self.pending = false;
+                    }
+                    None => {
+                        self.value = definition.default_value();
+                        self.pending = true;
+                    }
+                }
             }
-            ReactiveConfiguration::Definition { expr } => {
+            ReactiveConfiguration::Fold {
+                init,
+                step,
+                options,
+            } => {
+                // Carry over the accumulated state, or the current value if this was not a fold
+                // before, as long as it is of the same type as the new initial state.
+                let prior = match self.definition.as_ref().and_then(|d| d.fold.as_ref()) {
+                    Some(fold) => Some(&fold.state),
+                    None => self.value.as_ref().map(|value| &value.value),
+                };
+
+                let conforms = |prior: &Value| {
+                    let (prior, init) = (prior.compute_type(), init.compute_type());
+                    prior.zip(init).and_then(|(a, b)| a.unify(&b)).is_some()
+                };
+                let state = match prior {
+                    Some(prior) if conforms(prior) => prior.clone(),
+                    _ => {
+                        self.value = None;
+                        self.pending = true;
+                        init
+                    }
+                };
+
+                let fold = Some(Fold { address, state });
+
                 let definition = if let Some(definition) = &mut self.definition {
-                    definition.reconfigure(expr);
+                    definition.reconfigure(step, options, fold);
                     definition
                 } else {
-                    self.definition.insert(Definition::new(expr))
+                    self.definition.insert(Definition::new(step, options, fold))
                 };
 
-                self.value = definition.compute();
+                if self.pending {
+                    self.value = definition.default_value();
+                }
             }
         }
 
         self.changed = true;
+
+        Ok(())
+    }
+
+    /// Turns this reactive into a variable holding `value`, as computed by
+    /// [`Reactive::migrated_value`]. The value is applied as a write stamped with `basis`. Without
+    /// a value, the reactive becomes a variable that stays pending until it is written.
+    pub fn migrate(&mut self, value: Option<Value>, basis: BasisStamp) {
+        self.definition = None;
+        match value {
+            Some(value) => self.write(StampedValue { value, basis }),
+            None => {
+                self.value = None;
+                self.pending = true;
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Computes the value `migration` turns the current value of this reactive into, which is the
+    /// declared default while a definition is pending, or `None` if there is no value to migrate.
+    pub fn migrated_value(
+        &self,
+        address: &ReactiveAddress,
+        mut migration: Expr<ReactiveAddress>,
+    ) -> Result<Option<Value>, EvalError<ReactiveAddress>> {
+        let Some(prior) = &self.value else {
+            return Ok(None);
+        };
+
+        migration.eval(&mut MigrationContext {
+            address,
+            value: &prior.value,
+        })?;
+
+        // The migration only reads the reactive itself, whose value is at hand, so it either
+        // evaluates fully or fails.
+        match migration {
+            Expr::Value(value) => Ok(Some(value)),
+            _ => Err(EvalError::Stalled),
+        }
     }
 
     pub fn inputs(&self) -> impl Iterator<Item = &ReactiveAddress> {
         self.definition.iter().flat_map(|d| d.inputs.keys())
     }
 
+    /// Indexes the inputs by the roots they depend on, which the search for batches relies on.
+    /// This is done whenever the roots of the inputs may have changed.
+    pub fn index_roots<'a>(
+        &mut self,
+        roots: impl Fn(&ReactiveAddress) -> Option<&'a HashSet<ReactiveAddress>>,
+    ) {
+        if let Some(definition) = &mut self.definition {
+            definition.index_roots(roots);
+        }
+    }
+
     pub fn add_update(&mut self, sender: ReactiveAddress, value: StampedValue) {
         if let Some(definition) = &mut self.definition {
             definition.add_update(sender, value)
@@ -70,33 +231,123 @@ impl Reactive {
         }
     }
 
-    pub fn next_value<'a>(
-        &mut self,
-        roots: impl Fn(&ReactiveAddress) -> Option<&'a HashSet<ReactiveAddress>>,
-    ) -> Option<&StampedValue> {
+    /// Produces the next value of this reactive, if one is available.
+    ///
+    /// The returned flag is false when the value is unchanged from the prior one and the
+    /// definition suppresses unchanged values. Such a value only advances the basis, so it should
+    /// be passed on to consumers but not reported as a new value.
+    pub fn next_value(&mut self) -> Option<(&StampedValue, bool)> {
         if self.changed {
             self.changed = false;
 
-            if self.value.is_some() {
-                return self.value.as_ref();
+            if let Some(value) = self.value.clone() {
+                self.record(value);
+                return self.value.as_ref().map(|value| (value, true));
             }
         }
 
         if let Some(definition) = &mut self.definition {
-            if let Some(new_value) = definition.find_and_apply_batch(roots) {
-                self.value = Some(new_value);
+            if let Some(new_value) = definition.find_and_apply_batch() {
+                let changed = !definition.options.suppress_unchanged
+                    || self
+                        .value
+                        .as_ref()
+                        .is_none_or(|value| value.value != new_value.value);
 
-                return self.value.as_ref();
+                self.record(new_value.clone());
+                self.pending = false;
+                return Some((self.value.insert(new_value), changed));
             }
         }
 
         None
     }
 
+    /// The current value of this reactive. While the reactive is pending, this is its declared
+    /// default (stamped with an empty basis), if any.
     pub fn value(&self) -> Option<&StampedValue> {
         self.value.as_ref()
     }
 
+    /// Whether this is a definition that has not yet computed a value, because some of its inputs
+    /// have not received a value or its expression could not be fully evaluated.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Keeps up to `limit` of the most recently produced values so that they can be looked up by
+    /// [`Reactive::value_at`]. A limit of zero disables the history.
+    pub fn retain_history(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.truncate_history();
+
+        if limit > 0 && self.history.is_empty() {
+            if let Some(value) = &self.value {
+                self.history.push_back(value.clone());
+            }
+        }
+    }
+
+    /// Finds the value this reactive held when the roots mentioned in `basis` were at the given
+    /// iterations, i.e. the latest retained value whose basis does not exceed `basis` on any of
+    /// them. Roots not mentioned in `basis` are unconstrained.
+    pub fn value_at(&self, basis: &BasisStamp) -> Option<&StampedValue> {
+        self.history.iter().rev().find(|value| {
+            basis
+                .roots
+                .iter()
+                .all(|(root, iteration)| value.basis.latest(root) <= *iteration)
+        })
+    }
+
+    fn record(&mut self, value: StampedValue) {
+        if self.history_limit > 0 {
+            self.history.push_back(value);
+            self.truncate_history();
+        }
+    }
+
+    fn truncate_history(&mut self) {
+        while self.history.len() > self.history_limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Reports the state of the update queue of each input, along with the input that is
+    /// currently preventing a batch from being formed (if any) and why the latest evaluation
+    /// failed (if it did).
+    pub fn diagnostics(
+        &self,
+    ) -> (
+        HashMap<ReactiveAddress, InputDiagnostics>,
+        Option<ReactiveAddress>,
+        Option<EvalError<ReactiveAddress>>,
+    ) {
+        let Some(definition) = &self.definition else {
+            return (HashMap::new(), None, None);
+        };
+
+        let inputs = definition
+            .inputs
+            .iter()
+            .map(|(address, input)| {
+                (
+                    address.clone(),
+                    InputDiagnostics {
+                        queued: input.updates.len(),
+                        dropped: input.dropped,
+                    },
+                )
+            })
+            .collect();
+
+        (
+            inputs,
+            definition.blocking_input(),
+            definition.error().cloned(),
+        )
+    }
+
     pub fn finished_read(&mut self, basis: &BasisStamp) {
         self.read_by.merge_from(basis);
     }
@@ -105,6 +356,7 @@ impl Reactive {
         assert!(self.definition.is_none());
         value.basis.merge_from(&self.read_by);
         self.value = Some(value);
+        self.pending = false;
         self.read_by.clear();
         self.changed = true;
     }
@@ -112,66 +364,172 @@ impl Reactive {
 
 struct Definition {
     inputs: HashMap<ReactiveAddress, Input>,
+    /// The inputs depending on each root. When the basis of a batch advances on some root, only
+    /// the inputs depending on that root might need to advance as well.
+    dependents: HashMap<ReactiveAddress, Vec<ReactiveAddress>>,
     expr: Expr<ReactiveAddress>,
+    options: DefinitionOptions,
+    fold: Option<Fold>,
+    /// Why the latest evaluation failed, if it did.
+    error: Option<EvalError<ReactiveAddress>>,
+}
+
+/// The state threaded through the applied batches of a fold. The fold's step reads the state
+/// through the fold's own address.
+struct Fold {
+    address: ReactiveAddress,
+    state: Value,
 }
 
 #[derive(Debug)]
 struct Input {
+    /// Whether the input is read on every evaluation, as opposed to only in some branches.
+    definite: bool,
     value: Option<StampedValue>,
     updates: Vec<StampedValue>,
+    /// The number of updates discarded by compaction.
+    dropped: usize,
 }
 
-struct EvalContext<'a>(&'a HashMap<ReactiveAddress, Input>);
+struct EvalContext<'a> {
+    inputs: &'a HashMap<ReactiveAddress, Input>,
+    /// The number of queued updates of each input that are read past, so that a batch can be
+    /// evaluated before it is taken off the queues.
+    update_counts: &'a HashMap<ReactiveAddress, usize>,
+    fold: Option<&'a Fold>,
+}
 
-#[derive(Debug)]
-struct BatchInput<'a> {
-    roots: HashSet<ReactiveAddress>,
+/// Resolves reads of a migrating reactive's own address to its value prior to the migration.
+struct MigrationContext<'a> {
+    address: &'a ReactiveAddress,
+    value: &'a Value,
+}
+
+struct Batch {
+    /// The number of updates to take from the queue of each input.
+    update_counts: HashMap<ReactiveAddress, usize>,
     basis: BasisStamp,
-    remaining_updates: &'a [StampedValue],
-    update_count: usize,
 }
 
 impl Definition {
-    pub fn new(expr: Expr<ReactiveAddress>) -> Definition {
-        let mut inputs = HashMap::new();
-
-        expr.visit_reads(&mut |address, _| {
-            inputs.insert(address.clone(), Input::new());
-        });
+    pub fn new(
+        expr: Expr<ReactiveAddress>,
+        options: DefinitionOptions,
+        fold: Option<Fold>,
+    ) -> Definition {
+        let inputs = Self::reads(&expr, fold.as_ref())
+            .into_iter()
+            .map(|(address, definite)| (address, Input::new(definite)))
+            .collect();
 
-        Definition { inputs, expr }
+        Definition {
+            inputs,
+            dependents: HashMap::new(),
+            expr,
+            options,
+            fold,
+            error: None,
+        }
     }
 
-    pub fn reconfigure(&mut self, expr: Expr<ReactiveAddress>) {
-        let mut referenced_inputs = HashSet::new();
-        expr.visit_reads(&mut |address, _| {
-            referenced_inputs.insert(address.clone());
+    pub fn reconfigure(
+        &mut self,
+        expr: Expr<ReactiveAddress>,
+        options: DefinitionOptions,
+        fold: Option<Fold>,
+    ) {
+        let reads = Self::reads(&expr, fold.as_ref());
+        self.inputs.retain(|address, _| reads.contains_key(address));
+        for dependents in self.dependents.values_mut() {
+            dependents.retain(|address| reads.contains_key(address));
+        }
+        for (address, definite) in reads {
             self.inputs
-                .entry(address.clone())
-                .or_insert_with(Input::new);
-        });
-        self.inputs
-            .retain(|address, _| referenced_inputs.contains(address));
+                .entry(address)
+                .or_insert_with(|| Input::new(definite))
+                .definite = definite;
+        }
         self.expr = expr;
+        self.options = options;
+        self.fold = fold;
+    }
+
+    fn index_roots<'a>(
+        &mut self,
+        roots: impl Fn(&ReactiveAddress) -> Option<&'a HashSet<ReactiveAddress>>,
+    ) {
+        self.dependents.clear();
+        for address in self.inputs.keys() {
+            for root in roots(address).expect("input is locally inaccessible") {
+                let dependents = self.dependents.entry(root.clone()).or_default();
+                dependents.push(address.clone());
+            }
+        }
+    }
+
+    /// Collects the inputs read by `expr`, along with whether each is read definitely, i.e. on
+    /// every evaluation rather than only in some branches.
+    fn reads(expr: &Expr<ReactiveAddress>, fold: Option<&Fold>) -> HashMap<ReactiveAddress, bool> {
+        let mut reads = HashMap::<ReactiveAddress, bool>::new();
+        expr.visit_reads(&mut |address, definite| {
+            if fold.is_some_and(|fold| &fold.address == address) {
+                return;
+            }
+
+            *reads.entry(address.clone()).or_default() |= definite;
+        });
+        reads
+    }
+
+    /// Computes the value from the current value of every input, or returns `None` if some
+    /// definite input has not received a value yet or the expression cannot be evaluated without
+    /// one of the other inputs.
+    fn compute(&mut self) -> Option<StampedValue> {
+        let mut basis = BasisStamp::empty();
+        for input in self.inputs.values() {
+            match &input.value {
+                Some(value) => basis.merge_from(&value.basis),
+                None if input.definite => return None,
+                None => {}
+            }
+        }
+
+        let value = self.eval(&HashMap::new())?;
+
+        Some(StampedValue { value, basis })
     }
 
-    fn compute(&self) -> Option<StampedValue> {
+    /// Evaluates the expression with the values the inputs hold once the given numbers of
+    /// queued updates are applied, returning `None` if it cannot be fully evaluated.
+    ///
+    /// A failed evaluation, such as one that divides by zero, leaves the definition without a new
+    /// value, the same as one that lacks some input, but its error is kept for diagnostics.
+    fn eval(&mut self, update_counts: &HashMap<ReactiveAddress, usize>) -> Option<Value> {
         let mut expr = self.expr.clone();
-        expr.eval(&mut EvalContext(&self.inputs));
-        let Expr::Value(value) = expr else {
-            return None;
-        };
+        let result = expr.eval(&mut EvalContext {
+            inputs: &self.inputs,
+            update_counts,
+            fold: self.fold.as_ref(),
+        });
+        self.error = result.err();
+
+        match expr {
+            Expr::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Why the latest evaluation failed, if it did.
+    fn error(&self) -> Option<&EvalError<ReactiveAddress>> {
+        self.error.as_ref()
+    }
 
-        Some(StampedValue {
+    /// The value presented while the definition is pending. It has an empty basis, since it does
+    /// not depend on any iteration of the inputs.
+    fn default_value(&self) -> Option<StampedValue> {
+        self.options.default.clone().map(|value| StampedValue {
             value,
-            basis: self
-                .inputs
-                .values()
-                .map(|input| &input.value.as_ref().unwrap().basis)
-                .fold(BasisStamp::empty(), |mut a, b| {
-                    a.merge_from(&b);
-                    a
-                }),
+            basis: BasisStamp::empty(),
         })
     }
 
@@ -179,19 +537,642 @@ impl Definition {
         self.inputs
             .get_mut(&sender)
             .expect("received update from unknown input")
-            .updates
-            .push(value);
+            .push(value, self.options.queue_limit);
+    }
+
+    /// Finds the input that prevents a batch from being formed, if any.
+    fn blocking_input(&self) -> Option<ReactiveAddress> {
+        match self.find_batch() {
+            Err(blocking) => blocking,
+            Ok(Batch { update_counts, .. }) => update_counts
+                .into_iter()
+                .find(|(address, update_count)| {
+                    let input = &self.inputs[address];
+                    *update_count == 0 && input.value.is_none() && input.definite
+                })
+                .map(|(address, _)| address),
+        }
+    }
+
+    fn find_and_apply_batch(&mut self) -> Option<StampedValue> {
+        let Ok(Batch {
+            update_counts,
+            mut basis,
+        }) = self.find_batch()
+        else {
+            return None;
+        };
+
+        for (address, update_count) in &update_counts {
+            let input = &self.inputs[address];
+            match input.value_after(*update_count) {
+                // The basis we computed earlier only includes basis stamps from updated inputs.
+                // But we need to include the basis stamp from every input. Since this one was not
+                // updated, it has not been included yet, and so we need to add it.
+                Some(value) if *update_count == 0 => basis.merge_from(&value.basis),
+                Some(_) => {}
+                // The definition stays pending until a later batch includes this input.
+                None if input.definite => return None,
+                None => {}
+            }
+        }
+
+        // The batch is evaluated before its updates are taken off the queues, so that a batch
+        // that cannot be evaluated yet stays queued rather than being lost, which matters to folds
+        // in particular. A batch whose evaluation fails is taken off all the same, since it would
+        // fail again on every retry and hold back all updates after it.
+        let value = self.eval(&update_counts);
+        if value.is_none() && self.error.is_none() {
+            return None;
+        }
+
+        for (address, update_count) in update_counts {
+            let input = self.inputs.get_mut(&address).unwrap();
+
+            debug_assert!(update_count <= input.updates.len());
+
+            if let Some(value) = input.updates.drain(0..update_count).next_back() {
+                input.value = Some(value);
+            }
+        }
+
+        let value = value?;
+
+        if let Some(fold) = &mut self.fold {
+            fold.state = value.clone();
+        }
+
+        Some(StampedValue { value, basis })
+    }
+
+    /// Searches for a batch of updates that can be applied together.
+    ///
+    /// If there is no batch, returns the input that lacked an update the last time a batch was
+    /// attempted, or `None` if no input has any updates.
+    fn find_batch(&self) -> Result<Batch, Option<ReactiveAddress>> {
+        let empty = BasisStamp::empty();
+        let mut blocking = None;
+
+        // Inputs we have already tried as a seed. Since they were considered already, we know
+        // there are definitely no valid batches available now that involve their updates.
+        let mut explored = HashSet::new();
+
+        'seeds: for (seed, seed_input) in &self.inputs {
+            let Some(seed_update) = seed_input.updates.first() else {
+                explored.insert(seed);
+                continue 'seeds;
+            };
+
+            // The number of updates we have taken off the queue of each input.
+            let mut update_counts = HashMap::from([(seed, 1)]);
+            let mut basis = seed_update.basis.clone();
+
+            // Roots on which the basis has advanced since the inputs depending on them were last
+            // checked.
+            let mut advanced = seed_update.basis.roots.keys().collect::<Vec<_>>();
+
+            // Inputs that have yet to receive a value join the batch with their first update, so
+            // that a batch held back for lack of their value can be completed once they have one.
+            for (address, input) in &self.inputs {
+                if address == seed || input.value.is_some() || explored.contains(address) {
+                    continue;
+                }
+
+                if let Some(update) = input.updates.first() {
+                    update_counts.insert(address, 1);
+                    basis.merge_from(&update.basis);
+                    advanced.extend(update.basis.roots.keys());
+                }
+            }
+
+            while let Some(root) = advanced.pop() {
+                for address in self.dependents.get(root).into_iter().flatten() {
+                    let input = &self.inputs[address];
+
+                    loop {
+                        let update_count = update_counts.get(address).copied().unwrap_or(0);
+                        let input_basis = match update_count {
+                            0 => input.value.as_ref().map_or(&empty, |v| &v.basis),
+                            n => &input.updates[n - 1].basis,
+                        };
+
+                        if basis.latest(root) <= input_basis.latest(root) {
+                            break;
+                        }
+
+                        // An input read only in some branches that has yet to produce anything
+                        // cannot hold the batch back, since there is no value of it that could be
+                        // inconsistent with the batch. If the taken branch needs it after all, the
+                        // definition stays pending.
+                        if !input.definite && input.value.is_none() && input.updates.is_empty() {
+                            break;
+                        }
+
+                        let (Some(update), false) =
+                            (input.updates.get(update_count), explored.contains(address))
+                        else {
+                            // We need an update from this input, but the input does not have an
+                            // update to give us. That means there is no batch possible for the
+                            // current seed.
+                            explored.insert(seed);
+                            blocking = Some(address.clone());
+                            continue 'seeds;
+                        };
+
+                        update_counts.insert(address, update_count + 1);
+
+                        for (root, iteration) in &update.basis.roots {
+                            if *iteration > basis.latest(root) {
+                                basis.add(root.clone(), *iteration);
+                                advanced.push(root);
+                            }
+                        }
+                    }
+                }
+            }
+
+            return Ok(Batch {
+                update_counts: self
+                    .inputs
+                    .keys()
+                    .map(|address| {
+                        let update_count = update_counts.get(address).copied().unwrap_or(0);
+                        (address.clone(), update_count)
+                    })
+                    .collect(),
+                basis,
+            });
+        }
+
+        Err(blocking)
+    }
+}
+
+impl Input {
+    pub fn new(definite: bool) -> Input {
+        Input {
+            definite,
+            value: None,
+            updates: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// The value of this input once the first `update_count` queued updates are applied.
+    fn value_after(&self, update_count: usize) -> Option<&StampedValue> {
+        match update_count {
+            0 => self.value.as_ref(),
+            n => Some(&self.updates[n - 1]),
+        }
+    }
+
+    /// Queues `update`, compacting the queue so that it holds no more than `limit` updates.
+    ///
+    /// Every queued update is subsumed by the basis of the updates after it, so discarding the
+    /// oldest ones only skips intermediate values and never causes a glitch.
+    fn push(&mut self, update: StampedValue, limit: Option<NonZeroUsize>) {
+        // An update that does not advance past the applied value is subsumed by it.
+        if let Some(value) = &self.value {
+            if !update.basis.is_empty() && update.basis.prec_eq(&value.basis) {
+                self.dropped += 1;
+                return;
+            }
+        }
+
+        self.updates.push(update);
+
+        if let Some(limit) = limit.map(NonZeroUsize::get) {
+            if self.updates.len() > limit {
+                let excess = self.updates.len() - limit;
+                self.updates.drain(0..excess);
+                self.dropped += excess;
+            }
+        }
     }
+}
 
-    fn find_and_apply_batch<'a>(
+impl<'a> ExprEvalContext<ReactiveAddress> for EvalContext<'a> {
+    fn read(
         &mut self,
+        address: &ReactiveAddress,
+    ) -> Result<Option<&Value>, EvalError<ReactiveAddress>> {
+        if let Some(fold) = self.fold.filter(|fold| &fold.address == address) {
+            return Ok(Some(&fold.state));
+        }
+
+        match self.inputs.get(address) {
+            Some(input) => {
+                let update_count = self.update_counts.get(address).copied().unwrap_or(0);
+                Ok(input.value_after(update_count).map(|value| &value.value))
+            }
+            None => Err(EvalError::MissingReactive(address.clone())),
+        }
+    }
+}
+
+impl<'a> ExprEvalContext<ReactiveAddress> for MigrationContext<'a> {
+    fn read(
+        &mut self,
+        address: &ReactiveAddress,
+    ) -> Result<Option<&Value>, EvalError<ReactiveAddress>> {
+        if address == self.address {
+            Ok(Some(self.value))
+        } else {
+            Err(EvalError::MissingReactive(address.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        num::NonZeroUsize,
+    };
+
+    use crate::{
+        actor::Address,
+        expr::{eval::EvalError, BinaryOp, Expr, Value},
+        message::{BasisStamp, DefinitionOptions, Iteration, ReactiveConfiguration, StampedValue},
+        node::{ReactiveAddress, ReactiveId},
+    };
+
+    use super::{Batch, Definition, Input, Reactive};
+
+    fn address(id: usize) -> ReactiveAddress {
+        ReactiveAddress {
+            address: Address::from_index(0),
+            id: ReactiveId(id),
+        }
+    }
+
+    fn read(id: usize) -> Box<Expr<ReactiveAddress>> {
+        Box::new(Expr::Read(address(id)))
+    }
+
+    fn int(value: isize) -> Box<Expr<ReactiveAddress>> {
+        Box::new(Expr::Value(Value::Integer(value)))
+    }
+
+    /// An update of the variable `id` at the given iteration.
+    fn update(id: usize, iteration: usize, value: isize) -> StampedValue {
+        let mut basis = BasisStamp::empty();
+        let iteration = (0..iteration).fold(Iteration::ZERO, |i, _| i.increment());
+        basis.add(address(id), iteration);
+        StampedValue {
+            value: Value::Integer(value),
+            basis,
+        }
+    }
+
+    /// Every input is a variable, and so its own only root.
+    fn roots(ids: &[usize]) -> HashMap<ReactiveAddress, HashSet<ReactiveAddress>> {
+        ids.iter()
+            .map(|&id| (address(id), HashSet::from([address(id)])))
+            .collect()
+    }
+
+    fn values(
+        reactive: &mut Reactive,
+        roots: &HashMap<ReactiveAddress, HashSet<ReactiveAddress>>,
+    ) -> Vec<Value> {
+        reactive.index_roots(|address| roots.get(address));
+        let mut values = Vec::new();
+        while let Some((value, _)) = reactive.next_value() {
+            values.push(value.value.clone());
+        }
+        values
+    }
+
+    #[test]
+    fn queues_are_bounded_and_report_what_blocks_them() {
+        // Both inputs are computed from the same root, so a batch needs an update of each.
+        let (a, b, root) = (address(1), address(2), address(3));
+        let roots = HashMap::from([
+            (a.clone(), HashSet::from([root.clone()])),
+            (b.clone(), HashSet::from([root.clone()])),
+        ]);
+        let update = |iteration: usize| {
+            let mut basis = BasisStamp::empty();
+            let stamp = (0..iteration).fold(Iteration::ZERO, |i, _| i.increment());
+            basis.add(root.clone(), stamp);
+            StampedValue {
+                value: Value::Integer(iteration as isize),
+                basis,
+            }
+        };
+
+        let mut definition = Reactive::new(
+            address(0),
+            ReactiveConfiguration::Definition {
+                expr: Expr::Tuple(Box::new([Expr::Read(a.clone()), Expr::Read(b.clone())])),
+                options: DefinitionOptions {
+                    queue_limit: NonZeroUsize::new(2),
+                    ..DefinitionOptions::default()
+                },
+            },
+        );
+        definition.index_roots(|address| roots.get(address));
+        for iteration in 1..=5 {
+            definition.add_update(a.clone(), update(iteration));
+        }
+        assert!(definition.next_value().is_none());
+
+        let (inputs, blocked_on, _) = definition.diagnostics();
+        assert_eq!((inputs[&a].queued, inputs[&a].dropped), (2, 3));
+        assert_eq!(inputs[&b].queued, 0);
+        assert_eq!(blocked_on, Some(b.clone()));
+
+        // Once the other input catches up, the latest updates form a batch.
+        definition.add_update(b.clone(), update(5));
+        let value = definition
+            .next_value()
+            .map(|(value, _)| value.value.clone());
+        let Some(Value::Tuple(items)) = value else {
+            panic!("no batch was formed");
+        };
+        assert!(matches!(*items, [Value::Integer(5), Value::Integer(5)]));
+    }
+
+    #[test]
+    fn folds_thread_their_state_through_each_batch() {
+        let (me, input) = (address(0), address(1));
+        let roots = roots(&[1]);
+
+        // The state starts out as the latest update.
+        let mut fold = Reactive::new(
+            me.clone(),
+            ReactiveConfiguration::Fold {
+                init: Value::Integer(0),
+                step: Expr::Read(input.clone()),
+                options: DefinitionOptions::default(),
+            },
+        );
+        fold.add_update(input.clone(), update(1, 1, 1));
+        fold.add_update(input.clone(), update(1, 2, 2));
+        assert_eq!(values(&mut fold, &roots), [1, 2].map(Value::Integer));
+
+        // A new step of the same type carries the state over, which it pairs with each update.
+        let pair = Expr::Tuple(Box::new([
+            Expr::Read(me.clone()),
+            Expr::Read(input.clone()),
+        ]));
+        let config = ReactiveConfiguration::Fold {
+            init: Value::Integer(0),
+            step: pair,
+            options: DefinitionOptions::default(),
+        };
+        fold.reconfigure(me, config, BasisStamp::empty()).unwrap();
+        fold.add_update(input.clone(), update(1, 3, 3));
+        let pair = Value::Tuple(Box::new([Value::Integer(2), Value::Integer(3)]));
+        assert_eq!(values(&mut fold, &roots).last(), Some(&pair));
+    }
+
+    #[test]
+    fn definitions_are_pending_until_every_input_has_a_value() {
+        let mut definition = Reactive::new(
+            address(0),
+            ReactiveConfiguration::Definition {
+                expr: *read(1),
+                options: DefinitionOptions {
+                    default: Some(Value::Integer(7)),
+                    ..DefinitionOptions::default()
+                },
+            },
+        );
+        assert!(definition.is_pending());
+        assert_eq!(
+            definition.value().map(|value| &value.value),
+            Some(&Value::Integer(7))
+        );
+
+        // Reading another input without a value leaves it pending, without a default this time.
+        let pair = Expr::Tuple(Box::new([*read(1), *read(2)]));
+        let config = ReactiveConfiguration::Definition {
+            expr: pair,
+            options: DefinitionOptions::default(),
+        };
+        definition
+            .reconfigure(address(0), config, BasisStamp::empty())
+            .unwrap();
+        let roots = roots(&[1, 2]);
+        definition.index_roots(|address| roots.get(address));
+        assert!(definition.is_pending());
+        assert!(definition.value().is_none());
+
+        definition.add_update(address(1), update(1, 1, 1));
+        assert!(definition.next_value().is_none());
+        definition.add_update(address(2), update(2, 1, 2));
+        let value = definition
+            .next_value()
+            .map(|(value, _)| value.value.clone());
+        let pair = Value::Tuple(Box::new([Value::Integer(1), Value::Integer(2)]));
+        assert_eq!(value, Some(pair));
+        assert!(!definition.is_pending());
+    }
+
+    #[test]
+    fn migrations_compute_a_variable_from_the_prior_value() {
+        let config = ReactiveConfiguration::Definition {
+            expr: Expr::Read(address(1)),
+            options: DefinitionOptions::default(),
+        };
+        let mut reactive = Reactive::new(address(0), config);
+        let roots = roots(&[1]);
+        reactive.index_roots(|address| roots.get(address));
+        reactive.add_update(address(1), update(1, 1, 1));
+        assert!(reactive.next_value().is_some());
+
+        let migration = Expr::Tuple(Box::new([
+            Expr::Read(address(0)),
+            Expr::Value(Value::Integer(2)),
+        ]));
+        let basis = update(0, 1, 0).basis;
+        let config = ReactiveConfiguration::Migration { migration };
+        reactive.reconfigure(address(0), config, basis).unwrap();
+
+        let pair = Value::Tuple(Box::new([Value::Integer(1), Value::Integer(2)]));
+        assert_eq!(reactive.value().map(|value| &value.value), Some(&pair));
+        assert_eq!(reactive.inputs().count(), 0);
+        assert!(!reactive.is_pending());
+    }
+
+    #[test]
+    fn untaken_branches_do_not_hold_back_batches() {
+        let condition = Box::new(Expr::Binary(BinaryOp::Gt, read(1), int(0)));
+        let config = ReactiveConfiguration::Definition {
+            expr: Expr::If(condition, read(2), read(3)),
+            options: DefinitionOptions::default(),
+        };
+        let mut definition = Reactive::new(address(0), config);
+        let roots = roots(&[1, 2, 3]);
+        definition.index_roots(|address| roots.get(address));
+
+        // The condition is read on every evaluation, so nothing is computed without it.
+        definition.add_update(address(2), update(2, 1, 2));
+        assert!(definition.next_value().is_none());
+        definition.add_update(address(1), update(1, 1, 1));
+        let value = definition
+            .next_value()
+            .map(|(value, _)| value.value.clone());
+        assert_eq!(value, Some(Value::Integer(2)));
+        // Nor is the definition blocked on the input of the other branch.
+        assert_eq!(definition.diagnostics().1, None);
+
+        definition.add_update(address(3), update(3, 1, 3));
+        let value = definition
+            .next_value()
+            .map(|(value, _)| value.value.clone());
+        assert_eq!(value, Some(Value::Integer(2)));
+        definition.add_update(address(1), update(1, 2, 0));
+        let value = definition
+            .next_value()
+            .map(|(value, _)| value.value.clone());
+        assert_eq!(value, Some(Value::Integer(3)));
+    }
+
+    #[test]
+    fn batches_that_cannot_be_evaluated_yet_stay_queued() {
+        // Sums the second input over the updates of the first one that are positive. The second
+        // input is only read once the first one is positive.
+        let step = Expr::If(
+            Box::new(Expr::Binary(BinaryOp::Gt, read(1), int(0))),
+            Box::new(Expr::Binary(BinaryOp::Add, read(0), read(2))),
+            read(0),
+        );
+        let mut fold = Reactive::new(
+            address(0),
+            ReactiveConfiguration::Fold {
+                init: Value::Integer(0),
+                step,
+                options: DefinitionOptions::default(),
+            },
+        );
+        let roots = roots(&[1, 2]);
+
+        fold.add_update(address(1), update(1, 1, 1));
+        fold.add_update(address(1), update(1, 2, 2));
+        assert_eq!(values(&mut fold, &roots), vec![]);
+        assert!(fold.is_pending());
+
+        // Both updates of the first input are counted once the second input has a value.
+        fold.add_update(address(2), update(2, 1, 5));
+        assert_eq!(
+            values(&mut fold, &roots),
+            vec![Value::Integer(5), Value::Integer(10)]
+        );
+    }
+
+    #[test]
+    fn failed_evaluations_are_reported_and_skipped() {
+        let mut definition = Reactive::new(
+            address(0),
+            ReactiveConfiguration::Definition {
+                expr: Expr::Binary(BinaryOp::Div, int(10), read(1)),
+                options: DefinitionOptions::default(),
+            },
+        );
+        let roots = roots(&[1]);
+
+        definition.add_update(address(1), update(1, 1, 0));
+        assert_eq!(values(&mut definition, &roots), vec![]);
+
+        let (inputs, _, error) = definition.diagnostics();
+        assert_eq!(error, Some(EvalError::DivisionByZero));
+        assert_eq!(inputs[&address(1)].queued, 0);
+
+        definition.add_update(address(1), update(1, 2, 2));
+        assert_eq!(values(&mut definition, &roots), vec![Value::Integer(5)]);
+
+        let (_, _, error) = definition.diagnostics();
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn migrations_carry_over_values_and_pending_states() {
+        let increment = || Expr::Binary(BinaryOp::Add, read(0), int(1));
+
+        let mut variable = Reactive::new(
+            address(0),
+            ReactiveConfiguration::Variable {
+                value: update(0, 1, 1),
+            },
+        );
+        let migration = ReactiveConfiguration::Migration {
+            migration: increment(),
+        };
+        assert_eq!(
+            variable.reconfigure(address(0), migration, update(0, 2, 0).basis),
+            Ok(())
+        );
+        assert_eq!(
+            variable.value().map(|value| &value.value),
+            Some(&Value::Integer(2))
+        );
+
+        // A pending definition is migrated from its default, or stays pending without one.
+        for (default, migrated) in [
+            (Some(Value::Integer(5)), Some(Value::Integer(6))),
+            (None, None),
+        ] {
+            let mut definition = Reactive::new(
+                address(0),
+                ReactiveConfiguration::Definition {
+                    expr: *read(1),
+                    options: DefinitionOptions {
+                        default,
+                        ..DefinitionOptions::default()
+                    },
+                },
+            );
+            let migration = ReactiveConfiguration::Migration {
+                migration: increment(),
+            };
+            assert_eq!(
+                definition.reconfigure(address(0), migration, BasisStamp::empty()),
+                Ok(())
+            );
+            assert_eq!(
+                definition.value().map(|value| value.value.clone()),
+                migrated
+            );
+            assert_eq!(definition.is_pending(), migrated.is_none());
+        }
+
+        // Migrations cannot read other reactives, and a failed one leaves the reactive as it was.
+        let migration = ReactiveConfiguration::Migration {
+            migration: Expr::Binary(BinaryOp::Add, read(0), read(1)),
+        };
+        assert_eq!(
+            variable.reconfigure(address(0), migration, BasisStamp::empty()),
+            Err(EvalError::MissingReactive(address(1)))
+        );
+        assert_eq!(
+            variable.value().map(|value| &value.value),
+            Some(&Value::Integer(2))
+        );
+    }
+
+    #[derive(Debug)]
+    struct BatchInput<'a> {
+        roots: HashSet<ReactiveAddress>,
+        basis: BasisStamp,
+        remaining_updates: &'a [StampedValue],
+        update_count: usize,
+    }
+
+    /// Finds a batch the way it was done before inputs were indexed by root, by sweeping over
+    /// every input until none is behind the batch on any of its roots. The one change is that it
+    /// stops at the first batch found rather than going on to the last, as the search does now.
+    fn find_batch_by_sweeping<'a>(
+        definition: &Definition,
         roots: impl Fn(&ReactiveAddress) -> Option<&'a HashSet<ReactiveAddress>>,
-    ) -> Option<StampedValue> {
-        let mut found = None;
+    ) -> Result<Batch, Option<ReactiveAddress>> {
+        let mut blocking = None;
 
         let mut explored = HashSet::new();
-        'seeds: for seed in self.inputs.keys() {
-            let mut inputs = self
+        'seeds: for seed in definition.inputs.keys() {
+            let mut inputs = definition
                 .inputs
                 .iter()
                 .map(|(address, input)| {
@@ -211,7 +1192,7 @@ impl Definition {
                             // are definitely no valid batches available now that involve this
                             // input.
                             remaining_updates: if !explored.contains(address) {
-                                &*input.updates
+                                &input.updates
                             } else {
                                 &[]
                             },
@@ -237,13 +1218,14 @@ impl Definition {
 
             while {
                 let mut changed = false;
-                for (_, input) in inputs.iter_mut() {
+                for (address, input) in inputs.iter_mut() {
                     while !basis.prec_eq_wrt_roots(&input.basis, &input.roots) {
                         let Some((update, rest)) = input.remaining_updates.split_first() else {
                             // We need an update from this input, but the input does not have an
                             // update to give us. That means there is no batch possible for the
                             // current seed.
                             explored.insert(seed.clone());
+                            blocking = Some((*address).clone());
                             continue 'seeds;
                         };
 
@@ -266,64 +1248,147 @@ impl Definition {
                         input.update_count - input.remaining_updates.len(),
                     )
                 })
-                .collect::<Vec<_>>();
+                .collect();
 
-            found = Some((update_counts, basis));
+            return Ok(Batch {
+                update_counts,
+                basis,
+            });
         }
 
-        let Some((update_counts, mut basis)) = found else {
-            return None;
+        Err(blocking)
+    }
+
+    /// The update counts and basis of a batch, if one was found.
+    fn batch(
+        found: Result<Batch, Option<ReactiveAddress>>,
+    ) -> Option<(
+        HashMap<ReactiveAddress, usize>,
+        HashMap<ReactiveAddress, Iteration>,
+    )> {
+        found
+            .ok()
+            .map(|batch| (batch.update_counts, batch.basis.roots))
+    }
+
+    /// A definition with `inputs` inputs computed from up to three of `roots` roots each, whose
+    /// queues hold the updates that reached it after the roots were written `writes` times, with
+    /// the inputs lagging behind at random. Every input is read definitely and holds a value, as
+    /// the search assumed before inputs were indexed. Returns the definition along with the roots
+    /// of each input.
+    fn scenario(
+        seed: u64,
+        inputs: usize,
+        roots: usize,
+        writes: usize,
+    ) -> (
+        Definition,
+        HashMap<ReactiveAddress, HashSet<ReactiveAddress>>,
+    ) {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut below = |bound: usize| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
         };
 
-        let mut complete = true;
-        for (address, update_count) in update_counts {
-            let input = self.inputs.get_mut(&address).unwrap();
+        let root = |index: usize| address(inputs + index);
+        let input_roots = (0..inputs)
+            .map(|id| {
+                let roots = (0..=below(3)).map(|_| root(below(roots))).collect();
+                (address(id), roots)
+            })
+            .collect::<HashMap<_, HashSet<_>>>();
 
-            debug_assert!(input.updates.len() <= update_count);
+        let mut iterations = vec![Iteration::ZERO; roots];
+        let mut in_flight = HashMap::<_, Vec<StampedValue>>::new();
+        let mut delivered = HashMap::<_, Vec<StampedValue>>::new();
+        for _ in 0..writes {
+            let written = below(roots);
+            iterations[written] = iterations[written].increment();
+            for (input, input_roots) in &input_roots {
+                if !input_roots.contains(&root(written)) {
+                    continue;
+                }
 
-            if let Some(value) = input.updates.drain(0..update_count).last() {
-                input.value = Some(value);
-            } else if let Some(value) = &input.value {
-                // The basis we computed earlier only includes basis stamps from updated inputs.
-                // But we need to include the basis stamp from every input. Since this one was not
-                // updated, it has not been included yet, and so we need to add it.
-                basis.merge_from(&value.basis);
-            } else {
-                complete = false;
+                let mut basis = BasisStamp::empty();
+                for root in input_roots {
+                    let iteration = iterations[root.id.0 - inputs];
+                    if iteration > Iteration::ZERO {
+                        basis.add(root.clone(), iteration);
+                    }
+                }
+                let value = Value::Integer(below(10) as isize);
+                in_flight
+                    .entry(input.clone())
+                    .or_default()
+                    .push(StampedValue { value, basis });
             }
-        }
 
-        if !complete {
-            return None;
+            for (input, updates) in &mut in_flight {
+                let arrived = below(updates.len() + 1);
+                delivered
+                    .entry(input.clone())
+                    .or_default()
+                    .extend(updates.drain(..arrived));
+            }
         }
 
-        let mut expr = self.expr.clone();
-        expr.eval(&mut EvalContext(&self.inputs));
-        let Expr::Value(value) = expr else {
-            panic!("expr did not fully evaluate")
+        let inputs = input_roots
+            .keys()
+            .map(|address| {
+                let mut updates = delivered.remove(address).unwrap_or_default();
+                let value = match below(3) {
+                    0 if !updates.is_empty() => updates.remove(0),
+                    _ => StampedValue {
+                        value: Value::Integer(0),
+                        basis: BasisStamp::empty(),
+                    },
+                };
+                let input = Input {
+                    definite: true,
+                    value: Some(value),
+                    updates,
+                    dropped: 0,
+                };
+                (address.clone(), input)
+            })
+            .collect();
+        let mut definition = Definition {
+            inputs,
+            dependents: HashMap::new(),
+            expr: Expr::Value(Value::Unit),
+            options: DefinitionOptions::default(),
+            fold: None,
+            error: None,
         };
-
-        Some(StampedValue { value, basis })
+        definition.index_roots(|address| input_roots.get(address));
+        (definition, input_roots)
     }
-}
 
-impl Input {
-    pub fn new() -> Input {
-        Input {
-            value: None,
-            updates: Vec::new(),
-        }
-    }
-}
+    #[test]
+    fn indexed_batches_are_those_found_by_sweeping() {
+        for seed in 0..1000 {
+            let (mut definition, roots) = scenario(
+                seed,
+                1 + seed as usize % 8,
+                1 + seed as usize % 4,
+                seed as usize % 12,
+            );
 
-impl<'a> ExprEvalContext<ReactiveAddress> for EvalContext<'a> {
-    fn read(&mut self, address: &ReactiveAddress) -> Option<&Value> {
-        match self.0.get(address) {
-            Some(input) => match &input.value {
-                Some(value) => Some(&value.value),
-                None => None,
-            },
-            None => None,
+            // Applying each batch in turn compares the batches found after it as well.
+            loop {
+                let indexed = definition.find_batch();
+                let swept = find_batch_by_sweeping(&definition, |address| roots.get(address));
+                assert_eq!(indexed.is_ok(), swept.is_ok(), "seed {seed}");
+                assert_eq!(batch(indexed), batch(swept), "seed {seed}");
+
+                if definition.find_and_apply_batch().is_none() {
+                    break;
+                }
+            }
         }
     }
 }
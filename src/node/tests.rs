@@ -0,0 +1,880 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    actor::{Actor, Address, Context, System},
+    expr::{eval::EvalError, BinaryOp, Expr, Value},
+    message::{
+        BasisStamp, DefinitionOptions, ImportConfiguration, Iteration, LockKind, Message,
+        MonotonicTimestampGenerator, PropagationMode, ReactiveConfiguration, StampedValue, TxId,
+        TxPriority,
+    },
+};
+
+use super::{Node, ReactiveAddress, ReactiveId};
+
+/// An actor recording every message sent to it.
+struct Recorder {
+    log: Arc<Mutex<Vec<Message>>>,
+}
+
+impl Actor for Recorder {
+    fn handle(&mut self, message: Message, _ctx: Context) {
+        self.log.lock().unwrap().push(message);
+    }
+}
+
+/// An actor that is gone as soon as it is sent anything.
+struct Retiring;
+
+impl Actor for Retiring {
+    fn handle(&mut self, _message: Message, ctx: Context) {
+        ctx.retire();
+    }
+}
+
+/// A set of nodes driven step by step by a client that coordinates transactions by hand.
+pub struct Network {
+    pub system: System,
+    pub client: Address,
+    pub nodes: Vec<Address>,
+    log: Arc<Mutex<Vec<Message>>>,
+    timestamps: MonotonicTimestampGenerator,
+}
+
+impl Network {
+    pub fn new(nodes: usize) -> Network {
+        let mut system = System::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = system.spawn(Recorder { log: log.clone() });
+        let nodes = (0..nodes).map(|_| system.spawn(Node::new())).collect();
+
+        Network {
+            system,
+            client,
+            nodes,
+            log,
+            timestamps: MonotonicTimestampGenerator::new(),
+        }
+    }
+
+    /// Sends `message` from the client to the actor at `target`.
+    pub fn send(&mut self, target: &Address, message: Message) {
+        self.system.send(&self.client, target, message);
+    }
+
+    /// Takes the messages received by the client so far.
+    pub fn received(&mut self) -> Vec<Message> {
+        std::mem::take(&mut *self.log.lock().unwrap())
+    }
+
+    /// Takes the messages received by the client so far that match `filter`, leaving the others.
+    fn take(&mut self, filter: impl Fn(&Message) -> bool) -> Vec<Message> {
+        let mut log = self.log.lock().unwrap();
+        let (taken, kept) = std::mem::take(&mut *log).into_iter().partition(filter);
+        *log = kept;
+        taken
+    }
+
+    /// Starts a transaction holding locks of `kind` on the given nodes, waiting until they are
+    /// all granted.
+    pub fn lock(&mut self, nodes: &[usize], kind: LockKind) -> TxId {
+        let txid = TxId {
+            priority: TxPriority::Low,
+            timestamp: self.timestamps.generate_timestamp(),
+            address: self.client.clone(),
+        };
+
+        for &node in nodes {
+            let target = self.nodes[node].clone();
+            self.send(
+                &target,
+                Message::Lock {
+                    txid: txid.clone(),
+                    kind,
+                },
+            );
+        }
+        self.system.run();
+
+        let granted = self
+            .take(|message| matches!(message, Message::LockGranted { .. }))
+            .len();
+        assert_eq!(granted, nodes.len());
+
+        txid
+    }
+
+    /// Asks the given nodes to prepare `txid`, returning the delivered messages.
+    pub fn prepare(&mut self, txid: &TxId, nodes: &[usize]) -> Vec<(Address, Address, Message)> {
+        for &node in nodes {
+            let target = self.nodes[node].clone();
+            self.send(&target, Message::PrepareCommit { txid: txid.clone() });
+        }
+
+        self.system.run_traced()
+    }
+
+    /// Commits `txid` on the given nodes once they have all prepared, returning the delivered
+    /// messages.
+    pub fn commit(&mut self, txid: &TxId, nodes: &[usize]) -> Vec<(Address, Address, Message)> {
+        let mut basis = BasisStamp::empty();
+        let mut prepared = 0;
+        for message in self.take(|message| matches!(message, Message::CommitPrepared { .. })) {
+            if let Message::CommitPrepared { basis: b, .. } = message {
+                basis.merge_from(&b);
+                prepared += 1;
+            }
+        }
+        assert_eq!(prepared, nodes.len());
+
+        for &node in nodes {
+            let target = self.nodes[node].clone();
+            self.send(
+                &target,
+                Message::Commit {
+                    txid: txid.clone(),
+                    basis: basis.clone(),
+                },
+            );
+        }
+
+        self.system.run_traced()
+    }
+
+    /// Configures the reactives of a node under the exclusive lock `txid`.
+    pub fn configure(
+        &mut self,
+        txid: &TxId,
+        node: usize,
+        reactives: Vec<(usize, Option<ReactiveConfiguration>)>,
+    ) {
+        let target = self.nodes[node].clone();
+        self.send(
+            &target,
+            Message::Configure {
+                txid: txid.clone(),
+                imports: HashMap::new(),
+                reactives: reactives
+                    .into_iter()
+                    .map(|(id, config)| (ReactiveId(id), config))
+                    .collect(),
+                exports: HashMap::new(),
+            },
+        );
+    }
+
+    /// Reads the reactive `id` of a node under a shared lock, once it has a value.
+    fn read(&mut self, node: usize, id: usize) -> Value {
+        let txid = self.lock(&[node], LockKind::Shared);
+        let target = self.nodes[node].clone();
+        self.send(
+            &target,
+            Message::Read {
+                txid: txid.clone(),
+                reactive: ReactiveId(id),
+                basis: BasisStamp::empty(),
+            },
+        );
+        self.system.run();
+
+        let mut results = self.take(|message| matches!(message, Message::ReadResult { .. }));
+        let Some(Message::ReadResult { value, .. }) = results.pop() else {
+            panic!("reactive {id} on node {node} has no value");
+        };
+
+        self.send(&target, Message::Abort { txid });
+        self.system.run();
+
+        value.value
+    }
+
+    /// Runs a transaction writing `value` to a variable.
+    pub fn write(&mut self, node: usize, id: usize, value: Value) {
+        let txid = self.lock(&[node], LockKind::Exclusive);
+        let target = self.nodes[node].clone();
+        self.send(
+            &target,
+            Message::Write {
+                txid: txid.clone(),
+                reactive: ReactiveId(id),
+                value,
+            },
+        );
+        self.prepare(&txid, &[node]);
+        self.commit(&txid, &[node]);
+    }
+
+    pub fn address(&self, node: usize, id: usize) -> ReactiveAddress {
+        ReactiveAddress {
+            address: self.nodes[node].clone(),
+            id: ReactiveId(id),
+        }
+    }
+
+    /// The latest value streamed to the client for the reactive at `address`.
+    pub fn latest_value(messages: &[Message], address: &ReactiveAddress) -> Option<Value> {
+        messages.iter().rev().find_map(|message| match message {
+            Message::ValueChanged { reactive, value } if reactive == address => {
+                Some(value.value.clone())
+            }
+            _ => None,
+        })
+    }
+}
+
+pub fn var(value: Value) -> Option<ReactiveConfiguration> {
+    Some(ReactiveConfiguration::Variable {
+        value: StampedValue {
+            value,
+            basis: BasisStamp::empty(),
+        },
+    })
+}
+
+pub fn def(expr: Expr<ReactiveAddress>) -> Option<ReactiveConfiguration> {
+    Some(ReactiveConfiguration::Definition {
+        expr,
+        options: DefinitionOptions::default(),
+    })
+}
+
+#[test]
+fn writes_reach_every_definition_downstream() {
+    let mut network = Network::new(1);
+
+    // Definitions 1 to 4 each read variable 0, definition 5 reads definition 4 and definition 6
+    // reads definition 5.
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let mut reactives = vec![(0, var(Value::Integer(1)))];
+    for id in 1..5 {
+        reactives.push((id, def(Expr::Read(network.address(0, 0)))));
+    }
+    for id in 5..7 {
+        reactives.push((id, def(Expr::Read(network.address(0, id - 1)))));
+    }
+    network.configure(&txid, 0, reactives);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    for value in [2, 3] {
+        network.write(0, 0, Value::Integer(value));
+        for id in 1..7 {
+            let read = network.read(0, id);
+            assert!(
+                matches!(read, Value::Integer(v) if v == value),
+                "{id}: {read:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn subscribers_receive_the_current_value_and_then_every_change() {
+    let mut network = Network::new(1);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let expr = Expr::Read(network.address(0, 0));
+    network.configure(&txid, 0, vec![(0, var(Value::Integer(1))), (1, def(expr))]);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+    network.write(0, 0, Value::Integer(2));
+
+    let target = network.nodes[0].clone();
+    let subscriber = network.client.clone();
+    network.send(
+        &target,
+        Message::Subscribe {
+            reactive: ReactiveId(1),
+            subscriber: subscriber.clone(),
+        },
+    );
+    network.system.run();
+    network.write(0, 0, Value::Integer(3));
+
+    let integers = |messages: Vec<Message>| {
+        messages
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::ValueChanged {
+                    value:
+                        StampedValue {
+                            value: Value::Integer(value),
+                            ..
+                        },
+                    ..
+                } => Some(value),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(integers(network.received()), [2, 3]);
+
+    network.send(
+        &target,
+        Message::Unsubscribe {
+            reactive: ReactiveId(1),
+            subscriber,
+        },
+    );
+    network.system.run();
+    network.write(0, 0, Value::Integer(4));
+    assert_eq!(integers(network.received()), []);
+}
+
+#[test]
+fn new_definitions_start_from_the_current_values_of_their_inputs() {
+    let mut network = Network::new(2);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    network.configure(&txid, 0, vec![(0, var(Value::Integer(5)))]);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    // Both definitions read the variable, which is not written again: one on the same node and
+    // one on the other node, through an import.
+    let x = network.address(0, 0);
+    let txid = network.lock(&[0, 1], LockKind::Exclusive);
+    network.configure(&txid, 0, vec![(1, def(Expr::Read(x.clone())))]);
+    let [exporter, importer] = [0, 1].map(|node| network.nodes[node].clone());
+    network.send(
+        &exporter,
+        Message::Configure {
+            txid: txid.clone(),
+            imports: HashMap::new(),
+            reactives: HashMap::new(),
+            exports: HashMap::from([(
+                ReactiveId(0),
+                HashMap::from([(importer.clone(), PropagationMode::All)]),
+            )]),
+        },
+    );
+    network.send(
+        &importer,
+        Message::Configure {
+            txid: txid.clone(),
+            imports: HashMap::from([(
+                x.clone(),
+                Some(ImportConfiguration {
+                    roots: HashSet::from([x.clone()]),
+                }),
+            )]),
+            reactives: HashMap::from([(ReactiveId(0), def(Expr::Read(x)))]),
+            exports: HashMap::new(),
+        },
+    );
+    network.prepare(&txid, &[0, 1]);
+    network.commit(&txid, &[0, 1]);
+
+    assert!(matches!(network.read(0, 1), Value::Integer(5)));
+    assert!(matches!(network.read(1, 0), Value::Integer(5)));
+}
+
+#[test]
+fn definitions_reading_other_nodes_are_wired_up_by_the_nodes() {
+    let mut network = Network::new(3);
+
+    // Node 1 reads the variable of node 0 and node 2 reads node 1, all configured in the same
+    // transaction without any imports or exports.
+    let txid = network.lock(&[0, 1, 2], LockKind::Exclusive);
+    network.configure(&txid, 0, vec![(0, var(Value::Integer(1)))]);
+    for node in 1..3 {
+        let expr = Expr::Read(network.address(node - 1, 0));
+        network.configure(&txid, node, vec![(0, def(expr))]);
+    }
+    network.prepare(&txid, &[0, 1, 2]);
+    network.commit(&txid, &[0, 1, 2]);
+    assert!(matches!(network.read(2, 0), Value::Integer(1)));
+
+    network.write(0, 0, Value::Integer(2));
+    assert!(matches!(network.read(1, 0), Value::Integer(2)));
+    assert!(matches!(network.read(2, 0), Value::Integer(2)));
+}
+
+/// The position of the first delivered message from `sender` to `target` matching `filter`.
+fn position(
+    trace: &[(Address, Address, Message)],
+    sender: &Address,
+    target: &Address,
+    filter: impl Fn(&Message) -> bool,
+) -> usize {
+    trace
+        .iter()
+        .position(|(s, t, message)| s == sender && t == target && filter(message))
+        .unwrap_or_else(|| panic!("no matching message from {sender:?} to {target:?}"))
+}
+
+/// Sets up a chain of nodes where node 1 reads variable 0 of node 0, node 2 reads node 1 and
+/// node 3 reads node 2. Node 0 also has variable 1, which is not read yet.
+fn chain() -> Network {
+    let mut network = Network::new(4);
+
+    let txid = network.lock(&[0, 1, 2, 3], LockKind::Exclusive);
+    network.configure(
+        &txid,
+        0,
+        vec![(0, var(Value::Integer(1))), (1, var(Value::Integer(10)))],
+    );
+    for node in 1..4 {
+        let expr = Expr::Read(network.address(node - 1, 0));
+        network.configure(&txid, node, vec![(0, def(expr))]);
+    }
+    network.prepare(&txid, &[0, 1, 2, 3]);
+    network.commit(&txid, &[0, 1, 2, 3]);
+
+    let subscriber = network.client.clone();
+    let target = network.nodes[3].clone();
+    network.send(
+        &target,
+        Message::Subscribe {
+            reactive: ReactiveId(0),
+            subscriber,
+        },
+    );
+    network.system.run();
+
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(1))
+    );
+
+    network
+}
+
+#[test]
+fn root_changes_are_acknowledged_downstream_first() {
+    let mut network = chain();
+    let [a, b, c, d] = [0, 1, 2, 3].map(|i| network.nodes[i].clone());
+
+    // Node 1 switches to variable 1, which changes the roots of everything downstream of it.
+    let txid = network.lock(&[0, 1], LockKind::Exclusive);
+    let expr = Expr::Read(network.address(0, 1));
+    network.configure(&txid, 1, vec![(0, def(expr))]);
+    let trace = network.prepare(&txid, &[0, 1]);
+
+    let update = |m: &Message| matches!(m, Message::UpdateRoots { .. });
+    let ack = |m: &Message| matches!(m, Message::RootsUpdated { .. });
+    let prepared = |m: &Message| matches!(m, Message::CommitPrepared { .. });
+
+    let announced_to_c = position(&trace, &b, &c, update);
+    let announced_to_d = position(&trace, &c, &d, update);
+    let acked_by_d = position(&trace, &d, &c, ack);
+    let acked_by_c = position(&trace, &c, &b, ack);
+    let b_prepared = position(&trace, &b, &network.client, prepared);
+    assert!(announced_to_c < announced_to_d);
+    assert!(announced_to_d < acked_by_d);
+    assert!(acked_by_d < acked_by_c);
+    assert!(acked_by_c < b_prepared);
+
+    // Node 0 is not affected by the change of roots, so it is not held back.
+    assert!(position(&trace, &a, &network.client, prepared) < b_prepared);
+
+    let trace = network.commit(&txid, &[0, 1]);
+    let commit_roots = |m: &Message| matches!(m, Message::CommitRoots { .. });
+    assert!(position(&trace, &b, &c, commit_roots) < position(&trace, &c, &d, commit_roots));
+
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(10))
+    );
+
+    // The new roots are in effect downstream, so updates of variable 1 reach node 3 while those
+    // of variable 0 no longer do.
+    network.write(0, 1, Value::Integer(20));
+    network.write(0, 0, Value::Integer(2));
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(20))
+    );
+}
+
+#[test]
+fn aborted_root_changes_are_discarded_downstream() {
+    let mut network = chain();
+    let [b, c, d] = [1, 2, 3].map(|i| network.nodes[i].clone());
+
+    let txid = network.lock(&[0, 1], LockKind::Exclusive);
+    let expr = Expr::Read(network.address(0, 1));
+    network.configure(&txid, 1, vec![(0, def(expr))]);
+    network.prepare(&txid, &[0, 1]);
+    network.received();
+
+    for node in [0, 1] {
+        let target = network.nodes[node].clone();
+        network.send(&target, Message::Abort { txid: txid.clone() });
+    }
+    let trace = network.system.run_traced();
+
+    let abort_roots = |m: &Message| matches!(m, Message::AbortRoots { .. });
+    assert!(position(&trace, &b, &c, abort_roots) < position(&trace, &c, &d, abort_roots));
+
+    // Node 3 keeps following variable 0.
+    network.write(0, 1, Value::Integer(20));
+    network.write(0, 0, Value::Integer(2));
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(2))
+    );
+}
+
+#[test]
+fn root_changes_wait_for_locks_held_by_other_transactions() {
+    let mut network = chain();
+
+    // A transaction reading node 2 holds its lock while the root change commits.
+    let reader = network.lock(&[2], LockKind::Shared);
+
+    let txid = network.lock(&[0, 1], LockKind::Exclusive);
+    let expr = Expr::Read(network.address(0, 1));
+    network.configure(&txid, 1, vec![(0, def(expr))]);
+    network.prepare(&txid, &[0, 1]);
+    network.commit(&txid, &[0, 1]);
+
+    network.write(0, 1, Value::Integer(20));
+
+    // Once the reader is done, the new roots apply and updates keep flowing downstream.
+    network.prepare(&reader, &[2]);
+    network.commit(&reader, &[2]);
+    network.write(0, 1, Value::Integer(30));
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(30))
+    );
+}
+
+#[test]
+fn failed_configurations_are_reported_when_preparing() {
+    let mut network = chain();
+    let divide = |address| {
+        Expr::Binary(
+            BinaryOp::Div,
+            Box::new(Expr::Read(address)),
+            Box::new(Expr::Value(Value::Integer(0))),
+        )
+    };
+
+    // A migration of variable 0 and a new definition on node 1 reading it both divide by zero.
+    let migration = ReactiveConfiguration::Migration {
+        migration: divide(network.address(0, 0)),
+    };
+    let definition = def(divide(network.address(0, 0)));
+    for (node, id, config) in [(0, 0, Some(migration)), (1, 5, definition)] {
+        let txid = network.lock(&[0, 1], LockKind::Exclusive);
+        network.configure(&txid, node, vec![(id, config)]);
+        network.prepare(&txid, &[0, 1]);
+
+        let received = network.received();
+        let failed = received.iter().find_map(|message| match message {
+            Message::PrepareFailed { address, error, .. } => Some((address, error)),
+            _ => None,
+        });
+        assert_eq!(
+            failed,
+            Some((&network.nodes[node], &EvalError::DivisionByZero))
+        );
+
+        for node in [0, 1] {
+            let target = network.nodes[node].clone();
+            network.send(&target, Message::Abort { txid: txid.clone() });
+        }
+        network.system.run();
+    }
+
+    // Migrating the variable to a value that can be computed works, and reaches node 3.
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let migration = ReactiveConfiguration::Migration {
+        migration: Expr::Binary(
+            BinaryOp::Mul,
+            Box::new(Expr::Read(network.address(0, 0))),
+            Box::new(Expr::Value(Value::Integer(3))),
+        ),
+    };
+    network.configure(&txid, 0, vec![(0, Some(migration))]);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+    let received = network.received();
+    assert_eq!(
+        Network::latest_value(&received, &network.address(3, 0)),
+        Some(Value::Integer(3))
+    );
+}
+
+#[test]
+fn values_propagated_together_reach_each_importer_in_one_batch() {
+    let mut network = Network::new(2);
+
+    // Node 1 reads the variable of node 0 and both definitions of it.
+    let txid = network.lock(&[0, 1], LockKind::Exclusive);
+    let x = network.address(0, 0);
+    let reactives = vec![
+        (0, var(Value::Integer(1))),
+        (1, def(Expr::Read(x.clone()))),
+        (2, def(Expr::Read(x.clone()))),
+    ];
+    network.configure(&txid, 0, reactives);
+    let reads = (0..3).map(|id| Expr::Read(network.address(0, id)));
+    network.configure(&txid, 1, vec![(0, def(Expr::Tuple(reads.collect())))]);
+    network.prepare(&txid, &[0, 1]);
+    network.commit(&txid, &[0, 1]);
+
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let [exporter, importer] = [0, 1].map(|node| network.nodes[node].clone());
+    network.send(
+        &exporter,
+        Message::Write {
+            txid: txid.clone(),
+            reactive: ReactiveId(0),
+            value: Value::Integer(2),
+        },
+    );
+    network.prepare(&txid, &[0]);
+    let trace = network.commit(&txid, &[0]);
+
+    let batches = trace
+        .iter()
+        .filter_map(|(sender, target, message)| match message {
+            Message::PropagateBatch { updates } if sender == &exporter && target == &importer => {
+                Some(updates.len())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(batches, [3]);
+
+    let Value::Tuple(items) = network.read(1, 0) else {
+        panic!("definition 0 of node 1 does not hold a tuple");
+    };
+    assert!(items.iter().all(|item| matches!(item, Value::Integer(2))));
+}
+
+#[test]
+fn unchanged_recomputations_are_not_reported_when_suppressed() {
+    let mut network = Network::new(1);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let x = network.address(0, 0);
+    let suppressing = ReactiveConfiguration::Definition {
+        expr: Expr::Read(x.clone()),
+        options: DefinitionOptions {
+            suppress_unchanged: true,
+            ..DefinitionOptions::default()
+        },
+    };
+    let reactives = vec![
+        (0, var(Value::Integer(1))),
+        (1, Some(suppressing)),
+        (2, def(Expr::Read(x))),
+    ];
+    network.configure(&txid, 0, reactives);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    let target = network.nodes[0].clone();
+    for id in [1, 2] {
+        let subscriber = network.client.clone();
+        network.send(
+            &target,
+            Message::Subscribe {
+                reactive: ReactiveId(id),
+                subscriber,
+            },
+        );
+    }
+    network.system.run();
+    network.received();
+
+    // Writing the same value again is only reported by the definition that does not suppress it.
+    let reported = |network: &mut Network, value| {
+        network.write(0, 0, Value::Integer(value));
+        let mut reported = network
+            .received()
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::ValueChanged { reactive, value } => Some((reactive.id.0, value.value)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        reported.sort_by_key(|(id, _)| *id);
+        reported
+    };
+    assert_eq!(reported(&mut network, 1), [(2, Value::Integer(1))]);
+    assert_eq!(
+        reported(&mut network, 2),
+        [(1, Value::Integer(2)), (2, Value::Integer(2))]
+    );
+}
+
+#[test]
+fn reads_at_a_past_basis_find_the_retained_value_of_that_time() {
+    let mut network = Network::new(1);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let x = network.address(0, 0);
+    let reactives = vec![(0, var(Value::Integer(0))), (1, def(Expr::Read(x.clone())))];
+    network.configure(&txid, 0, reactives);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    let target = network.nodes[0].clone();
+    network.send(
+        &target,
+        Message::RetainHistory {
+            reactive: ReactiveId(1),
+            limit: 2,
+        },
+    );
+    for value in 1..=3 {
+        network.write(0, 0, Value::Integer(value));
+    }
+
+    // Only the values of the last two writes are retained.
+    let mut iteration = Iteration::ZERO;
+    for expected in [None, Some(2), Some(3)] {
+        iteration = iteration.increment();
+        let mut basis = BasisStamp::empty();
+        basis.add(x.clone(), iteration);
+        let requester = network.client.clone();
+        network.send(
+            &target,
+            Message::ReadAt {
+                reactive: ReactiveId(1),
+                basis,
+                requester,
+            },
+        );
+        network.system.run();
+
+        let received = network.received();
+        let [Message::ValueAt { value, .. }] = received.as_slice() else {
+            panic!("expected a single value, got {received:?}");
+        };
+        let value = value.as_ref().map(|value| value.value.clone());
+        assert_eq!(value, expected.map(Value::Integer));
+    }
+}
+
+#[test]
+fn reads_of_own_writes_are_based_on_the_requested_basis() {
+    let mut network = Network::new(1);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    network.configure(&txid, 0, vec![(0, var(Value::Integer(1)))]);
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let target = network.nodes[0].clone();
+    network.send(
+        &target,
+        Message::Write {
+            txid: txid.clone(),
+            reactive: ReactiveId(0),
+            value: Value::Integer(2),
+        },
+    );
+
+    // The transaction has read another node's reactive at its third iteration.
+    let mut basis = BasisStamp::empty();
+    let elsewhere = ReactiveAddress {
+        address: network.client.clone(),
+        id: ReactiveId(7),
+    };
+    let third = Iteration::ZERO.increment().increment();
+    basis.add(elsewhere.clone(), third);
+    network.send(
+        &target,
+        Message::Read {
+            txid,
+            reactive: ReactiveId(0),
+            basis,
+        },
+    );
+    network.system.run();
+
+    let received = network.received();
+    let [Message::ReadResult { value, .. }] = received.as_slice() else {
+        panic!("expected a read result, found {received:?}");
+    };
+    assert_eq!(value.value, Value::Integer(2));
+    assert_eq!(value.basis.latest(&elsewhere), third);
+    let next = Iteration::ZERO.increment();
+    assert_eq!(value.basis.latest(&network.address(0, 0)), next);
+}
+
+#[test]
+fn pending_definitions_can_be_read_repeatedly_through_their_default() {
+    let mut network = Network::new(1);
+    let txid = network.lock(&[0], LockKind::Exclusive);
+    let quotient = Expr::Binary(
+        BinaryOp::Div,
+        Box::new(Expr::Value(Value::Integer(10))),
+        Box::new(Expr::Read(network.address(0, 0))),
+    );
+    let pending = ReactiveConfiguration::Definition {
+        expr: quotient,
+        options: DefinitionOptions {
+            default: Some(Value::Integer(7)),
+            ..DefinitionOptions::default()
+        },
+    };
+    network.configure(
+        &txid,
+        0,
+        vec![(0, var(Value::Integer(0))), (1, Some(pending))],
+    );
+    network.prepare(&txid, &[0]);
+    network.commit(&txid, &[0]);
+
+    // The default has an empty basis, yet the first read is complete once it is served.
+    let txid = network.lock(&[0], LockKind::Shared);
+    let target = network.nodes[0].clone();
+    for _ in 0..2 {
+        network.send(
+            &target,
+            Message::Read {
+                txid: txid.clone(),
+                reactive: ReactiveId(1),
+                basis: BasisStamp::empty(),
+            },
+        );
+        network.system.run();
+    }
+    network.prepare(&txid, &[0]);
+
+    let received = network.received();
+    let reads = received
+        .iter()
+        .filter_map(|message| match message {
+            Message::ReadResult { value, .. } => Some(value.value.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(reads, [Value::Integer(7), Value::Integer(7)]);
+    assert!(received
+        .iter()
+        .any(|message| matches!(message, Message::CommitPrepared { .. })));
+}
+
+#[test]
+fn locks_granted_to_unreachable_coordinators_are_released() {
+    let mut network = Network::new(1);
+    let coordinator = network.system.spawn(Retiring);
+    let txid = TxId {
+        priority: TxPriority::Low,
+        timestamp: network.timestamps.generate_timestamp(),
+        address: coordinator.clone(),
+    };
+    network.send(&coordinator, Message::Abort { txid: txid.clone() });
+    network.system.run();
+
+    let target = network.nodes[0].clone();
+    let kind = LockKind::Exclusive;
+    network.send(&target, Message::Lock { txid, kind });
+    network.system.run();
+
+    // The lock granted to the coordinator bounced back and was released, so another transaction
+    // is granted the node.
+    network.lock(&[0], LockKind::Exclusive);
+}